@@ -1,32 +1,48 @@
 //! Crude wall-clock benchmark for the list view hot path.
 //!
-//! Run with: cargo bench --bench scroll -- [installable]
+//! Run with: cargo bench --bench scroll -- [installable | --load-snapshot FILE]
 //! Defaults to /run/current-system (or ~/.nix-profile) so it exercises a
 //! realistically sized closure.
 
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::Instant;
 
 use crossterm::event::{KeyCode, KeyEvent};
 use nix_tree::nix::{self, QueryOptions};
 use nix_tree::path_stats;
+use nix_tree::snapshot;
+use nix_tree::store_path::PathId;
 use nix_tree::ui::{self, App};
 use ratatui::{Terminal, backend::TestBackend};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let target = std::env::args().skip(1).find(|a| !a.starts_with('-'));
-    let paths = match target {
-        Some(p) => vec![p],
-        None => nix::get_default_roots().await?,
-    };
-
-    eprintln!("loading {paths:?} ...");
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let snapshot = args
+        .iter()
+        .position(|a| a == "--load-snapshot")
+        .and_then(|i| args.get(i + 1));
     let t = Instant::now();
-    let graph = nix::query_path_info(&paths, true, &QueryOptions::default()).await?;
+    let graph = match snapshot {
+        Some(file) => {
+            eprintln!("loading {file} ...");
+            snapshot::load(file.as_ref())?
+        }
+        None => {
+            let paths = match args.iter().find(|a| !a.starts_with('-')) {
+                Some(p) => vec![p.clone()],
+                None => nix::get_default_roots().await?,
+            };
+            eprintln!("loading {paths:?} ...");
+            nix::query_path_info(&paths, true, &QueryOptions::default()).await?
+        }
+    };
     eprintln!(
-        "  nix path-info: {:?} ({} paths)",
+        "  load: {:?} ({} paths, {} KiB live heap)",
         t.elapsed(),
-        graph.paths.len()
+        graph.paths().len(),
+        LIVE.load(Ordering::Relaxed) / 1024
     );
 
     let t = Instant::now();
@@ -36,10 +52,14 @@ async fn main() -> anyhow::Result<()> {
     let t = Instant::now();
     let mut app = App::new(graph, stats);
     eprintln!("  App::new: {:?}", t.elapsed());
+    eprintln!(
+        "  live heap with stats and app: {} KiB",
+        LIVE.load(Ordering::Relaxed) / 1024
+    );
 
     // Put a large list into the current pane to mimic the laggy case
     // (e.g. referrers of glibc, or search results).
-    let mut all: Vec<String> = app.graph.paths.iter().map(|p| p.path.clone()).collect();
+    let mut all: Vec<PathId> = (0..app.graph.paths().len() as PathId).collect();
     path_stats::sort_paths(&mut all, &app.graph, &app.stats, app.sort_order, None);
     eprintln!("  current pane size: {}", all.len());
     app.current_items = all;
@@ -66,3 +86,23 @@ async fn main() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+/// Bytes currently allocated, so the graph's footprint can be reported.
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+
+struct Counting;
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        LIVE.fetch_add(layout.size(), Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
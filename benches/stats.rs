@@ -0,0 +1,86 @@
+//! Crude wall-clock benchmark for `calculate_stats`, serial vs. parallel.
+//!
+//! Run with: cargo bench --bench stats -- [installable]
+//! Without an installable it uses a synthetic 30k-path graph shaped like a
+//! NixOS system closure (a few wide, deep layers), so it runs without nix.
+
+use std::time::Instant;
+
+use nix_tree::nix::{self, QueryOptions};
+use nix_tree::path_stats;
+use nix_tree::store_path::{StorePath, StorePathGraph};
+
+/// Layered DAG: every path refers to a handful of paths in the layers below,
+/// so closures overlap heavily like real ones do.
+fn synthetic(n: usize) -> StorePathGraph {
+    const LAYERS: usize = 30;
+    let mut seed = 0x2545_f491_4f6c_dd1du64;
+    let mut next = |n: usize| {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        (seed % n as u64) as usize
+    };
+    let p = |i: usize| format!("/nix/store/{:0>32}-p{i}", i);
+    let per_layer = n / LAYERS;
+    let mut graph = StorePathGraph::new();
+    for i in 0..n {
+        let layer = i / per_layer;
+        let below = (layer + 1) * per_layer;
+        let references = if below < n {
+            (0..1 + next(8))
+                .map(|_| p(below + next(n - below)))
+                .collect()
+        } else {
+            Vec::new()
+        };
+        graph.add_path(StorePath {
+            path: p(i),
+            hash: format!("{i:0>32}"),
+            name: format!("p{i}"),
+            nar_size: 1 + next(1 << 20) as u64,
+            references,
+            ..Default::default()
+        });
+    }
+    graph.roots = (0..per_layer).map(p).collect();
+    graph
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let target = std::env::args().skip(1).find(|a| !a.starts_with('-'));
+    let graph = match target {
+        Some(p) => {
+            eprintln!("loading {p} ...");
+            nix::query_path_info(&[p], true, &QueryOptions::default()).await?
+        }
+        None => synthetic(30_000),
+    };
+    eprintln!("  {} paths", graph.paths().len());
+
+    let t = Instant::now();
+    let serial = path_stats::calculate_stats_with_jobs(&graph, 1);
+    let serial_time = t.elapsed();
+    eprintln!("  calculate_stats, 1 job: {serial_time:?}");
+
+    let mut jobs = 2;
+    while jobs <= path_stats::default_jobs().max(2) {
+        let t = Instant::now();
+        let parallel = path_stats::calculate_stats_with_jobs(&graph, jobs);
+        let elapsed = t.elapsed();
+        eprintln!(
+            "  calculate_stats, {jobs} jobs: {elapsed:?} ({:.1}x)",
+            serial_time.as_secs_f64() / elapsed.as_secs_f64()
+        );
+        assert!(
+            serial
+                .iter()
+                .all(|(p, s)| parallel[p].closure_size == s.closure_size),
+            "parallel closure sizes differ from serial"
+        );
+        jobs *= 2;
+    }
+
+    Ok(())
+}
@@ -34,7 +34,7 @@
           doCheck = false;
 
           buildInputs = with pkgs; [
-            # Add any system dependencies here if needed
+            sqlite
           ];
 
           meta = with pkgs.lib; {
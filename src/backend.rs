@@ -0,0 +1,265 @@
+use anyhow::{Result, bail};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use crate::binary_cache::BinaryCacheBackend;
+use crate::daemon::DaemonBackend;
+use crate::nix::{self, CliBackend, QueryOptions};
+use crate::store_path::StorePath;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Source of store path metadata. `nix::query_path_info` only talks to a
+/// backend, so the loading code runs the same against the nix CLI, the local
+/// store database or an in-memory fixture.
+pub trait StoreBackend: fmt::Debug + Send + Sync {
+    /// Map installables (flake refs, store paths, files inside the store) to
+    /// the store paths that become the graph roots.
+    fn resolve<'a>(
+        &'a self,
+        installables: &'a [String],
+        opts: &'a QueryOptions,
+    ) -> BoxFuture<'a, Result<Vec<String>>>;
+
+    /// Path info for exactly `paths`.
+    fn path_info<'a>(
+        &'a self,
+        paths: &'a [String],
+        opts: &'a QueryOptions,
+    ) -> BoxFuture<'a, Result<Vec<StorePath>>>;
+
+    /// Path info for the closure of `paths`. The default walks references one
+    /// level at a time through `path_info`, which is what backends without a
+    /// native recursive query want anyway.
+    fn closure<'a>(
+        &'a self,
+        paths: &'a [String],
+        opts: &'a QueryOptions,
+    ) -> BoxFuture<'a, Result<Vec<StorePath>>> {
+        Box::pin(walk_closure(self, paths, opts))
+    }
+}
+
+/// Breadth-first closure walk with one batched `path_info` call per level.
+pub async fn walk_closure<B: StoreBackend + ?Sized>(
+    backend: &B,
+    paths: &[String],
+    opts: &QueryOptions,
+) -> Result<Vec<StorePath>> {
+    let mut seen: HashSet<String> = paths.iter().cloned().collect();
+    let mut frontier: Vec<String> = seen.iter().cloned().collect();
+    let mut closure = Vec::new();
+    while !frontier.is_empty() {
+        let infos = backend.path_info(&frontier, opts).await?;
+        frontier.clear();
+        for info in &infos {
+            for r in &info.references {
+                if seen.insert(r.clone()) {
+                    frontier.push(r.clone());
+                }
+            }
+        }
+        closure.extend(infos);
+    }
+    Ok(closure)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BackendKind {
+    /// See `auto`.
+    #[default]
+    Auto,
+    Cli,
+    Db,
+    BinaryCache,
+    Daemon,
+}
+
+impl BackendKind {
+    pub fn parse(s: &str) -> Result<Self> {
+        Ok(match s {
+            "auto" => BackendKind::Auto,
+            "cli" => BackendKind::Cli,
+            "db" => BackendKind::Db,
+            "binary-cache" => BackendKind::BinaryCache,
+            "daemon" => BackendKind::Daemon,
+            _ => bail!("Unknown backend: {s} (expected auto, cli, db, binary-cache or daemon)"),
+        })
+    }
+
+    /// `None` for `Auto`, leaving the choice to `query_path_info`.
+    pub fn open(self, opts: &QueryOptions) -> Result<Option<Arc<dyn StoreBackend>>> {
+        Ok(match self {
+            BackendKind::Auto => None,
+            BackendKind::Cli => Some(Arc::new(CliBackend)),
+            BackendKind::Db => Some(Arc::new(crate::db::DbBackend::open_for_store(
+                opts.store.as_deref(),
+            )?)),
+            BackendKind::BinaryCache => match &opts.store {
+                Some(url) => Some(Arc::new(BinaryCacheBackend::new(url)?)),
+                None => bail!("--backend binary-cache requires --store <URL>"),
+            },
+            BackendKind::Daemon => match DaemonBackend::socket_for_store(opts.store.as_deref()) {
+                Some(socket) => Some(Arc::new(DaemonBackend::new(socket))),
+                None => bail!("--backend daemon requires --store daemon or unix://<socket>"),
+            },
+        })
+    }
+}
+
+/// Backend used when `QueryOptions::backend` is unset: the local (or chroot)
+/// store database if we can read it, narinfo files for `http(s)://`/`file://`
+/// stores, the daemon socket when there is one, falling back to shelling out
+/// to nix.
+pub fn auto(opts: &QueryOptions) -> Arc<dyn StoreBackend> {
+    if let Some(url) = &opts.store
+        && BinaryCacheBackend::is_cache_url(url)
+        && let Ok(cache) = BinaryCacheBackend::new(url)
+    {
+        return Arc::new(cache);
+    }
+    if let Ok(db) = crate::db::DbBackend::open_for_store(opts.store.as_deref()) {
+        return Arc::new(db);
+    }
+    if let Some(socket) = DaemonBackend::socket_for_store(opts.store.as_deref())
+        && socket.exists()
+    {
+        return Arc::new(DaemonBackend::new(socket));
+    }
+    Arc::new(CliBackend)
+}
+
+/// Fixed set of store paths held in memory, for tests and for library users
+/// that already have the metadata at hand.
+#[derive(Debug, Default, Clone)]
+pub struct MemoryBackend {
+    paths: HashMap<String, StorePath>,
+}
+
+impl MemoryBackend {
+    pub fn new(paths: impl IntoIterator<Item = StorePath>) -> Self {
+        Self {
+            paths: paths.into_iter().map(|p| (p.path.clone(), p)).collect(),
+        }
+    }
+
+    pub fn insert(&mut self, path: StorePath) {
+        self.paths.insert(path.path.clone(), path);
+    }
+}
+
+impl StoreBackend for MemoryBackend {
+    fn resolve<'a>(
+        &'a self,
+        installables: &'a [String],
+        opts: &'a QueryOptions,
+    ) -> BoxFuture<'a, Result<Vec<String>>> {
+        Box::pin(async move {
+            installables
+                .iter()
+                .map(|i| match nix::store_path_of(i, opts.store_dir()) {
+                    Some(p) if self.paths.contains_key(&p) => Ok(p),
+                    _ => bail!("'{i}' is not in the store"),
+                })
+                .collect()
+        })
+    }
+
+    fn path_info<'a>(
+        &'a self,
+        paths: &'a [String],
+        _opts: &'a QueryOptions,
+    ) -> BoxFuture<'a, Result<Vec<StorePath>>> {
+        Box::pin(async move {
+            paths
+                .iter()
+                .map(|p| match self.paths.get(p) {
+                    Some(info) => Ok(info.clone()),
+                    None => bail!("'{p}' is not in the store"),
+                })
+                .collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MemoryBackend;
+    use crate::nix::{QueryOptions, query_path_info};
+    use crate::store_path::StorePath;
+    use crate::store_path::testing::{p, store_path};
+    use std::sync::Arc;
+
+    fn sp(name: &str, refs: &[&str]) -> StorePath {
+        let refs: Vec<String> = refs.iter().map(|r| p(r)).collect();
+        store_path(&p(name), 100, &refs)
+    }
+
+    fn opts() -> QueryOptions {
+        QueryOptions {
+            backend: Some(Arc::new(MemoryBackend::new([
+                sp("app", &["lib"]),
+                sp("lib", &["libc", "lib"]),
+                sp("libc", &[]),
+                sp("unrelated", &[]),
+            ]))),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn loads_closure_through_backend() {
+        let graph = query_path_info(&[format!("{}/bin/app", p("app"))], true, &opts())
+            .await
+            .unwrap();
+        assert_eq!(graph.roots, [p("app")]);
+        assert_eq!(graph.paths().len(), 3);
+        assert!(graph.get_path(&p("unrelated")).is_none());
+
+        let graph = query_path_info(&[p("lib")], false, &opts()).await.unwrap();
+        assert_eq!(graph.paths().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn non_default_store_dir() {
+        let p = |name: &str| format!("/opt/nix/store/{:a<32}-{name}", "");
+        let sp = |name: &str, refs: &[&str]| StorePath {
+            path: p(name),
+            references: refs.iter().map(|r| p(r)).collect(),
+            ..sp(name, &[])
+        };
+        let opts = QueryOptions {
+            store_dir: Some("/opt/nix/store".to_string()),
+            backend: Some(Arc::new(MemoryBackend::new([
+                sp("app", &["lib"]),
+                sp("lib", &[]),
+            ]))),
+            ..Default::default()
+        };
+        let graph = query_path_info(&[format!("{}/bin/app", p("app"))], true, &opts)
+            .await
+            .unwrap();
+        assert_eq!(graph.store_dir, "/opt/nix/store");
+        assert_eq!(graph.roots, [p("app")]);
+        assert_eq!(graph.paths().len(), 2);
+        assert_eq!(graph.base_name(&p("lib")), format!("{:a<32}-lib", ""));
+
+        // The default store dir does not match these paths.
+        let opts = QueryOptions {
+            store_dir: None,
+            ..opts
+        };
+        assert!(query_path_info(&[p("app")], true, &opts).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn unknown_installable_is_an_error() {
+        let err = query_path_info(&[p("missing")], true, &opts())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("not in the store"), "{err}");
+    }
+}
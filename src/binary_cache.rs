@@ -0,0 +1,325 @@
+use anyhow::{Context, Result, bail};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::backend::{BoxFuture, StoreBackend};
+use crate::nix::{self, CliBackend, QueryOptions};
+use crate::store_path::StorePath;
+
+/// Parsed `<hash>.narinfo`. Only `StorePath`, `NarSize` and `References` are
+/// required; caches in the wild omit everything else at times.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NarInfo {
+    pub store_path: String,
+    pub url: Option<String>,
+    pub compression: Option<String>,
+    pub file_size: Option<u64>,
+    pub nar_hash: Option<String>,
+    pub nar_size: u64,
+    /// Base names (`<hash>-<name>`), as written in the file.
+    pub references: Vec<String>,
+    pub deriver: Option<String>,
+    pub sigs: Vec<String>,
+    pub ca: Option<String>,
+}
+
+impl NarInfo {
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut info = NarInfo::default();
+        let mut nar_size = None;
+        for line in text.lines() {
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            match key {
+                "StorePath" => info.store_path = value.to_string(),
+                "URL" => info.url = Some(value.to_string()),
+                "Compression" => info.compression = Some(value.to_string()),
+                "FileSize" => info.file_size = Some(value.parse().context("Invalid FileSize")?),
+                "NarHash" => info.nar_hash = Some(value.to_string()),
+                "NarSize" => nar_size = Some(value.parse().context("Invalid NarSize")?),
+                "References" => {
+                    info.references = value.split_whitespace().map(str::to_string).collect();
+                }
+                "Deriver" if value != "unknown-deriver" => info.deriver = Some(value.to_string()),
+                "Sig" => info.sigs.push(value.to_string()),
+                "CA" => info.ca = Some(value.to_string()),
+                _ => {}
+            }
+        }
+        if info.store_path.is_empty() {
+            bail!("narinfo has no StorePath");
+        }
+        info.nar_size = nar_size.context("narinfo has no NarSize")?;
+        Ok(info)
+    }
+
+    pub fn into_store_path(self) -> Result<StorePath> {
+        let (hash, name) = StorePath::parse(&self.store_path)?;
+        let mut path = StorePath {
+            path: self.store_path,
+            hash,
+            name,
+            nar_size: self.nar_size,
+            signatures: self.sigs,
+            nar_hash: self.nar_hash,
+            ca: self.ca,
+            download_size: self.file_size,
+            ..Default::default()
+        };
+        let store_dir = path.store_dir().to_string();
+        path.references = self
+            .references
+            .iter()
+            .map(|r| format!("{store_dir}/{r}"))
+            .collect();
+        path.deriver = self.deriver.map(|d| format!("{store_dir}/{d}"));
+        Ok(path)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Location {
+    Http(String),
+    Dir(PathBuf),
+}
+
+/// Reads `.narinfo` files straight from an `http(s)://` or `file://` binary
+/// cache, so closures that were never realised locally can be explored
+/// without nix evaluating or substituting anything.
+#[derive(Debug, Clone)]
+pub struct BinaryCacheBackend {
+    location: Location,
+    agent: ureq::Agent,
+}
+
+/// narinfo requests in flight at once; cache.nixos.org is fine with this and
+/// it keeps a 10k-path walk to a few seconds.
+const PARALLEL_FETCHES: usize = 32;
+
+impl BinaryCacheBackend {
+    pub fn is_cache_url(url: &str) -> bool {
+        ["http://", "https://", "file://"]
+            .iter()
+            .any(|scheme| url.starts_with(scheme))
+    }
+
+    pub fn new(url: &str) -> Result<Self> {
+        // Store settings such as `?trusted=1` mean nothing to us.
+        let url = url.split_once('?').map_or(url, |(base, _)| base);
+        let url = url.trim_end_matches('/');
+        let location = if let Some(dir) = url.strip_prefix("file://") {
+            Location::Dir(PathBuf::from(dir))
+        } else if url.starts_with("http://") || url.starts_with("https://") {
+            Location::Http(url.to_string())
+        } else {
+            bail!("Unsupported binary cache URL: {url}");
+        };
+        let agent = ureq::Agent::config_builder()
+            .timeout_global(Some(Duration::from_secs(30)))
+            .build()
+            .into();
+        Ok(Self { location, agent })
+    }
+
+    /// Fetch and parse the narinfo for `path`.
+    pub async fn narinfo(&self, path: &str) -> Result<NarInfo> {
+        let (hash, _) = StorePath::parse(path)?;
+        let file = format!("{hash}.narinfo");
+        let text = match &self.location {
+            Location::Dir(dir) => {
+                let p = dir.join(&file);
+                tokio::fs::read_to_string(&p)
+                    .await
+                    .with_context(|| format!("'{path}' is not in the cache ({})", p.display()))?
+            }
+            Location::Http(base) => {
+                let url = format!("{base}/{file}");
+                let agent = self.agent.clone();
+                tokio::task::spawn_blocking(move || -> Result<String> {
+                    let mut resp = agent.get(&url).call()?;
+                    Ok(resp.body_mut().read_to_string()?)
+                })
+                .await?
+                .with_context(|| format!("'{path}' is not in the cache ({base}/{file})"))?
+            }
+        };
+        NarInfo::parse(&text).with_context(|| format!("Invalid narinfo for {path}"))
+    }
+}
+
+impl StoreBackend for BinaryCacheBackend {
+    fn resolve<'a>(
+        &'a self,
+        installables: &'a [String],
+        opts: &'a QueryOptions,
+    ) -> BoxFuture<'a, Result<Vec<String>>> {
+        Box::pin(async move {
+            // Store paths need no evaluation; anything else is handed to nix
+            // (which then only needs the cache for path info, not for eval).
+            match installables
+                .iter()
+                .map(|i| nix::store_path_of(i, opts.store_dir()))
+                .collect::<Option<Vec<_>>>()
+            {
+                Some(paths) => Ok(paths),
+                None => CliBackend.resolve(installables, opts).await,
+            }
+        })
+    }
+
+    fn path_info<'a>(
+        &'a self,
+        paths: &'a [String],
+        _opts: &'a QueryOptions,
+    ) -> BoxFuture<'a, Result<Vec<StorePath>>> {
+        Box::pin(async move {
+            let mut infos = Vec::with_capacity(paths.len());
+            for chunk in paths.chunks(PARALLEL_FETCHES) {
+                let fetches = chunk.iter().map(|p| {
+                    let this = self.clone();
+                    let p = p.clone();
+                    tokio::spawn(async move { this.narinfo(&p).await })
+                });
+                for fetch in fetches.collect::<Vec<_>>() {
+                    infos.push(fetch.await??.into_store_path()?);
+                }
+            }
+            Ok(infos)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BinaryCacheBackend, NarInfo};
+    use crate::nix::{QueryOptions, query_path_info};
+    use crate::store_path::testing::ph as p;
+    use std::io::{BufRead, BufReader, Write};
+    use std::sync::Arc;
+
+    const SAMPLE: &str = "\
+StorePath: /nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-app
+URL: nar/1x8cq4y3ip7spxyz.nar.xz
+Compression: xz
+FileHash: sha256:1111111111111111111111111111111111111111111111111111
+FileSize: 500
+NarHash: sha256:0000000000000000000000000000000000000000000000000000
+NarSize: 1000
+References: bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb-lib aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-app
+Deriver: dddddddddddddddddddddddddddddddd-app.drv
+Sig: cache.example.org-1:c2lnMQ==
+Sig: other-1:c2lnMg==
+";
+
+    /// app → lib → libc as `(file name, narinfo)` pairs.
+    fn cache_files() -> Vec<(String, String)> {
+        let info = |c: char, name: &str, refs: &[(char, &str)]| {
+            let refs: Vec<String> = refs
+                .iter()
+                .map(|(c, n)| p(*c, n).trim_start_matches("/nix/store/").to_string())
+                .collect();
+            (
+                format!("{}.narinfo", c.to_string().repeat(32)),
+                format!(
+                    "StorePath: {}\nNarSize: 100\nReferences: {}\n",
+                    p(c, name),
+                    refs.join(" ")
+                ),
+            )
+        };
+        vec![
+            (
+                "nix-cache-info".to_string(),
+                "StoreDir: /nix/store\n".to_string(),
+            ),
+            info('a', "app", &[('b', "lib"), ('a', "app")]),
+            info('b', "lib", &[('c', "libc")]),
+            info('c', "libc", &[]),
+        ]
+    }
+
+    async fn check_closure(url: &str) {
+        let opts = QueryOptions {
+            backend: Some(Arc::new(BinaryCacheBackend::new(url).unwrap())),
+            ..Default::default()
+        };
+        let graph = query_path_info(&[p('a', "app")], true, &opts)
+            .await
+            .unwrap();
+        assert_eq!(graph.roots, [p('a', "app")]);
+        assert_eq!(graph.paths().len(), 3);
+        assert_eq!(graph.get_references(&p('b', "lib"))[0].name, "libc");
+
+        let err = query_path_info(&[p('d', "missing")], true, &opts)
+            .await
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("not in the cache"), "{err:#}");
+    }
+
+    #[test]
+    fn parse_narinfo() {
+        let info = NarInfo::parse(SAMPLE).unwrap();
+        assert_eq!(info.nar_size, 1000);
+        assert_eq!(info.file_size, Some(500));
+        assert_eq!(info.compression.as_deref(), Some("xz"));
+        assert_eq!(info.sigs.len(), 2);
+        assert_eq!(info.references.len(), 2);
+
+        let sp = info.into_store_path().unwrap();
+        assert_eq!(sp.name, "app");
+        assert_eq!(sp.references[0], p('b', "lib"));
+        assert_eq!(sp.deriver, Some(p('d', "app.drv")));
+        assert_eq!(sp.download_size, Some(500));
+        assert!(sp.nar_hash.unwrap().starts_with("sha256:"));
+
+        assert!(NarInfo::parse("StorePath: /nix/store/x\n").is_err());
+    }
+
+    #[tokio::test]
+    async fn walks_file_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, body) in cache_files() {
+            std::fs::write(dir.path().join(name), body).unwrap();
+        }
+        check_closure(&format!("file://{}", dir.path().display())).await;
+    }
+
+    /// Minimal HTTP/1.1 stand-in: one request per connection, 404 for
+    /// anything not in `files`.
+    fn serve(files: Vec<(String, String)>) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut header = String::new();
+                while reader.read_line(&mut header).unwrap() > 2 {
+                    header.clear();
+                }
+                let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+                let resp = match files.iter().find(|(name, _)| path[1..] == **name) {
+                    Some((_, body)) => format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                        body.len()
+                    ),
+                    None => {
+                        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                            .to_string()
+                    }
+                };
+                stream.write_all(resp.as_bytes()).unwrap();
+            }
+        });
+        format!("http://{addr}")
+    }
+
+    #[tokio::test]
+    async fn walks_http_cache() {
+        check_closure(&serve(cache_files())).await;
+    }
+}
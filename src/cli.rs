@@ -1,5 +1,15 @@
 use anyhow::{Result, bail};
 
+use crate::backend::BackendKind;
+use crate::pattern::Pattern;
+
+/// A graph read from a file instead of queried from a store.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GraphFile {
+    Snapshot(String),
+    ClosureInfo(String),
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct Config {
     pub paths: Vec<String>,
@@ -7,11 +17,29 @@ pub struct Config {
     pub impure: bool,
     pub dot: bool,
     pub diff: bool,
+    pub duplicates: bool,
+    /// Print the output-splitting advice for each root and exit.
+    pub split: bool,
+    /// `nix-tree why <from> <to>`: print the chains between two paths.
+    pub why: bool,
+    /// `--limit`: chains `why` prints; `None` uses `why::LIMIT`.
+    pub limit: Option<usize>,
+    /// `--all`: print every chain.
+    pub all: bool,
     pub store: Option<String>,
+    pub store_dir: Option<String>,
     pub help: bool,
     pub version: bool,
     pub nix_options: Vec<(String, String)>,
     pub file: Option<String>,
+    pub backend: BackendKind,
+    /// Threads for the closure-size pass; `None` uses every core.
+    pub jobs: Option<usize>,
+    pub save_snapshot: Option<String>,
+    /// `--what-if` patterns; non-empty means print the report and exit.
+    pub what_if: Vec<Pattern>,
+    /// In command-line order, so `--diff` knows which side is which.
+    pub graph_files: Vec<GraphFile>,
 }
 
 pub fn parse_args() -> Result<Config> {
@@ -41,6 +69,25 @@ pub fn parse_args() -> Result<Config> {
             "--diff" => {
                 config.diff = true;
             }
+            "--duplicates" => {
+                config.duplicates = true;
+            }
+            "--split" => {
+                config.split = true;
+            }
+            "--all" => {
+                config.all = true;
+            }
+            "--limit" => {
+                i += 1;
+                if i >= args.len() {
+                    bail!("--limit requires an argument");
+                }
+                config.limit = Some(parse_limit(&args[i])?);
+            }
+            arg if arg.starts_with("--limit=") => {
+                config.limit = Some(parse_limit(arg.strip_prefix("--limit=").unwrap())?);
+            }
             "--store" => {
                 i += 1;
                 if i >= args.len() {
@@ -51,6 +98,87 @@ pub fn parse_args() -> Result<Config> {
             arg if arg.starts_with("--store=") => {
                 config.store = Some(arg.strip_prefix("--store=").unwrap().to_string());
             }
+            "--store-dir" => {
+                i += 1;
+                if i >= args.len() {
+                    bail!("--store-dir requires an argument");
+                }
+                config.store_dir = Some(args[i].clone());
+            }
+            arg if arg.starts_with("--store-dir=") => {
+                config.store_dir = Some(arg.strip_prefix("--store-dir=").unwrap().to_string());
+            }
+            "--backend" => {
+                i += 1;
+                if i >= args.len() {
+                    bail!("--backend requires an argument");
+                }
+                config.backend = BackendKind::parse(&args[i])?;
+            }
+            arg if arg.starts_with("--backend=") => {
+                config.backend = BackendKind::parse(arg.strip_prefix("--backend=").unwrap())?;
+            }
+            "-j" | "--jobs" => {
+                i += 1;
+                if i >= args.len() {
+                    bail!("--jobs requires an argument");
+                }
+                config.jobs = Some(parse_jobs(&args[i])?);
+            }
+            arg if arg.starts_with("--jobs=") => {
+                config.jobs = Some(parse_jobs(arg.strip_prefix("--jobs=").unwrap())?);
+            }
+            "--save-snapshot" => {
+                i += 1;
+                if i >= args.len() {
+                    bail!("--save-snapshot requires an argument");
+                }
+                config.save_snapshot = Some(args[i].clone());
+            }
+            arg if arg.starts_with("--save-snapshot=") => {
+                config.save_snapshot =
+                    Some(arg.strip_prefix("--save-snapshot=").unwrap().to_string());
+            }
+            "--what-if" => {
+                i += 1;
+                if i >= args.len() {
+                    bail!("--what-if requires an argument");
+                }
+                config.what_if.extend(Pattern::parse_list(&args[i]));
+            }
+            arg if arg.starts_with("--what-if=") => {
+                config
+                    .what_if
+                    .extend(Pattern::parse_list(arg.strip_prefix("--what-if=").unwrap()));
+            }
+            "--load-snapshot" => {
+                i += 1;
+                if i >= args.len() {
+                    bail!("--load-snapshot requires an argument");
+                }
+                config
+                    .graph_files
+                    .push(GraphFile::Snapshot(args[i].clone()));
+            }
+            arg if arg.starts_with("--load-snapshot=") => {
+                config.graph_files.push(GraphFile::Snapshot(
+                    arg.strip_prefix("--load-snapshot=").unwrap().to_string(),
+                ));
+            }
+            "--closure-info" => {
+                i += 1;
+                if i >= args.len() {
+                    bail!("--closure-info requires an argument");
+                }
+                config
+                    .graph_files
+                    .push(GraphFile::ClosureInfo(args[i].clone()));
+            }
+            arg if arg.starts_with("--closure-info=") => {
+                config.graph_files.push(GraphFile::ClosureInfo(
+                    arg.strip_prefix("--closure-info=").unwrap().to_string(),
+                ));
+            }
             "--option" => {
                 i += 1;
                 if i + 1 >= args.len() {
@@ -74,6 +202,9 @@ pub fn parse_args() -> Result<Config> {
             arg if arg.starts_with('-') => {
                 bail!("Unknown option: {arg}");
             }
+            "why" if !config.why && config.paths.is_empty() => {
+                config.why = true;
+            }
             _ => {
                 config.paths.push(args[i].clone());
             }
@@ -84,12 +215,27 @@ pub fn parse_args() -> Result<Config> {
     Ok(config)
 }
 
+fn parse_jobs(s: &str) -> Result<usize> {
+    match s.parse() {
+        Ok(0) | Err(_) => bail!("--jobs expects a positive number, got '{s}'"),
+        Ok(n) => Ok(n),
+    }
+}
+
+fn parse_limit(s: &str) -> Result<usize> {
+    match s.parse() {
+        Ok(n) => Ok(n),
+        Err(_) => bail!("--limit expects a number, got '{s}'"),
+    }
+}
+
 pub fn print_help() {
     println!(
         r#"nix-tree - Interactively browse dependency graphs of Nix derivations
 
 USAGE:
     nix-tree [OPTIONS] [PATHS]...
+    nix-tree why [OPTIONS] <FROM> <TO>
 
 OPTIONS:
     -h, --help              Display help message
@@ -97,14 +243,40 @@ OPTIONS:
     -d, --derivation        Operate on derivation store paths
     --impure                Allow access to mutable paths and repositories
     --dot                   Print the dependency graph in dot format and exit
-    --diff                  Compare two closures (requires exactly two installables)
+    --diff                  Compare two closures (requires exactly two installables,
+                            snapshots or closure infos; files are the older side)
+    --duplicates            List packages present in more than one version or build,
+                            with what pulls each copy in, and exit
+    --split                 Find references of each root that only documentation
+                            or development files hold, with what moving them to
+                            doc/man/dev outputs would save, and exit (reads the
+                            files, so the paths must be on disk)
+    --what-if <PATTERNS>    Report how much the closure shrinks without the paths
+                            matching PATTERNS (comma-separated globs over names like
+                            "*-doc,*-man", or package names like "perl") and exit
+    --save-snapshot <FILE>  Save the loaded graph to FILE and exit
+    --load-snapshot <FILE>  Browse a saved graph instead of querying nix
+    --closure-info <PATH>   Browse a closureInfo output, registration file or
+                            exportReferencesGraph file instead of querying nix
     --store <STORE>         The URL of the Nix store, e.g. "daemon" or "https://cache.nixos.org"
                             See "nix help-stores" for supported store types and settings
+    --store-dir <DIR>       Logical store directory installables live in (default:
+                            $NIX_STORE_DIR or /nix/store)
+    --backend <BACKEND>     Where to load path info from: "auto" (default), "cli"
+                            (nix path-info), "db" (read the local store database),
+                            "binary-cache" (fetch .narinfo files from the --store URL)
+                            or "daemon" (talk to nix-daemon over its socket)
+    --limit <N>             Chains `why` prints (default: 200)
+    --all                   Print every chain `why` finds
+    -j, --jobs <N>          Threads used to compute closure sizes (default: all cores)
     --option <NAME> <VALUE> Pass option to nix commands
     -f, --file <FILE>       Interpret installables as attribute paths relative to the Nix expression in file
 
 ARGUMENTS:
     [PATHS]...          Paths to explore (defaults to current system profile)
+    <FROM> <TO>         Print every reference chain from FROM to TO, shortest
+                        first. FROM is an installable (or a path in the loaded
+                        graph file), TO a store path or package name in its closure
 
 Press ? inside the TUI for keybindings.
 "#
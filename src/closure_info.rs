@@ -0,0 +1,274 @@
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::Path;
+
+use crate::store_path::{StorePath, StorePathGraph};
+
+/// Load a closure captured inside a build: a `closureInfo` output directory
+/// (its `registration` file), a registration file on its own, a plain-text
+/// `exportReferencesGraph` file, or the JSON that `exportReferencesGraph`
+/// produces under `__structuredAttrs`.
+pub fn load(path: &Path) -> Result<StorePathGraph> {
+    let file = if path.is_dir() {
+        path.join("registration")
+    } else {
+        path.to_path_buf()
+    };
+    let text = std::fs::read_to_string(&file)
+        .with_context(|| format!("Failed to read {}", file.display()))?;
+    let paths = if text.trim_start().starts_with(['[', '{']) {
+        parse_json(&text)
+    } else {
+        parse_registration(&text)
+    }
+    .with_context(|| format!("Failed to parse {}", file.display()))?;
+    Ok(build_graph(paths))
+}
+
+/// The `nix-store --register-validity` format, one record per path:
+///
+/// ```text
+/// <path>
+/// [<narHash>
+///  <narSize>]      only with --hash-given, as closureInfo writes it
+/// <deriver>        empty when unknown
+/// <n>
+/// <reference 1>
+/// …
+/// <reference n>
+/// ```
+///
+/// Whether the hash lines are present is decided from the first record: in
+/// the short form the line after the path is a deriver (a store path or
+/// empty), never a hash.
+pub fn parse_registration(text: &str) -> Result<Vec<StorePath>> {
+    let mut lines = text.lines();
+    let mut next = |what: &str| -> Result<&str> {
+        match lines.next() {
+            Some(line) => Ok(line),
+            None => bail!("Unexpected end of input, expected {what}"),
+        }
+    };
+
+    let mut paths = Vec::new();
+    let mut hash_given = None;
+    loop {
+        let path = match next("a store path") {
+            Ok(line) if !line.is_empty() => line,
+            // Trailing newline(s) after the last record.
+            _ => break,
+        };
+        let (hash, name) = StorePath::parse(path)?;
+
+        let mut line = next("a deriver or hash")?;
+        let hash_given = *hash_given.get_or_insert(!line.is_empty() && !line.starts_with('/'));
+        let mut nar_size = 0;
+        let mut nar_hash = None;
+        if hash_given {
+            nar_hash = Some(line.to_string());
+            let size = next("a NAR size")?;
+            nar_size = size
+                .parse()
+                .with_context(|| format!("Invalid NAR size '{size}' for {path}"))?;
+            line = next("a deriver")?;
+        }
+        let deriver = (!line.is_empty()).then(|| line.to_string());
+
+        let count = next("a reference count")?;
+        let count: usize = count
+            .parse()
+            .with_context(|| format!("Invalid reference count '{count}' for {path}"))?;
+        let mut references = Vec::with_capacity(count);
+        for _ in 0..count {
+            references.push(next("a reference")?.to_string());
+        }
+
+        paths.push(StorePath {
+            path: path.to_string(),
+            hash,
+            name,
+            nar_size,
+            references,
+            deriver,
+            nar_hash,
+            ..Default::default()
+        });
+    }
+    Ok(paths)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonPathInfo {
+    path: String,
+    nar_size: u64,
+    #[serde(default)]
+    references: Vec<String>,
+    #[serde(default)]
+    signatures: Vec<String>,
+    deriver: Option<String>,
+    registration_time: Option<u64>,
+    nar_hash: Option<String>,
+    ca: Option<String>,
+    ultimate: Option<bool>,
+}
+
+/// `exportReferencesGraph` under `__structuredAttrs`: an array of path infos,
+/// either bare or as the value of its attribute in `.attrs.json`.
+pub fn parse_json(text: &str) -> Result<Vec<StorePath>> {
+    let value: serde_json::Value = serde_json::from_str(text)?;
+    let list = match value {
+        serde_json::Value::Array(list) => list,
+        serde_json::Value::Object(attrs) => attrs
+            .into_values()
+            .filter_map(|v| match v {
+                serde_json::Value::Array(list)
+                    if list.first().is_some_and(|e| e.get("path").is_some()) =>
+                {
+                    Some(list)
+                }
+                _ => None,
+            })
+            .next()
+            .context("No exportReferencesGraph attribute found")?,
+        _ => bail!("Expected a JSON array or object"),
+    };
+
+    list.into_iter()
+        .map(|entry| {
+            let info: JsonPathInfo = serde_json::from_value(entry)?;
+            let (hash, name) = StorePath::parse(&info.path)?;
+            Ok(StorePath {
+                path: info.path,
+                hash,
+                name,
+                nar_size: info.nar_size,
+                references: info.references,
+                signatures: info.signatures,
+                deriver: info.deriver,
+                registration_time: info.registration_time,
+                nar_hash: info.nar_hash,
+                ca: info.ca,
+                ultimate: info.ultimate,
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+/// Neither format records which paths the closure was taken of, so the roots
+/// are the paths nothing else in the set refers to.
+fn build_graph(paths: Vec<StorePath>) -> StorePathGraph {
+    let referenced: HashSet<&str> = paths
+        .iter()
+        .flat_map(|p| {
+            p.references
+                .iter()
+                .filter(move |r| **r != p.path)
+                .map(String::as_str)
+        })
+        .collect();
+    let mut roots: Vec<String> = paths
+        .iter()
+        .filter(|p| !referenced.contains(p.path.as_str()))
+        .map(|p| p.path.clone())
+        .collect();
+    // A closure that is one big cycle has no unreferenced path.
+    if roots.is_empty() {
+        roots = paths.iter().map(|p| p.path.clone()).collect();
+    }
+
+    let mut graph = StorePathGraph::new();
+    for path in paths {
+        graph.add_path(path);
+    }
+    graph.roots = roots;
+    graph.detect_store_dir();
+    graph.disambiguate_names();
+    graph
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store_path::testing::ph as p;
+
+    #[test]
+    fn registration_with_hashes() {
+        let text = format!(
+            "{app}\nsha256:0000\n1000\n\n2\n{app}\n{lib}\n{lib}\nsha256:1111\n500\n{drv}\n0\n",
+            app = p('a', "app"),
+            lib = p('b', "lib"),
+            drv = p('c', "lib.drv"),
+        );
+        let paths = parse_registration(&text).unwrap();
+        assert_eq!(paths.len(), 2);
+        assert_eq!(paths[0].nar_size, 1000);
+        assert_eq!(paths[0].references, [p('a', "app"), p('b', "lib")]);
+        assert_eq!(paths[1].nar_size, 500);
+        assert!(paths[1].references.is_empty());
+        assert_eq!(paths[0].deriver, None);
+        assert_eq!(paths[1].deriver, Some(p('c', "lib.drv")));
+        assert_eq!(paths[1].nar_hash.as_deref(), Some("sha256:1111"));
+
+        let graph = build_graph(paths);
+        assert_eq!(graph.roots, [p('a', "app")]);
+    }
+
+    #[test]
+    fn registration_without_hashes() {
+        let text = format!(
+            "{app}\n\n1\n{lib}\n{lib}\n{drv}\n0\n",
+            app = p('a', "app"),
+            lib = p('b', "lib"),
+            drv = p('c', "lib.drv"),
+        );
+        let paths = parse_registration(&text).unwrap();
+        assert_eq!(paths.len(), 2);
+        assert_eq!(paths[0].references, [p('b', "lib")]);
+        assert_eq!(paths[0].nar_size, 0);
+        assert_eq!(paths[1].deriver, Some(p('c', "lib.drv")));
+    }
+
+    #[test]
+    fn truncated_registration() {
+        let text = format!(
+            "{}\nsha256:0000\n1000\n\n2\n{}\n",
+            p('a', "app"),
+            p('a', "app")
+        );
+        let err = parse_registration(&text).unwrap_err();
+        assert!(err.to_string().contains("expected a reference"), "{err}");
+    }
+
+    #[test]
+    fn structured_attrs_json() {
+        let text = format!(
+            r#"{{"name": "x", "closure": [
+                {{"path": "{app}", "narSize": 1000, "references": ["{lib}"]}},
+                {{"path": "{lib}", "narSize": 500, "references": []}}
+            ]}}"#,
+            app = p('a', "app"),
+            lib = p('b', "lib"),
+        );
+        let paths = parse_json(&text).unwrap();
+        assert_eq!(paths.len(), 2);
+        assert_eq!(paths[1].nar_size, 500);
+
+        let text = format!(r#"[{{"path": "{}", "references": []}}]"#, p('a', "app"));
+        let err = parse_json(&text).unwrap_err();
+        assert!(err.to_string().contains("narSize"), "{err}");
+    }
+
+    #[test]
+    fn closure_info_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let text = format!("{}\nsha256:0000\n1000\n\n0\n", p('a', "app"));
+        std::fs::write(dir.path().join("registration"), text).unwrap();
+        std::fs::write(dir.path().join("store-paths"), p('a', "app")).unwrap();
+        let graph = load(dir.path()).unwrap();
+        assert_eq!(graph.roots, [p('a', "app")]);
+        assert_eq!(graph.paths()[0].nar_size, 1000);
+    }
+}
@@ -0,0 +1,569 @@
+use anyhow::{Context, Result, bail};
+use std::path::{Path, PathBuf};
+use tokio::io::{
+    AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, ReadHalf, WriteHalf,
+};
+use tokio::net::UnixStream;
+
+use crate::backend::{BoxFuture, StoreBackend};
+use crate::nix::{self, CliBackend, QueryOptions};
+use crate::store_path::StorePath;
+
+pub const DEFAULT_SOCKET_PATH: &str = "/nix/var/nix/daemon-socket/socket";
+
+const WORKER_MAGIC_1: u64 = 0x6e697863;
+const WORKER_MAGIC_2: u64 = 0x6478696f;
+/// 1.35: the newest version before feature negotiation, spoken by every
+/// Nix and Lix daemon still in use.
+const PROTOCOL_VERSION: u64 = 1 << 8 | 35;
+/// `QueryPathInfo` reports invalid paths in-band from 1.17 on; anything
+/// older predates Nix 2.0.
+const MIN_MINOR: u64 = 17;
+
+const OP_QUERY_PATH_INFO: u64 = 26;
+const OP_QUERY_VALID_PATHS: u64 = 31;
+
+const STDERR_NEXT: u64 = 0x6f6c6d67;
+const STDERR_READ: u64 = 0x64617461;
+const STDERR_WRITE: u64 = 0x64617416;
+const STDERR_LAST: u64 = 0x616c7473;
+const STDERR_ERROR: u64 = 0x63787470;
+const STDERR_START_ACTIVITY: u64 = 0x53545254;
+const STDERR_STOP_ACTIVITY: u64 = 0x53544f50;
+const STDERR_RESULT: u64 = 0x52534c54;
+
+/// `ValidPathInfo` as sent in reply to `QueryPathInfo`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DaemonPathInfo {
+    pub path: String,
+    pub deriver: Option<String>,
+    /// Base16 sha256, without the `sha256:` prefix.
+    pub nar_hash: String,
+    pub references: Vec<String>,
+    pub registration_time: u64,
+    pub nar_size: u64,
+    pub ultimate: bool,
+    pub sigs: Vec<String>,
+    pub ca: Option<String>,
+}
+
+impl DaemonPathInfo {
+    pub fn into_store_path(self) -> Result<StorePath> {
+        let (hash, name) = StorePath::parse(&self.path)?;
+        Ok(StorePath {
+            path: self.path,
+            hash,
+            name,
+            nar_size: self.nar_size,
+            references: self.references,
+            signatures: self.sigs,
+            deriver: self.deriver,
+            registration_time: Some(self.registration_time).filter(|&t| t != 0),
+            nar_hash: Some(format!("sha256:{}", self.nar_hash)),
+            ca: self.ca,
+            ultimate: Some(self.ultimate),
+            ..Default::default()
+        })
+    }
+}
+
+/// Client side of one worker-protocol connection. Generic over the stream so
+/// tests can run it against an in-process fake daemon.
+pub struct DaemonConnection<S> {
+    reader: BufReader<ReadHalf<S>>,
+    writer: WriteHalf<S>,
+    minor: u64,
+}
+
+impl DaemonConnection<UnixStream> {
+    pub async fn connect(socket: &Path) -> Result<Self> {
+        let stream = UnixStream::connect(socket)
+            .await
+            .with_context(|| format!("Failed to connect to {}", socket.display()))?;
+        Self::handshake(stream).await
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> DaemonConnection<S> {
+    pub async fn handshake(stream: S) -> Result<Self> {
+        let (reader, writer) = tokio::io::split(stream);
+        let mut conn = Self {
+            reader: BufReader::new(reader),
+            writer,
+            minor: 0,
+        };
+
+        conn.writer.write_u64_le(WORKER_MAGIC_1).await?;
+        conn.writer.flush().await?;
+        if read_u64(&mut conn.reader).await? != WORKER_MAGIC_2 {
+            bail!("Not a nix daemon (bad magic)");
+        }
+        let daemon_version = read_u64(&mut conn.reader).await?;
+        if daemon_version >> 8 != 1 || daemon_version & 0xff < MIN_MINOR {
+            bail!(
+                "Unsupported daemon protocol version {}.{}",
+                daemon_version >> 8,
+                daemon_version & 0xff
+            );
+        }
+        conn.minor = (daemon_version & 0xff).min(PROTOCOL_VERSION & 0xff);
+
+        let mut buf = Vec::new();
+        put_u64(&mut buf, PROTOCOL_VERSION);
+        put_u64(&mut buf, 0); // no CPU affinity
+        put_u64(&mut buf, 0); // reserveSpace
+        conn.writer.write_all(&buf).await?;
+        conn.writer.flush().await?;
+
+        if conn.minor >= 33 {
+            read_string(&mut conn.reader).await?; // daemon nix version
+        }
+        if conn.minor >= 35 {
+            read_u64(&mut conn.reader).await?; // trusted flag
+        }
+        process_stderr(&mut conn.reader, conn.minor).await?;
+        Ok(conn)
+    }
+
+    pub async fn query_valid_paths(&mut self, paths: &[String]) -> Result<Vec<String>> {
+        let mut buf = Vec::new();
+        put_u64(&mut buf, OP_QUERY_VALID_PATHS);
+        put_strings(&mut buf, paths);
+        if self.minor >= 27 {
+            put_u64(&mut buf, 0); // don't substitute
+        }
+        self.writer.write_all(&buf).await?;
+        self.writer.flush().await?;
+        process_stderr(&mut self.reader, self.minor).await?;
+        read_strings(&mut self.reader).await
+    }
+
+    /// One `QueryPathInfo` per path, all written before the first reply is
+    /// read so the whole batch costs a single round trip. `None` marks paths
+    /// the daemon does not consider valid.
+    pub async fn query_path_infos(
+        &mut self,
+        paths: &[String],
+    ) -> Result<Vec<Option<DaemonPathInfo>>> {
+        let mut buf = Vec::new();
+        for p in paths {
+            put_u64(&mut buf, OP_QUERY_PATH_INFO);
+            put_string(&mut buf, p);
+        }
+
+        // Write and read concurrently: the daemon answers while we are still
+        // sending, and neither side may block on a full socket buffer.
+        let minor = self.minor;
+        let writer = &mut self.writer;
+        let reader = &mut self.reader;
+        let write = async {
+            writer.write_all(&buf).await?;
+            writer.flush().await?;
+            Ok::<_, anyhow::Error>(())
+        };
+        let read = async {
+            let mut infos = Vec::with_capacity(paths.len());
+            for p in paths {
+                process_stderr(reader, minor).await?;
+                if read_u64(reader).await? == 0 {
+                    infos.push(None);
+                    continue;
+                }
+                infos.push(Some(read_path_info(reader, p.clone()).await?));
+            }
+            Ok(infos)
+        };
+        let ((), infos) = tokio::try_join!(write, read)?;
+        Ok(infos)
+    }
+}
+
+/// `ultimate`, `sigs` and `ca` exist from 1.16 on, below `MIN_MINOR`.
+async fn read_path_info<R: AsyncRead + Unpin>(r: &mut R, path: String) -> Result<DaemonPathInfo> {
+    let deriver = read_string(r).await?;
+    let nar_hash = read_string(r).await?;
+    let references = read_strings(r).await?;
+    let registration_time = read_u64(r).await?;
+    let nar_size = read_u64(r).await?;
+    let ultimate = read_u64(r).await? != 0;
+    let sigs = read_strings(r).await?;
+    let ca = read_string(r).await?;
+    Ok(DaemonPathInfo {
+        path,
+        deriver: (!deriver.is_empty()).then_some(deriver),
+        nar_hash,
+        references,
+        registration_time,
+        nar_size,
+        ultimate,
+        sigs,
+        ca: (!ca.is_empty()).then_some(ca),
+    })
+}
+
+/// Drain log/activity messages until `STDERR_LAST`, turning `STDERR_ERROR`
+/// into an error.
+async fn process_stderr<R: AsyncRead + Unpin>(r: &mut R, minor: u64) -> Result<()> {
+    loop {
+        match read_u64(r).await? {
+            STDERR_LAST => return Ok(()),
+            STDERR_NEXT | STDERR_WRITE => {
+                read_string(r).await?;
+            }
+            STDERR_START_ACTIVITY => {
+                read_u64(r).await?; // id
+                read_u64(r).await?; // level
+                read_u64(r).await?; // type
+                read_string(r).await?;
+                read_fields(r).await?;
+                read_u64(r).await?; // parent
+            }
+            STDERR_STOP_ACTIVITY => {
+                read_u64(r).await?;
+            }
+            STDERR_RESULT => {
+                read_u64(r).await?; // id
+                read_u64(r).await?; // type
+                read_fields(r).await?;
+            }
+            STDERR_ERROR if minor >= 26 => {
+                read_string(r).await?; // type, always "Error"
+                read_u64(r).await?; // level
+                read_string(r).await?; // name, always "Error"
+                let msg = read_string(r).await?;
+                read_u64(r).await?; // position, always absent
+                for _ in 0..read_u64(r).await? {
+                    read_u64(r).await?;
+                    read_string(r).await?;
+                }
+                bail!("nix-daemon: {msg}");
+            }
+            STDERR_ERROR => {
+                let msg = read_string(r).await?;
+                read_u64(r).await?; // exit status
+                bail!("nix-daemon: {msg}");
+            }
+            STDERR_READ => bail!("nix-daemon asked for input we never offered"),
+            other => bail!("Unexpected message from nix-daemon: {other:#x}"),
+        }
+    }
+}
+
+async fn read_fields<R: AsyncRead + Unpin>(r: &mut R) -> Result<()> {
+    for _ in 0..read_u64(r).await? {
+        match read_u64(r).await? {
+            0 => {
+                read_u64(r).await?;
+            }
+            1 => {
+                read_string(r).await?;
+            }
+            t => bail!("Unknown logger field type {t}"),
+        }
+    }
+    Ok(())
+}
+
+async fn read_u64<R: AsyncRead + Unpin>(r: &mut R) -> Result<u64> {
+    r.read_u64_le()
+        .await
+        .context("nix-daemon closed the connection")
+}
+
+async fn read_string<R: AsyncRead + Unpin>(r: &mut R) -> Result<String> {
+    let len = read_u64(r).await? as usize;
+    let mut buf = vec![0u8; len.next_multiple_of(8)];
+    r.read_exact(&mut buf).await?;
+    buf.truncate(len);
+    String::from_utf8(buf).context("nix-daemon sent invalid UTF-8")
+}
+
+async fn read_strings<R: AsyncRead + Unpin>(r: &mut R) -> Result<Vec<String>> {
+    let n = read_u64(r).await?;
+    let mut out = Vec::with_capacity(n.min(1 << 16) as usize);
+    for _ in 0..n {
+        out.push(read_string(r).await?);
+    }
+    Ok(out)
+}
+
+fn put_u64(buf: &mut Vec<u8>, v: u64) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_string(buf: &mut Vec<u8>, s: &str) {
+    put_u64(buf, s.len() as u64);
+    buf.extend_from_slice(s.as_bytes());
+    buf.resize(buf.len().next_multiple_of(8), 0);
+}
+
+fn put_strings(buf: &mut Vec<u8>, strings: &[String]) {
+    put_u64(buf, strings.len() as u64);
+    for s in strings {
+        put_string(buf, s);
+    }
+}
+
+/// Talks to nix-daemon directly instead of going through `nix path-info`,
+/// so the CLI and daemon versions don't have to agree on a JSON format.
+#[derive(Debug, Clone)]
+pub struct DaemonBackend {
+    socket: PathBuf,
+}
+
+impl DaemonBackend {
+    pub fn new(socket: impl Into<PathBuf>) -> Self {
+        Self {
+            socket: socket.into(),
+        }
+    }
+
+    /// Socket for `--store daemon` / `--store unix://<path>`, honouring
+    /// `NIX_DAEMON_SOCKET_PATH` like nix does. `None` for other store URLs.
+    pub fn socket_for_store(store: Option<&str>) -> Option<PathBuf> {
+        match store {
+            None | Some("daemon") => Some(
+                std::env::var_os("NIX_DAEMON_SOCKET_PATH")
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|| PathBuf::from(DEFAULT_SOCKET_PATH)),
+            ),
+            Some(url) => url
+                .strip_prefix("unix://")
+                .map(|p| PathBuf::from(p.split_once('?').map_or(p, |(p, _)| p))),
+        }
+    }
+}
+
+impl StoreBackend for DaemonBackend {
+    fn resolve<'a>(
+        &'a self,
+        installables: &'a [String],
+        opts: &'a QueryOptions,
+    ) -> BoxFuture<'a, Result<Vec<String>>> {
+        Box::pin(async move {
+            let direct = if opts.file.is_none() && !opts.derivation {
+                installables
+                    .iter()
+                    .map(|i| nix::store_path_of(i, opts.store_dir()))
+                    .collect::<Option<Vec<_>>>()
+            } else {
+                None
+            };
+            let Some(direct) = direct else {
+                return CliBackend.resolve(installables, opts).await;
+            };
+            let mut conn = DaemonConnection::connect(&self.socket).await?;
+            let valid = conn.query_valid_paths(&direct).await?;
+            if let Some(missing) = direct.iter().find(|p| !valid.contains(p)) {
+                bail!(
+                    "'{missing}' is not in the store (garbage-collected, or pass --derivation for unbuilt outputs)"
+                );
+            }
+            Ok(direct)
+        })
+    }
+
+    fn path_info<'a>(
+        &'a self,
+        paths: &'a [String],
+        _opts: &'a QueryOptions,
+    ) -> BoxFuture<'a, Result<Vec<StorePath>>> {
+        Box::pin(async move {
+            if paths.is_empty() {
+                return Ok(Vec::new());
+            }
+            let mut conn = DaemonConnection::connect(&self.socket).await?;
+            let infos = conn.query_path_infos(paths).await?;
+            paths
+                .iter()
+                .zip(infos)
+                .map(|(p, info)| match info {
+                    Some(info) => info.into_store_path(),
+                    None => bail!("'{p}' is not valid according to nix-daemon"),
+                })
+                .collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nix::query_path_info;
+    use crate::store_path::testing::ph as p;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use tokio::net::UnixListener;
+
+    async fn write(w: &mut (impl AsyncWrite + Unpin), buf: Vec<u8>) {
+        w.write_all(&buf).await.unwrap();
+        w.flush().await.unwrap();
+    }
+
+    /// Server half of the protocol at `minor`, answering from `paths`
+    /// (path → references). `…-broken` paths reply with `STDERR_ERROR`.
+    async fn fake_daemon(
+        stream: impl AsyncRead + AsyncWrite + Unpin,
+        minor: u64,
+        paths: HashMap<String, Vec<String>>,
+    ) {
+        let (r, mut w) = tokio::io::split(stream);
+        let mut r = BufReader::new(r);
+        assert_eq!(read_u64(&mut r).await.unwrap(), WORKER_MAGIC_1);
+        let mut buf = Vec::new();
+        put_u64(&mut buf, WORKER_MAGIC_2);
+        put_u64(&mut buf, 1 << 8 | minor);
+        write(&mut w, buf).await;
+        read_u64(&mut r).await.unwrap(); // client version
+        read_u64(&mut r).await.unwrap(); // affinity
+        read_u64(&mut r).await.unwrap(); // reserveSpace
+        let mut buf = Vec::new();
+        if minor >= 33 {
+            put_string(&mut buf, "2.24.0");
+        }
+        if minor >= 35 {
+            put_u64(&mut buf, 1);
+        }
+        put_u64(&mut buf, STDERR_LAST);
+        write(&mut w, buf).await;
+
+        while let Ok(op) = read_u64(&mut r).await {
+            let mut buf = Vec::new();
+            // Exercise log handling on every reply.
+            put_u64(&mut buf, STDERR_START_ACTIVITY);
+            for v in [7, 3, 0] {
+                put_u64(&mut buf, v);
+            }
+            put_string(&mut buf, "querying");
+            put_u64(&mut buf, 1);
+            put_u64(&mut buf, 1);
+            put_string(&mut buf, "field");
+            put_u64(&mut buf, 0);
+            put_u64(&mut buf, STDERR_NEXT);
+            put_string(&mut buf, "log line");
+            put_u64(&mut buf, STDERR_STOP_ACTIVITY);
+            put_u64(&mut buf, 7);
+            match op {
+                OP_QUERY_VALID_PATHS => {
+                    let query = read_strings(&mut r).await.unwrap();
+                    if minor >= 27 {
+                        read_u64(&mut r).await.unwrap();
+                    }
+                    let valid: Vec<String> = query
+                        .into_iter()
+                        .filter(|q| paths.contains_key(q))
+                        .collect();
+                    put_u64(&mut buf, STDERR_LAST);
+                    put_strings(&mut buf, &valid);
+                }
+                OP_QUERY_PATH_INFO => {
+                    let path = read_string(&mut r).await.unwrap();
+                    if path.ends_with("-broken") {
+                        put_u64(&mut buf, STDERR_ERROR);
+                        put_string(&mut buf, "Error");
+                        put_u64(&mut buf, 0);
+                        put_string(&mut buf, "Error");
+                        put_string(&mut buf, "path is broken");
+                        put_u64(&mut buf, 0);
+                        put_u64(&mut buf, 0);
+                        write(&mut w, buf).await;
+                        return;
+                    }
+                    put_u64(&mut buf, STDERR_LAST);
+                    match paths.get(&path) {
+                        None => put_u64(&mut buf, 0),
+                        Some(refs) => {
+                            put_u64(&mut buf, 1);
+                            put_string(&mut buf, "");
+                            put_string(&mut buf, &"0".repeat(64));
+                            put_strings(&mut buf, refs);
+                            put_u64(&mut buf, 1700000000);
+                            put_u64(&mut buf, 100 * refs.len() as u64 + 1);
+                            put_u64(&mut buf, 1);
+                            put_strings(&mut buf, &["cache.example.org-1:c2ln".to_string()]);
+                            put_string(&mut buf, "");
+                        }
+                    }
+                }
+                _ => panic!("unexpected op {op}"),
+            }
+            write(&mut w, buf).await;
+        }
+    }
+
+    fn closure() -> HashMap<String, Vec<String>> {
+        HashMap::from([
+            (p('a', "app"), vec![p('a', "app"), p('b', "lib")]),
+            (p('b', "lib"), vec![p('c', "libc")]),
+            (p('c', "libc"), vec![]),
+        ])
+    }
+
+    #[tokio::test]
+    async fn pipelined_path_info() {
+        for minor in [17, 26, 35] {
+            let (client, server) = tokio::io::duplex(64);
+            tokio::spawn(fake_daemon(server, minor, closure()));
+            let mut conn = DaemonConnection::handshake(client).await.unwrap();
+
+            // Many more requests than fit in the 64-byte duplex buffer.
+            let mut query = vec![p('a', "app"), p('d', "missing")];
+            query.extend(std::iter::repeat_n(p('c', "libc"), 100));
+            let infos = conn.query_path_infos(&query).await.unwrap();
+            assert_eq!(infos.len(), query.len());
+            let app = infos[0].as_ref().unwrap();
+            assert_eq!(app.references.len(), 2);
+            assert_eq!(app.registration_time, 1700000000);
+            assert_eq!(app.nar_size, 201);
+            assert_eq!(app.sigs.len(), 1);
+            assert!(infos[1].is_none());
+            assert!(infos[2..].iter().all(|i| i.as_ref().unwrap().nar_size == 1));
+
+            let valid = conn
+                .query_valid_paths(&[p('b', "lib"), p('d', "missing")])
+                .await
+                .unwrap();
+            assert_eq!(valid, [p('b', "lib")]);
+        }
+    }
+
+    #[tokio::test]
+    async fn daemon_errors_surface() {
+        let (client, server) = tokio::io::duplex(4096);
+        tokio::spawn(fake_daemon(server, 35, closure()));
+        let mut conn = DaemonConnection::handshake(client).await.unwrap();
+        let err = conn
+            .query_path_infos(&[p('b', "broken")])
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("path is broken"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn backend_over_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("socket");
+        let listener = UnixListener::bind(&socket).unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(fake_daemon(stream, 35, closure()));
+            }
+        });
+
+        let opts = QueryOptions {
+            backend: Some(Arc::new(DaemonBackend::new(&socket))),
+            ..Default::default()
+        };
+        let graph = query_path_info(&[p('a', "app")], true, &opts)
+            .await
+            .unwrap();
+        assert_eq!(graph.roots, [p('a', "app")]);
+        assert_eq!(graph.paths().len(), 3);
+
+        let err = query_path_info(&[p('d', "missing")], true, &opts)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("not in the store"), "{err}");
+    }
+}
@@ -0,0 +1,413 @@
+use anyhow::{Context, Result, bail};
+use rusqlite::{Connection, OpenFlags, params_from_iter};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::backend::{BoxFuture, StoreBackend};
+use crate::nix::{self, CliBackend, QueryOptions};
+use crate::store_path::StorePath;
+
+pub const DEFAULT_DB_PATH: &str = "/nix/var/nix/db/db.sqlite";
+
+/// Paths bound per query, below the 999 variables SQLite before 3.32 allows.
+const MAX_VARIABLES: usize = 900;
+
+/// Read-only handle on the local store's `db.sqlite`. Reading `ValidPaths`
+/// and `Refs` directly skips spawning `nix path-info` and parsing its JSON,
+/// which dominates startup on large closures.
+pub struct StoreDb {
+    conn: Connection,
+}
+
+impl std::fmt::Debug for StoreDb {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StoreDb")
+            .field("path", &self.conn.path())
+            .finish()
+    }
+}
+
+impl StoreDb {
+    pub fn open(path: &Path) -> Result<Self> {
+        let conn = Connection::open_with_flags(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+        // Opening is lazy; touch the schema now so permission or WAL
+        // problems surface here and the caller can fall back early.
+        conn.query_row("SELECT count(*) FROM ValidPaths WHERE id = 0", [], |_| {
+            Ok(())
+        })
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+        Ok(Self { conn })
+    }
+
+    /// The subset of `paths` that is registered as valid, in input order.
+    pub fn valid_paths(&self, paths: &[String]) -> Result<Vec<String>> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT 1 FROM ValidPaths WHERE path = ?1")?;
+        let mut valid = Vec::with_capacity(paths.len());
+        for path in paths {
+            if stmt.exists([path])? {
+                valid.push(path.clone());
+            }
+        }
+        Ok(valid)
+    }
+
+    /// Path info for `paths` only, like `nix path-info` without `--recursive`.
+    pub fn path_info(&self, paths: &[String]) -> Result<Vec<StorePath>> {
+        self.query(paths, false)
+    }
+
+    /// Path info for the full closure of `paths`.
+    pub fn closure(&self, paths: &[String]) -> Result<Vec<StorePath>> {
+        self.query(paths, true)
+    }
+
+    fn query(&self, paths: &[String], recursive: bool) -> Result<Vec<StorePath>> {
+        // Keyed by row id, so closures shared between chunks are read once.
+        let mut infos: HashMap<i64, StorePath> = HashMap::new();
+        for chunk in paths.chunks(MAX_VARIABLES) {
+            self.query_chunk(chunk, recursive, &mut infos)?;
+        }
+        let mut infos: Vec<StorePath> = infos.into_values().collect();
+        for info in &mut infos {
+            info.references.sort();
+        }
+        Ok(infos)
+    }
+
+    /// `query` for at most `MAX_VARIABLES` paths, adding what `infos` does
+    /// not have yet.
+    fn query_chunk(
+        &self,
+        paths: &[String],
+        recursive: bool,
+        infos: &mut HashMap<i64, StorePath>,
+    ) -> Result<()> {
+        let placeholders = vec!["?"; paths.len()].join(", ");
+        let recurse = if recursive {
+            "UNION SELECT Refs.reference FROM Refs JOIN selected ON Refs.referrer = selected.id"
+        } else {
+            ""
+        };
+        let selected = format!(
+            "WITH RECURSIVE selected(id) AS (
+                SELECT id FROM ValidPaths WHERE path IN ({placeholders}) {recurse}
+            )"
+        );
+
+        let mut found: HashMap<i64, StorePath> = HashMap::new();
+        let mut stmt = self.conn.prepare_cached(&format!(
+            "{selected} SELECT id, path, narSize, sigs, hash, registrationTime, deriver,
+                    ultimate, ca
+             FROM ValidPaths
+             WHERE id IN (SELECT id FROM selected)"
+        ))?;
+        let mut rows = stmt.query(params_from_iter(paths))?;
+        while let Some(row) = rows.next()? {
+            let id: i64 = row.get(0)?;
+            if infos.contains_key(&id) {
+                continue;
+            }
+            let path: String = row.get(1)?;
+            let nar_size: Option<i64> = row.get(2)?;
+            let sigs: Option<String> = row.get(3)?;
+            let registration_time: Option<i64> = row.get(5)?;
+            let ultimate: Option<i64> = row.get(7)?;
+            let (hash, name) = StorePath::parse(&path)?;
+            found.insert(
+                id,
+                StorePath {
+                    path,
+                    hash,
+                    name,
+                    nar_size: nar_size.unwrap_or(0) as u64,
+                    references: Vec::new(),
+                    signatures: sigs
+                        .unwrap_or_default()
+                        .split_whitespace()
+                        .map(str::to_string)
+                        .collect(),
+                    nar_hash: row.get(4)?,
+                    registration_time: registration_time.map(|t| t as u64),
+                    deriver: row.get(6)?,
+                    ultimate: ultimate.map(|u| u != 0),
+                    ca: row.get(8)?,
+                    ..Default::default()
+                },
+            );
+        }
+
+        let mut stmt = self.conn.prepare_cached(&format!(
+            "{selected} SELECT Refs.referrer, ValidPaths.path FROM Refs
+             JOIN ValidPaths ON ValidPaths.id = Refs.reference
+             WHERE Refs.referrer IN (SELECT id FROM selected)"
+        ))?;
+        let mut rows = stmt.query(params_from_iter(paths))?;
+        while let Some(row) = rows.next()? {
+            let referrer: i64 = row.get(0)?;
+            if let Some(info) = found.get_mut(&referrer) {
+                info.references.push(row.get(1)?);
+            }
+        }
+        infos.extend(found);
+        Ok(())
+    }
+}
+
+/// Database path of a local store: the system one without `--store` (or for
+/// `local`), `<root>/nix/var/nix/db/db.sqlite` for a chroot store given as a
+/// bare path or `local?root=<path>`. `None` for every other store type.
+pub fn db_path_for_store(store: Option<&str>) -> Option<PathBuf> {
+    let Some(store) = store else {
+        return Some(PathBuf::from(DEFAULT_DB_PATH));
+    };
+    let (base, params) = store.split_once('?').unwrap_or((store, ""));
+    let root = params
+        .split('&')
+        .find_map(|param| param.strip_prefix("root="));
+    let root = match (base, root) {
+        (_, Some(root)) if base == "local" || base.starts_with('/') => root,
+        ("local", None) => return Some(PathBuf::from(DEFAULT_DB_PATH)),
+        (path, None) if path.starts_with('/') => path,
+        _ => return None,
+    };
+    Some(Path::new(root).join(DEFAULT_DB_PATH.trim_start_matches('/')))
+}
+
+/// `StoreBackend` over `StoreDb`. rusqlite connections are not `Sync`, and
+/// every query is short, so a plain mutex is enough.
+#[derive(Debug)]
+pub struct DbBackend {
+    db: Mutex<StoreDb>,
+}
+
+impl DbBackend {
+    pub fn open(path: &Path) -> Result<Self> {
+        Ok(Self {
+            db: Mutex::new(StoreDb::open(path)?),
+        })
+    }
+
+    pub fn open_default() -> Result<Self> {
+        Self::open(Path::new(DEFAULT_DB_PATH))
+    }
+
+    /// Open the database of the local store `--store` names, if it names one
+    /// (see `db_path_for_store`).
+    pub fn open_for_store(store: Option<&str>) -> Result<Self> {
+        match db_path_for_store(store) {
+            Some(path) => Self::open(&path),
+            None => bail!("--store {} is not a local store", store.unwrap_or_default()),
+        }
+    }
+
+    fn with_db<T>(&self, f: impl FnOnce(&StoreDb) -> Result<T>) -> Result<T> {
+        let db = self.db.lock().unwrap_or_else(|e| e.into_inner());
+        f(&db)
+    }
+
+    /// Plain store paths resolve to themselves, so nix only has to be asked
+    /// when something actually needs evaluating (flake refs, --file, or
+    /// --derivation mapping outputs to their deriver). `None` means "ask nix".
+    fn resolve_direct(&self, installables: &[String], opts: &QueryOptions) -> Option<Vec<String>> {
+        if opts.file.is_some() || opts.derivation {
+            return None;
+        }
+        let direct: Vec<String> = installables
+            .iter()
+            .map(|i| nix::store_path_of(i, opts.store_dir()))
+            .collect::<Option<_>>()?;
+        let valid = self.with_db(|db| db.valid_paths(&direct)).ok()?;
+        (valid.len() == direct.len()).then_some(direct)
+    }
+
+    fn query(&self, paths: &[String], recursive: bool) -> Result<Vec<StorePath>> {
+        let infos = self.with_db(|db| db.query(paths, recursive))?;
+        let found: HashSet<&str> = infos.iter().map(|i| i.path.as_str()).collect();
+        for p in paths {
+            if !found.contains(p.as_str()) {
+                bail!("'{p}' is not registered in the store database");
+            }
+        }
+        Ok(infos)
+    }
+}
+
+impl StoreBackend for DbBackend {
+    fn resolve<'a>(
+        &'a self,
+        installables: &'a [String],
+        opts: &'a QueryOptions,
+    ) -> BoxFuture<'a, Result<Vec<String>>> {
+        Box::pin(async move {
+            match self.resolve_direct(installables, opts) {
+                Some(direct) => Ok(direct),
+                None => CliBackend.resolve(installables, opts).await,
+            }
+        })
+    }
+
+    fn path_info<'a>(
+        &'a self,
+        paths: &'a [String],
+        _opts: &'a QueryOptions,
+    ) -> BoxFuture<'a, Result<Vec<StorePath>>> {
+        Box::pin(async move { self.query(paths, false) })
+    }
+
+    fn closure<'a>(
+        &'a self,
+        paths: &'a [String],
+        _opts: &'a QueryOptions,
+    ) -> BoxFuture<'a, Result<Vec<StorePath>>> {
+        Box::pin(async move { self.query(paths, true) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DbBackend, StoreDb, db_path_for_store};
+    use crate::nix::{QueryOptions, query_path_info};
+    use crate::store_path::testing::p;
+    use rusqlite::Connection;
+    use std::sync::Arc;
+
+    /// Subset of the schema nix creates in `schema.sql`, enough for the
+    /// columns and tables we read.
+    fn fixture() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let conn = Connection::open(dir.path().join("db.sqlite")).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE ValidPaths (
+                id integer primary key autoincrement not null,
+                path text unique not null,
+                hash text not null,
+                registrationTime integer not null,
+                deriver text,
+                narSize integer,
+                ultimate integer,
+                sigs text,
+                ca text
+            );
+            CREATE TABLE Refs (
+                referrer integer not null,
+                reference integer not null,
+                primary key (referrer, reference)
+            );",
+        )
+        .unwrap();
+        let paths = [
+            ("app", 1000, Some("cache.example.org-1:abc= other-1:def=")),
+            ("lib", 500, None),
+            ("libc", 200, None),
+            ("unrelated", 50, None),
+        ];
+        for (name, size, sigs) in paths {
+            conn.execute(
+                "INSERT INTO ValidPaths (path, hash, registrationTime, narSize, sigs, ultimate)
+                 VALUES (?1, 'sha256:0000', 1700000000, ?2, ?3, ?3 IS NULL)",
+                rusqlite::params![p(name), size, sigs],
+            )
+            .unwrap();
+        }
+        // app → lib → libc (→ libc, self-reference as nix records it)
+        for (referrer, reference) in [(1, 2), (2, 3), (3, 3)] {
+            conn.execute(
+                "INSERT INTO Refs (referrer, reference) VALUES (?1, ?2)",
+                [referrer, reference],
+            )
+            .unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn closure_from_fixture() {
+        let dir = fixture();
+        let db = StoreDb::open(&dir.path().join("db.sqlite")).unwrap();
+
+        let mut closure = db.closure(&[p("app")]).unwrap();
+        closure.sort_by(|a, b| a.name.cmp(&b.name));
+        let names: Vec<&str> = closure.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["app", "lib", "libc"]);
+        assert_eq!(closure[0].nar_size, 1000);
+        assert_eq!(closure[0].references, [p("lib")]);
+        assert_eq!(closure[0].signatures.len(), 2);
+        assert_eq!(closure[0].ultimate, Some(false));
+        assert_eq!(closure[0].registration_time, Some(1700000000));
+        assert_eq!(closure[0].nar_hash.as_deref(), Some("sha256:0000"));
+        assert_eq!(closure[1].ultimate, Some(true));
+        assert_eq!(closure[2].references, [p("libc")]);
+
+        let single = db.path_info(&[p("lib")]).unwrap();
+        assert_eq!(single.len(), 1);
+        assert_eq!(single[0].references, [p("libc")]);
+
+        assert_eq!(
+            db.valid_paths(&[p("app"), p("missing")]).unwrap(),
+            [p("app")]
+        );
+    }
+
+    #[test]
+    fn queries_in_chunks() {
+        let dir = fixture();
+        let db = StoreDb::open(&dir.path().join("db.sqlite")).unwrap();
+
+        // app and lib land in different chunks whose closures overlap.
+        let mut paths = vec![p("app")];
+        paths.resize(super::MAX_VARIABLES + 1, p("missing"));
+        paths.push(p("lib"));
+        let mut closure = db.closure(&paths).unwrap();
+        closure.sort_by(|a, b| a.name.cmp(&b.name));
+        let names: Vec<&str> = closure.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["app", "lib", "libc"]);
+        assert_eq!(closure[1].references, [p("libc")]);
+        assert_eq!(closure[2].references, [p("libc")]);
+    }
+
+    #[tokio::test]
+    async fn backend_resolves_store_paths_without_nix() {
+        let dir = fixture();
+        let opts = QueryOptions {
+            backend: Some(Arc::new(
+                DbBackend::open(&dir.path().join("db.sqlite")).unwrap(),
+            )),
+            ..Default::default()
+        };
+        let graph = query_path_info(&[format!("{}/bin/app", p("app"))], true, &opts)
+            .await
+            .unwrap();
+        assert_eq!(graph.roots, [p("app")]);
+        assert_eq!(graph.paths().len(), 3);
+    }
+
+    #[test]
+    fn missing_db_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(StoreDb::open(&dir.path().join("db.sqlite")).is_err());
+    }
+
+    #[test]
+    fn db_paths_for_chroot_stores() {
+        let db = |store| db_path_for_store(store).map(|p| p.display().to_string());
+        assert_eq!(db(None).as_deref(), Some(super::DEFAULT_DB_PATH));
+        assert_eq!(db(Some("local")).as_deref(), Some(super::DEFAULT_DB_PATH));
+        assert_eq!(
+            db(Some("/tmp/root")).as_deref(),
+            Some("/tmp/root/nix/var/nix/db/db.sqlite")
+        );
+        assert_eq!(
+            db(Some("local?root=/tmp/root&store=/opt/nix/store")).as_deref(),
+            Some("/tmp/root/nix/var/nix/db/db.sqlite")
+        );
+        assert_eq!(db(Some("daemon")), None);
+        assert_eq!(db(Some("https://cache.nixos.org")), None);
+    }
+}
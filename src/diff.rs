@@ -21,7 +21,7 @@ impl Ansi {
 /// Derivation outputs (-dev, -man, -lib, ...) end up either on the version
 /// (hello-2.12-man -> version "2.12-man") or, for unversioned paths, on the
 /// pname. Fold them away so all outputs of one derivation group together.
-fn strip_output_suffix<'a>(pname: &'a str, version: &'a str) -> (&'a str, &'a str) {
+pub(crate) fn strip_output_suffix<'a>(pname: &'a str, version: &'a str) -> (&'a str, &'a str) {
     fn strip(s: &str) -> Option<&str> {
         let i = s.rfind('-')?;
         let suffix = &s[i + 1..];
@@ -40,7 +40,7 @@ fn strip_output_suffix<'a>(pname: &'a str, version: &'a str) -> (&'a str, &'a st
 
 /// Mirrors nix's builtins.parseDrvName: the version is the suffix starting at
 /// the first `-` that is followed by a digit; everything before is the pname.
-fn parse_drv_name(name: &str) -> (&str, &str) {
+pub(crate) fn parse_drv_name(name: &str) -> (&str, &str) {
     let bytes = name.as_bytes();
     let mut i = 0;
     while i + 1 < bytes.len() {
@@ -62,15 +62,11 @@ struct Side {
 fn group_by_pname(graph: &StorePathGraph) -> (HashMap<String, Side>, u64) {
     let mut groups: HashMap<String, Side> = HashMap::new();
     let mut total = 0u64;
-    for p in &graph.paths {
+    for p in graph.paths() {
         total += p.nar_size;
-        // disambiguate_names() may have prefixed a hash slice; derive the
-        // original name from the path so duplicates group together.
-        let raw_name = p
-            .path
-            .get(11 + p.hash.len() + 1..)
-            .unwrap_or(p.name.as_str());
-        let (pname, version) = parse_drv_name(raw_name);
+        // Not `p.name`, which may be disambiguated, so duplicates group
+        // together.
+        let (pname, version) = parse_drv_name(graph.original_name(p));
         let (pname, version) = strip_output_suffix(pname, version);
         let entry = groups.entry(pname.to_string()).or_default();
         entry.paths.insert(p.path.clone());
@@ -99,10 +95,10 @@ pub fn write(old: &StorePathGraph, new: &StorePathGraph, out: &mut impl Write) -
     let mut rows = Vec::new();
     for (pname, after) in b {
         let before = a.remove(&pname);
-        if let Some(before) = &before {
-            if before.paths == after.paths {
-                continue;
-            }
+        if let Some(before) = &before
+            && before.paths == after.paths
+        {
+            continue;
         }
         let delta = after.nar_size as i64 - before.as_ref().map_or(0, |s| s.nar_size) as i64;
         rows.push(Row {
@@ -159,8 +155,8 @@ pub fn write(old: &StorePathGraph, new: &StorePathGraph, out: &mut impl Write) -
     writeln!(
         out,
         "{} paths → {} paths, {} → {} ({})",
-        old.paths.len(),
-        new.paths.len(),
+        old.paths().len(),
+        new.paths().len(),
         bytesize::ByteSize(total_a),
         bytesize::ByteSize(total_b),
         fmt_delta(total_b as i64 - total_a as i64),
@@ -186,9 +182,8 @@ fn fmt_delta(d: i64) -> String {
 
 #[cfg(test)]
 mod tests {
-    use super::parse_drv_name;
-
-    use super::strip_output_suffix;
+    use super::{group_by_pname, parse_drv_name, strip_output_suffix};
+    use crate::store_path::testing::{TestGraph, path_in};
 
     #[test]
     fn output_suffix_stripping() {
@@ -220,4 +215,27 @@ mod tests {
             ("nixos-system-eve", "26.05pre-git")
         );
     }
+
+    #[test]
+    fn groups_paths_outside_nix_store() {
+        let mut graph = TestGraph::default();
+        for (c, name) in [
+            ('a', "hello-2.12"),
+            ('b', "hello-2.12-man"),
+            ('c', "hello-2.12"),
+        ] {
+            graph.add(&path_in("/opt/nix/store", c, name), 10, &[]);
+        }
+        let mut graph = graph.build(&[]);
+        graph.detect_store_dir();
+        graph.disambiguate_names();
+
+        let (groups, total) = group_by_pname(&graph);
+        assert_eq!(total, 30);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(
+            groups["hello"].versions.iter().collect::<Vec<_>>(),
+            ["2.12"]
+        );
+    }
 }
@@ -1,20 +1,35 @@
+use crate::path_stats::Layers;
 use crate::store_path::StorePathGraph;
+use std::collections::BTreeMap;
 use std::io::{self, Write};
 
 /// Emit the reference graph in Graphviz dot format, matching the output shape
 /// of the Haskell nix-tree so existing tooling/pipelines keep working.
+///
+/// Paths at the same depth are pinned to one rank. Max depth is used because
+/// between strongly connected components every reference points to a larger
+/// one, so the hint never fights an edge; the members of a cycle share a
+/// depth and so a rank.
 pub fn write(graph: &StorePathGraph, out: &mut impl Write) -> io::Result<()> {
     writeln!(out, "strict digraph {{")?;
-    for p in &graph.paths {
-        for r in &p.references {
-            if r == &p.path {
-                continue;
-            }
-            if let Some(dst) = graph.get_path(r) {
-                writeln!(out, "  {} -> {} [];", quote(&p.name), quote(&dst.name))?;
-            }
+    for (id, p) in (0..).zip(graph.paths()) {
+        for &r in graph.references_of(id) {
+            let dst = graph.path(r);
+            writeln!(out, "  {} -> {} [];", quote(&p.name), quote(&dst.name))?;
         }
     }
+
+    let layers = Layers::new(graph);
+    let mut ranks: BTreeMap<u32, Vec<&str>> = BTreeMap::new();
+    for (p, depth) in graph.paths().iter().zip(&layers.max_depth) {
+        if let Some(depth) = depth {
+            ranks.entry(*depth).or_default().push(&p.name);
+        }
+    }
+    for names in ranks.values().filter(|names| names.len() > 1) {
+        let names: Vec<String> = names.iter().map(|n| quote(n)).collect();
+        writeln!(out, "  {{ rank = same; {}; }}", names.join("; "))?;
+    }
     writeln!(out, "}}")
 }
 
@@ -22,3 +37,53 @@ fn quote(s: &str) -> String {
     let escaped = s.replace('\\', "\\\\").replace('"', "\\\"");
     format!("\"{escaped}\"")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::write;
+    use crate::store_path::testing::{TestGraph, p};
+
+    #[test]
+    fn ranks_by_max_depth() {
+        let mut graph = TestGraph::default();
+        for (name, refs) in [
+            ("app", &["wrapper", "lib"][..]),
+            ("wrapper", &["python"]),
+            ("python", &["lib"]),
+            ("lib", &[]),
+            ("docs", &[]),
+        ] {
+            graph.add_named(name, 0, refs);
+        }
+        let graph = graph.build(&[p("app"), p("docs")]);
+
+        let mut out = Vec::new();
+        write(&graph, &mut out).unwrap();
+        let dot = String::from_utf8(out).unwrap();
+        assert!(
+            dot.contains(r#"  { rank = same; "app"; "docs"; }"#),
+            "{dot}"
+        );
+        // lib is one step from app but three via wrapper → python.
+        assert!(!dot.contains(r#""wrapper"; "lib""#), "{dot}");
+        assert!(dot.ends_with("}\n"));
+
+        // out ↔ dev is one component, a step above lib.
+        let mut graph = TestGraph::default();
+        for (name, refs) in [
+            ("app", &["out"][..]),
+            ("out", &["dev"]),
+            ("dev", &["out", "lib"]),
+            ("lib", &[]),
+        ] {
+            graph.add_named(name, 0, refs);
+        }
+        let graph = graph.build(&[p("app")]);
+
+        let mut out = Vec::new();
+        write(&graph, &mut out).unwrap();
+        let dot = String::from_utf8(out).unwrap();
+        let ranks: Vec<&str> = dot.lines().filter(|l| l.contains("rank")).collect();
+        assert_eq!(ranks, [r#"  { rank = same; "out"; "dev"; }"#], "{dot}");
+    }
+}
@@ -0,0 +1,249 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{self, Write};
+
+use crate::diff::{parse_drv_name, strip_output_suffix};
+use crate::path_stats::Stats;
+use crate::store_path::{PathId, StorePathGraph};
+
+/// One of several store paths of the same package and output.
+#[derive(Debug, Clone)]
+pub struct DuplicateCopy {
+    pub path: PathId,
+    pub version: String,
+    pub nar_size: u64,
+    pub closure_size: u64,
+    /// Top-level paths (what the roots refer to directly) whose closure
+    /// contains this copy; the copy itself if it is top-level.
+    pub pulled_in_by: Vec<PathId>,
+}
+
+/// A package present in more than one version or build.
+#[derive(Debug, Clone)]
+pub struct Duplicate {
+    pub pname: String,
+    /// `out`, `dev`, `man`, …; only copies of the same output are compared.
+    pub output: String,
+    /// Largest first.
+    pub copies: Vec<DuplicateCopy>,
+}
+
+impl Duplicate {
+    /// What keeping only the largest copy would save.
+    pub fn extra_size(&self) -> u64 {
+        self.copies.iter().skip(1).map(|c| c.nar_size).sum()
+    }
+}
+
+/// Every package/output with more than one store path in the closure, the
+/// most wasteful first.
+pub fn find(graph: &StorePathGraph, stats: &Stats) -> Vec<Duplicate> {
+    let mut groups: HashMap<(&str, &str), Vec<(PathId, &str)>> = HashMap::new();
+    for (id, p) in (0..).zip(graph.paths()) {
+        let name = graph.original_name(p);
+        let (raw_pname, raw_version) = parse_drv_name(name);
+        let (pname, version) = strip_output_suffix(raw_pname, raw_version);
+        // Whatever strip_output_suffix took off, without its `-`.
+        let output = if version.len() < raw_version.len() {
+            &raw_version[version.len() + 1..]
+        } else if pname.len() < raw_pname.len() {
+            &raw_pname[pname.len() + 1..]
+        } else {
+            "out"
+        };
+        groups
+            .entry((pname, output))
+            .or_default()
+            .push((id, version));
+    }
+
+    // With one root, "top-level" means what it refers to (a system's
+    // packages); with several, the roots themselves.
+    let top_level: HashSet<PathId> = match &graph.root_ids()[..] {
+        &[root] => graph.references_of(root).iter().copied().collect(),
+        roots => roots.iter().copied().collect(),
+    };
+
+    let mut duplicates: Vec<Duplicate> = groups
+        .into_iter()
+        .filter(|(_, paths)| paths.len() > 1)
+        .map(|((pname, output), paths)| {
+            let mut copies: Vec<DuplicateCopy> = paths
+                .into_iter()
+                .map(|(path, version)| DuplicateCopy {
+                    path,
+                    version: version.to_string(),
+                    nar_size: graph.path(path).nar_size,
+                    closure_size: stats[path].closure_size,
+                    pulled_in_by: pulled_in_by(graph, stats, &top_level, path),
+                })
+                .collect();
+            copies.sort_by(|a, b| {
+                b.nar_size
+                    .cmp(&a.nar_size)
+                    .then_with(|| graph.path(a.path).path.cmp(&graph.path(b.path).path))
+            });
+            Duplicate {
+                pname: pname.to_string(),
+                output: output.to_string(),
+                copies,
+            }
+        })
+        .collect();
+    duplicates.sort_by(|a, b| {
+        b.extra_size()
+            .cmp(&a.extra_size())
+            .then_with(|| a.pname.cmp(&b.pname))
+            .then_with(|| a.output.cmp(&b.output))
+    });
+    duplicates
+}
+
+/// Walk referrers up from `path` and collect the top-level paths reached.
+fn pulled_in_by(
+    graph: &StorePathGraph,
+    stats: &Stats,
+    top_level: &HashSet<PathId>,
+    path: PathId,
+) -> Vec<PathId> {
+    let mut seen = HashSet::from([path]);
+    let mut queue = VecDeque::from([path]);
+    let mut found = Vec::new();
+    while let Some(p) = queue.pop_front() {
+        if top_level.contains(&p) {
+            found.push(p);
+            continue;
+        }
+        for &parent in &stats[p].immediate_parents {
+            if seen.insert(parent) {
+                queue.push_back(parent);
+            }
+        }
+    }
+    found.sort_by_key(|&p| &graph.path(p).path);
+    found
+}
+
+/// The report for `--duplicates` and the TUI overlay.
+pub fn lines(graph: &StorePathGraph, duplicates: &[Duplicate]) -> Vec<String> {
+    let extra: u64 = duplicates.iter().map(Duplicate::extra_size).sum();
+    let mut lines = vec![format!(
+        "{} duplicated packages, {} extra",
+        duplicates.len(),
+        bytesize::ByteSize(extra)
+    )];
+    for dup in duplicates {
+        lines.push(String::new());
+        let output = if dup.output == "out" {
+            String::new()
+        } else {
+            format!(" ({})", dup.output)
+        };
+        lines.push(format!(
+            "{}{output}: {} copies, {} extra",
+            dup.pname,
+            dup.copies.len(),
+            bytesize::ByteSize(dup.extra_size())
+        ));
+        for copy in &dup.copies {
+            let hash = &graph.path(copy.path).hash[..8];
+            let mut via: Vec<String> = copy
+                .pulled_in_by
+                .iter()
+                .take(5)
+                .map(|&p| graph.path(p).short_name().to_string())
+                .collect();
+            if copy.pulled_in_by.len() > 5 {
+                via.push(format!("+{} more", copy.pulled_in_by.len() - 5));
+            }
+            lines.push(format!(
+                "  {:<12} {hash}  nar {:>10}  closure {:>10}  via {}",
+                if copy.version.is_empty() {
+                    "-"
+                } else {
+                    &copy.version
+                },
+                bytesize::ByteSize(copy.nar_size).to_string(),
+                bytesize::ByteSize(copy.closure_size).to_string(),
+                via.join(", ")
+            ));
+        }
+    }
+    lines
+}
+
+pub fn write(
+    graph: &StorePathGraph,
+    duplicates: &[Duplicate],
+    out: &mut impl Write,
+) -> io::Result<()> {
+    for line in lines(graph, duplicates) {
+        writeln!(out, "{line}")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::path_stats::calculate_stats;
+    use crate::store_path::testing::{TestGraph, ph as p};
+
+    #[test]
+    fn finds_versions_and_rebuilds() {
+        let mut graph = TestGraph::default();
+        for (c, name, size, refs) in [
+            (
+                's',
+                "system",
+                1,
+                vec![p('f', "firefox-128"), p('g', "gimp-2.10")],
+            ),
+            (
+                'f',
+                "firefox-128",
+                100,
+                vec![p('a', "python3-3.12.4"), p('x', "openssl-3.0.13")],
+            ),
+            (
+                'g',
+                "gimp-2.10",
+                100,
+                vec![p('b', "python3-3.11.9"), p('y', "openssl-3.0.13")],
+            ),
+            ('a', "python3-3.12.4", 60, vec![p('x', "openssl-3.0.13")]),
+            (
+                'b',
+                "python3-3.11.9",
+                50,
+                vec![p('c', "python3-3.11.9-dev")],
+            ),
+            ('c', "python3-3.11.9-dev", 5, vec![]),
+            ('x', "openssl-3.0.13", 6, vec![]),
+            ('y', "openssl-3.0.13", 6, vec![]),
+        ] {
+            graph.add(&p(c, name), size, &refs);
+        }
+        let mut graph = graph.build(&[p('s', "system")]);
+        graph.disambiguate_names();
+        let stats = calculate_stats(&graph);
+
+        let dups = find(&graph, &stats);
+        let summary: Vec<(&str, &str, usize)> = dups
+            .iter()
+            .map(|d| (d.pname.as_str(), d.output.as_str(), d.copies.len()))
+            .collect();
+        assert_eq!(summary, [("python3", "out", 2), ("openssl", "out", 2)]);
+        assert_eq!(dups[0].extra_size(), 50);
+        assert_eq!(dups[0].copies[0].version, "3.12.4");
+        assert_eq!(dups[0].copies[0].closure_size, 66);
+        let id = |c, name| graph.id_of(&p(c, name)).unwrap();
+        assert_eq!(dups[0].copies[0].pulled_in_by, [id('f', "firefox-128")]);
+        assert_eq!(dups[0].copies[1].pulled_in_by, [id('g', "gimp-2.10")]);
+        assert_eq!(dups[1].copies[0].pulled_in_by, [id('f', "firefox-128")]);
+
+        let lines = lines(&graph, &dups);
+        assert_eq!(lines[0], "2 duplicated packages, 56 B extra");
+        assert_eq!(lines[2], "python3: 2 copies, 50 B extra");
+        assert!(lines[3].contains("via firefox-128"), "{lines:#?}");
+    }
+}
@@ -0,0 +1,570 @@
+use anyhow::Result;
+use std::borrow::Cow;
+use std::fs;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+use crate::precise::{Unreadable, visit};
+use crate::store_path::{PathId, StorePathGraph};
+
+const PT_DYNAMIC: u32 = 2;
+const PT_INTERP: u32 = 3;
+const PT_LOAD: u32 = 1;
+const DT_NULL: u64 = 0;
+const DT_NEEDED: u64 = 1;
+const DT_STRTAB: u64 = 5;
+const DT_RPATH: u64 = 15;
+const DT_RUNPATH: u64 = 29;
+
+/// What the dynamic linker reads from an executable or shared library.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Dynamic {
+    /// `PT_INTERP`; only executables have one.
+    pub interpreter: Option<String>,
+    /// `DT_NEEDED` sonames, in load order.
+    pub needed: Vec<String>,
+    /// `DT_RUNPATH` directories, split at `:`.
+    pub runpath: Vec<String>,
+    /// `DT_RPATH` directories; the linker ignores them if there is a runpath.
+    pub rpath: Vec<String>,
+}
+
+impl Dynamic {
+    /// `None` for anything that is not a well-formed ELF file.
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        Self::from_reader(&Reader::new(Source::Bytes(bytes))?)
+    }
+
+    /// Like `parse`, reading only the headers, the dynamic section and the
+    /// strings it names; a file that cannot be read counts as not ELF.
+    pub fn read(file: &fs::File) -> Option<Self> {
+        let len = file.metadata().ok()?.len();
+        Self::from_reader(&Reader::new(Source::File(file, len))?)
+    }
+
+    fn from_reader(elf: &Reader) -> Option<Self> {
+        let (phoff, phentsize, phnum) = if elf.wide {
+            (elf.word(0x20)?, elf.half(0x36)?, elf.half(0x38)?)
+        } else {
+            (elf.word(0x1c)?, elf.half(0x2a)?, elf.half(0x2c)?)
+        };
+
+        let mut dynamic = Self::default();
+        let mut loads = Vec::new();
+        let mut dynamic_segment = None;
+        for i in 0..phnum as u64 {
+            let ph = phoff.checked_add(i * phentsize as u64)?;
+            // (offset, vaddr, filesz)
+            let field = |at: u64| elf.word(ph.checked_add(at)?);
+            let segment = if elf.wide {
+                (field(8)?, field(16)?, field(32)?)
+            } else {
+                (field(4)?, field(8)?, field(16)?)
+            };
+            match elf.u32(ph)? {
+                PT_LOAD => loads.push(segment),
+                PT_DYNAMIC => dynamic_segment = Some(segment),
+                PT_INTERP => dynamic.interpreter = Some(c_str(&elf.read(segment.0, segment.2)?)?),
+                _ => {}
+            }
+        }
+        let Some((offset, _, size)) = dynamic_segment else {
+            return Some(dynamic);
+        };
+
+        let entry = if elf.wide { 16 } else { 8 };
+        let table = elf.read(offset, size).filter(|t| t.len() as u64 == size)?;
+        let mut strtab = None;
+        let mut strings = Vec::new();
+        for entry in table.chunks_exact(entry) {
+            let (tag, value) = entry.split_at(entry.len() / 2);
+            let (tag, value) = (elf.decode(tag)?, elf.decode(value)?);
+            match tag {
+                DT_NULL => break,
+                DT_STRTAB => strtab = Some(value),
+                DT_NEEDED | DT_RUNPATH | DT_RPATH => strings.push((tag, value)),
+                _ => {}
+            }
+        }
+        // The string table is given as an address; find it in the file.
+        let strtab = strtab?;
+        let strtab = loads
+            .iter()
+            .find(|&&(_, vaddr, filesz)| vaddr <= strtab && strtab - vaddr < filesz)
+            .and_then(|&(offset, vaddr, _)| offset.checked_add(strtab - vaddr))?;
+        for (tag, value) in strings {
+            let s = elf.c_str(strtab.checked_add(value)?)?;
+            match tag {
+                DT_NEEDED => dynamic.needed.push(s),
+                DT_RUNPATH => dynamic.runpath.extend(split_dirs(&s)),
+                _ => dynamic.rpath.extend(split_dirs(&s)),
+            }
+        }
+        Some(dynamic)
+    }
+
+    /// The directories the linker searches for `needed`.
+    pub fn search_path(&self) -> &[String] {
+        if self.runpath.is_empty() {
+            &self.rpath
+        } else {
+            &self.runpath
+        }
+    }
+}
+
+fn split_dirs(s: &str) -> impl Iterator<Item = String> + '_ {
+    s.split(':').filter(|d| !d.is_empty()).map(str::to_string)
+}
+
+fn c_str(bytes: &[u8]) -> Option<String> {
+    let end = bytes.iter().position(|&b| b == 0)?;
+    Some(String::from_utf8_lossy(&bytes[..end]).into_owned())
+}
+
+/// Where a `Reader` takes its bytes from: a file already in memory, or an
+/// open file of the given length, read one field at a time.
+enum Source<'a> {
+    Bytes(&'a [u8]),
+    File(&'a fs::File, u64),
+}
+
+/// Bounds-checked reads of 32- or 64-bit, little- or big-endian fields.
+struct Reader<'a> {
+    source: Source<'a>,
+    wide: bool,
+    big_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn new(source: Source<'a>) -> Option<Self> {
+        let mut elf = Self {
+            source,
+            wide: false,
+            big_endian: false,
+        };
+        let ident = elf.read(0, 6)?;
+        if ident.get(..4)? != b"\x7fELF" {
+            return None;
+        }
+        elf.wide = match ident.get(4)? {
+            1 => false,
+            2 => true,
+            _ => return None,
+        };
+        elf.big_endian = match ident.get(5)? {
+            1 => false,
+            2 => true,
+            _ => return None,
+        };
+        Some(elf)
+    }
+
+    /// Up to `len` bytes from `at`, however many the file has.
+    fn read(&self, at: u64, len: u64) -> Option<Cow<'a, [u8]>> {
+        match self.source {
+            Source::Bytes(bytes) => {
+                let start = usize::try_from(at).ok()?;
+                let end = usize::try_from(at.saturating_add(len))
+                    .unwrap_or(usize::MAX)
+                    .min(bytes.len());
+                bytes.get(start..end).map(Cow::Borrowed)
+            }
+            Source::File(file, file_len) => {
+                let len = len.min(file_len.checked_sub(at)?);
+                let mut buf = vec![0; usize::try_from(len).ok()?];
+                file.read_exact_at(&mut buf, at).ok()?;
+                Some(Cow::Owned(buf))
+            }
+        }
+    }
+
+    /// The NUL-terminated string at `at`, read a piece at a time.
+    fn c_str(&self, at: u64) -> Option<String> {
+        let mut bytes = Vec::new();
+        loop {
+            let piece = self.read(at.checked_add(bytes.len() as u64)?, 256)?;
+            if piece.is_empty() {
+                return None;
+            }
+            if let Some(end) = piece.iter().position(|&b| b == 0) {
+                bytes.extend_from_slice(&piece[..end]);
+                return Some(String::from_utf8_lossy(&bytes).into_owned());
+            }
+            bytes.extend_from_slice(&piece);
+        }
+    }
+
+    /// A field of up to 8 bytes in the file's byte order.
+    fn decode(&self, bytes: &[u8]) -> Option<u64> {
+        if bytes.len() > 8 {
+            return None;
+        }
+        let mut value = 0u64;
+        for i in 0..bytes.len() {
+            let b = if self.big_endian {
+                bytes[i]
+            } else {
+                bytes[bytes.len() - 1 - i]
+            };
+            value = value << 8 | b as u64;
+        }
+        Some(value)
+    }
+
+    fn uint(&self, at: u64, len: u64) -> Option<u64> {
+        let bytes = self.read(at, len)?;
+        if bytes.len() as u64 != len {
+            return None;
+        }
+        self.decode(&bytes)
+    }
+
+    fn half(&self, at: u64) -> Option<u16> {
+        self.uint(at, 2).map(|v| v as u16)
+    }
+
+    fn u32(&self, at: u64) -> Option<u32> {
+        self.uint(at, 4).map(|v| v as u32)
+    }
+
+    /// An address, offset or size: 8 bytes in 64-bit files, 4 otherwise.
+    fn word(&self, at: u64) -> Option<u64> {
+        self.uint(at, if self.wide { 8 } else { 4 })
+    }
+}
+
+/// How the ELF files of one path use its references.
+#[derive(Debug, Clone, Default)]
+pub struct Linking {
+    /// Executables and shared libraries found, not counting symlinks.
+    pub binaries: usize,
+    /// References that provide a needed library or the interpreter, with
+    /// the sonames (or interpreter file name) taken from each.
+    pub linked: Vec<(PathId, Vec<String>)>,
+    /// References named by a runpath (or rpath) entry although no needed
+    /// library resolves there: over-linking, typically a stale entry.
+    pub runpath_only: Vec<PathId>,
+    /// Needed libraries found in none of the searched directories.
+    pub unresolved: Vec<String>,
+    /// Files that could not be opened, so are not counted.
+    pub unreadable: Vec<Unreadable>,
+}
+
+impl Linking {
+    /// Parse the headers of every ELF file under `path` on disk and resolve what it needs
+    /// the way the dynamic linker would: runpath (or rpath) with `$ORIGIN`
+    /// expanded, then the interpreter's own directory, which is where the
+    /// glibc in nixpkgs looks by default.
+    pub fn new(graph: &StorePathGraph, path: PathId) -> Result<Self> {
+        let root = Path::new(&graph.path(path).path);
+        let mut linking = Self::default();
+        let mut searched = Vec::new();
+        linking.unreadable = visit(root, |file, full, meta| {
+            if !meta.is_file() {
+                return Ok(());
+            }
+            let Some(dynamic) = Dynamic::read(&fs::File::open(full)?) else {
+                return Ok(());
+            };
+            linking.binaries += 1;
+            let origin = root.join(file);
+            let origin = origin.parent().unwrap_or(root);
+            let dirs: Vec<PathBuf> = dynamic
+                .search_path()
+                .iter()
+                .map(|d| expand_origin(d, origin))
+                .collect();
+            searched.extend(dirs.iter().filter_map(|d| owner(graph, d)));
+
+            if let Some(interp) = &dynamic.interpreter {
+                let name = Path::new(interp).file_name().unwrap_or_default();
+                linking.link(owner(graph, Path::new(interp)), &name.to_string_lossy());
+            }
+            let interp_dir = dynamic
+                .interpreter
+                .as_deref()
+                .and_then(|i| Path::new(i).parent());
+            for needed in &dynamic.needed {
+                let found = if needed.contains('/') {
+                    Some(expand_origin(needed, origin))
+                } else {
+                    dirs.iter()
+                        .map(PathBuf::as_path)
+                        .chain(interp_dir)
+                        .map(|d| d.join(needed))
+                        .find(|lib| lib.exists())
+                };
+                match found {
+                    Some(lib) => linking.link(owner(graph, &lib), needed),
+                    None if !linking.unresolved.contains(needed) => {
+                        linking.unresolved.push(needed.clone());
+                    }
+                    None => {}
+                }
+            }
+            Ok(())
+        })?;
+
+        linking.linked.retain(|(p, _)| *p != path);
+        for p in searched {
+            if p != path
+                && !linking.runpath_only.contains(&p)
+                && !linking.linked.iter().any(|(l, _)| *l == p)
+            {
+                linking.runpath_only.push(p);
+            }
+        }
+        Ok(linking)
+    }
+
+    fn link(&mut self, provider: Option<PathId>, name: &str) {
+        let Some(provider) = provider else {
+            return;
+        };
+        let names = match self.linked.iter().position(|(p, _)| *p == provider) {
+            Some(i) => &mut self.linked[i].1,
+            None => {
+                self.linked.push((provider, Vec::new()));
+                &mut self.linked.last_mut().unwrap().1
+            }
+        };
+        if !names.iter().any(|n| n == name) {
+            names.push(name.to_string());
+        }
+    }
+}
+
+fn expand_origin(dir: &str, origin: &Path) -> PathBuf {
+    let origin = origin.to_string_lossy();
+    PathBuf::from(
+        dir.replace("${ORIGIN}", &origin)
+            .replace("$ORIGIN", &origin),
+    )
+}
+
+/// The store path `file` lives in, if it is in the graph.
+fn owner(graph: &StorePathGraph, file: &Path) -> Option<PathId> {
+    let rest = file.strip_prefix(&graph.store_dir).ok()?;
+    let base = rest.components().next()?;
+    graph.id_of(Path::new(&graph.store_dir).join(base).to_str()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store_path::testing::TestGraph;
+    use std::io::Write;
+
+    /// A minimal shared object of either class and byte order: one load
+    /// segment mapping the whole file at 0x1000, a dynamic segment and an
+    /// interpreter.
+    fn elf(wide: bool, big_endian: bool, interp: &str, needed: &[&str], runpath: &str) -> Vec<u8> {
+        let uint = |value: u64, len: usize| -> Vec<u8> {
+            let bytes = value.to_le_bytes()[..len].to_vec();
+            if big_endian {
+                bytes.into_iter().rev().collect()
+            } else {
+                bytes
+            }
+        };
+        let word = |value: u64| uint(value, if wide { 8 } else { 4 });
+        let (ehsize, phentsize, entry) = if wide { (64, 56, 16) } else { (52, 32, 8) };
+
+        let mut strtab = vec![0u8];
+        let mut add = |s: &str| {
+            let at = strtab.len() as u64;
+            strtab.extend_from_slice(s.as_bytes());
+            strtab.push(0);
+            at
+        };
+        let mut dynamic: Vec<(u64, u64)> = needed.iter().map(|n| (DT_NEEDED, add(n))).collect();
+        dynamic.push((DT_RUNPATH, add(runpath)));
+        let phnum = 3u64;
+        let interp_at = ehsize + phnum * phentsize;
+        let dynamic_at = interp_at + interp.len() as u64 + 1;
+        let strtab_at = dynamic_at + (dynamic.len() as u64 + 2) * entry;
+        dynamic.push((DT_STRTAB, 0x1000 + strtab_at));
+        dynamic.push((DT_NULL, 0));
+
+        let mut out = vec![
+            0x7f,
+            b'E',
+            b'L',
+            b'F',
+            1 + wide as u8,
+            1 + big_endian as u8,
+            1,
+        ];
+        out.resize(if wide { 0x20 } else { 0x1c }, 0);
+        out.extend(word(ehsize));
+        out.resize(if wide { 0x36 } else { 0x2a }, 0);
+        out.extend(uint(phentsize, 2));
+        out.extend(uint(phnum, 2));
+        out.resize(ehsize as usize, 0);
+        let total = strtab_at + strtab.len() as u64;
+        for (kind, offset, size) in [
+            (PT_LOAD, 0, total),
+            (PT_INTERP, interp_at, interp.len() as u64 + 1),
+            (PT_DYNAMIC, dynamic_at, dynamic.len() as u64 * entry),
+        ] {
+            let (vaddr, kind) = (0x1000 + offset, uint(kind as u64, 4));
+            if wide {
+                out.extend(kind);
+                out.extend(uint(0, 4));
+                for field in [offset, vaddr, vaddr, size, size, 8] {
+                    out.extend(word(field));
+                }
+            } else {
+                out.extend(kind);
+                for field in [offset, vaddr, vaddr, size, size, 0, 8] {
+                    out.extend(word(field));
+                }
+            }
+        }
+        out.extend(interp.as_bytes());
+        out.push(0);
+        for (tag, value) in dynamic {
+            out.extend(word(tag));
+            out.extend(word(value));
+        }
+        out.extend(strtab);
+        out
+    }
+
+    fn elf64(interp: &str, needed: &[&str], runpath: &str) -> Vec<u8> {
+        elf(true, false, interp, needed, runpath)
+    }
+
+    #[test]
+    fn parses_dynamic_section() {
+        let bytes = elf64(
+            "/lib/ld.so",
+            &["libssl.so.3", "libc.so.6"],
+            "/a:$ORIGIN/../lib",
+        );
+        assert_eq!(
+            Dynamic::parse(&bytes).unwrap(),
+            Dynamic {
+                interpreter: Some("/lib/ld.so".to_string()),
+                needed: vec!["libssl.so.3".to_string(), "libc.so.6".to_string()],
+                runpath: vec!["/a".to_string(), "$ORIGIN/../lib".to_string()],
+                rpath: vec![],
+            }
+        );
+        assert_eq!(Dynamic::parse(b"#!/bin/sh\n"), None);
+        assert_eq!(Dynamic::parse(&bytes[..100]), None);
+    }
+
+    #[test]
+    fn parses_32_bit_and_big_endian_files() {
+        let expected = Dynamic {
+            interpreter: Some("/lib/ld.so".to_string()),
+            needed: vec!["libc.so.6".to_string()],
+            runpath: vec!["/a".to_string()],
+            rpath: vec![],
+        };
+        for (wide, big_endian) in [(false, false), (false, true), (true, true)] {
+            let bytes = elf(wide, big_endian, "/lib/ld.so", &["libc.so.6"], "/a");
+            assert_eq!(
+                Dynamic::parse(&bytes).as_ref(),
+                Some(&expected),
+                "wide: {wide}, big endian: {big_endian}"
+            );
+            let mut file = tempfile::tempfile().unwrap();
+            file.write_all(&bytes).unwrap();
+            assert_eq!(Dynamic::read(&file).as_ref(), Some(&expected));
+        }
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(b"#!/bin/sh\n").unwrap();
+        assert_eq!(Dynamic::read(&file), None);
+    }
+
+    #[test]
+    fn rejects_malformed_headers() {
+        let bytes = elf64("/lib/ld.so", &["libc.so.6"], "/a");
+        let patch = |at: usize, value: u64| {
+            let mut bytes = bytes.clone();
+            bytes[at..at + 8].copy_from_slice(&value.to_le_bytes());
+            bytes
+        };
+        // e_phoff, then the offset of the load segment the string table is
+        // found through, then the offset of the dynamic segment.
+        let (load, dynamic) = (64, 64 + 2 * 56);
+        for (at, value) in [
+            (0x20, u64::MAX - 4),
+            (load + 8, u64::MAX - 4),
+            (dynamic + 8, u64::MAX - 4),
+            (dynamic + 32, u64::MAX),
+        ] {
+            assert_eq!(Dynamic::parse(&patch(at, value)), None, "{at:#x}");
+        }
+    }
+
+    #[test]
+    fn flags_runpath_only_references() {
+        let store = tempfile::tempdir().unwrap();
+        let mut graph = TestGraph::new(store.path().to_str().unwrap());
+        for (c, name, refs) in [
+            (
+                'a',
+                "app",
+                vec![
+                    graph.path('g', "glibc"),
+                    graph.path('o', "openssl"),
+                    graph.path('z', "zlib"),
+                ],
+            ),
+            ('g', "glibc", vec![]),
+            ('o', "openssl", vec![]),
+            ('z', "zlib", vec![]),
+        ] {
+            graph.add(&graph.path(c, name), 0, &refs);
+        }
+        for (c, name, lib) in [
+            ('g', "glibc", "libc.so.6"),
+            ('g', "glibc", "ld-linux.so.2"),
+            ('o', "openssl", "libssl.so.3"),
+            ('z', "zlib", "libz.so.1"),
+        ] {
+            fs::create_dir_all(format!("{}/lib", graph.path(c, name))).unwrap();
+            fs::write(format!("{}/lib/{lib}", graph.path(c, name)), b"").unwrap();
+        }
+        let app = graph.path('a', "app");
+        fs::create_dir_all(format!("{app}/bin")).unwrap();
+        fs::create_dir_all(format!("{app}/lib")).unwrap();
+        fs::write(format!("{app}/lib/libown.so"), b"").unwrap();
+        let runpath = format!(
+            "$ORIGIN/../lib:{}/lib:{}/lib",
+            graph.path('o', "openssl"),
+            graph.path('z', "zlib")
+        );
+        let interp = format!("{}/lib/ld-linux.so.2", graph.path('g', "glibc"));
+        fs::write(
+            format!("{app}/bin/app"),
+            elf64(
+                &interp,
+                &["libown.so", "libssl.so.3", "libc.so.6", "libgone.so"],
+                &runpath,
+            ),
+        )
+        .unwrap();
+        fs::write(format!("{app}/bin/script"), "#!/bin/sh\n").unwrap();
+
+        let graph = graph.build(&[]);
+        let linking = Linking::new(&graph, 0).unwrap();
+        assert_eq!(linking.binaries, 1);
+        assert_eq!(
+            linking.linked,
+            [
+                (
+                    1,
+                    vec!["ld-linux.so.2".to_string(), "libc.so.6".to_string()]
+                ),
+                (2, vec!["libssl.so.3".to_string()]),
+            ]
+        );
+        assert_eq!(linking.runpath_only, [3]);
+        assert_eq!(linking.unresolved, ["libgone.so"]);
+    }
+}
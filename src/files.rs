@@ -0,0 +1,431 @@
+use anyhow::{Context, Result};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use crate::pattern::glob_match;
+use crate::store_path::{PathId, StorePathGraph};
+use regex::Regex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    File,
+    Dir,
+    Symlink,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub name: String,
+    pub kind: Kind,
+    /// Disk usage like `du`: allocated blocks, for a directory everything
+    /// below it, and a file with several hardlinks only at the first name
+    /// seen (0 at the others).
+    pub size: u64,
+}
+
+/// The files of one store path on disk.
+#[derive(Debug, Clone, Default)]
+pub struct Tree {
+    pub size: u64,
+    /// Entries of each directory, keyed relative to the store path (the
+    /// path itself is ""), largest first. A store path that is a single
+    /// file is listed as the only entry of "".
+    dirs: HashMap<PathBuf, Vec<Entry>>,
+    single_file: bool,
+}
+
+/// Inodes already counted, so hardlinks are not counted twice.
+type Seen = HashSet<(u64, u64)>;
+
+impl Tree {
+    pub fn scan(path: &Path) -> Result<Self> {
+        Self::scan_with(path, &mut Seen::new())
+    }
+
+    fn scan_with(path: &Path, seen: &mut Seen) -> Result<Self> {
+        let mut tree = Self::default();
+        let meta = metadata(path)?;
+        tree.size = if meta.is_dir() {
+            tree.scan_dir(path, PathBuf::new(), &meta, seen)?
+        } else {
+            let entry = Entry {
+                name: path
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into(),
+                kind: kind(&meta),
+                size: usage(&meta, seen),
+            };
+            let size = entry.size;
+            tree.dirs.insert(PathBuf::new(), vec![entry]);
+            tree.single_file = true;
+            size
+        };
+        Ok(tree)
+    }
+
+    fn scan_dir(
+        &mut self,
+        full: &Path,
+        rel: PathBuf,
+        meta: &fs::Metadata,
+        seen: &mut Seen,
+    ) -> Result<u64> {
+        let mut total = usage(meta, seen);
+        let mut entries = Vec::new();
+        let read_error = || format!("Cannot read {}", full.display());
+        let mut names = fs::read_dir(full)
+            .with_context(read_error)?
+            .map(|e| e.map(|e| e.file_name()))
+            .collect::<std::io::Result<Vec<_>>>()
+            .with_context(read_error)?;
+        // By name, so which of several hardlinks counts does not depend on
+        // the file system's order.
+        names.sort();
+        for name in names {
+            let child = full.join(&name);
+            let meta = metadata(&child)?;
+            let size = if meta.is_dir() {
+                self.scan_dir(&child, rel.join(&name), &meta, seen)?
+            } else {
+                usage(&meta, seen)
+            };
+            total += size;
+            entries.push(Entry {
+                name: name.to_string_lossy().into(),
+                kind: kind(&meta),
+                size,
+            });
+        }
+        entries.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.name.cmp(&b.name)));
+        self.dirs.insert(rel, entries);
+        Ok(total)
+    }
+
+    /// The entries of `dir`, relative to the store path; empty if it is not
+    /// a directory.
+    pub fn entries(&self, dir: &Path) -> &[Entry] {
+        self.dirs.get(dir).map_or(&[], Vec::as_slice)
+    }
+
+    /// Every entry but the directories themselves, relative to the store
+    /// path, in no particular order; "" if the store path is a single file.
+    fn leaves(&self) -> impl Iterator<Item = (PathBuf, &Entry)> {
+        let single_file = self.single_file;
+        self.dirs.iter().flat_map(move |(dir, entries)| {
+            entries
+                .iter()
+                .filter(|e| e.kind != Kind::Dir)
+                .map(move |e| match single_file {
+                    true => (PathBuf::new(), e),
+                    false => (dir.join(&e.name), e),
+                })
+        })
+    }
+
+    /// Every file (not directory or symlink), like `leaves`.
+    pub fn files(&self) -> impl Iterator<Item = (PathBuf, &Entry)> {
+        self.leaves().filter(|(_, e)| e.kind == Kind::File)
+    }
+}
+
+fn metadata(path: &Path) -> Result<fs::Metadata> {
+    fs::symlink_metadata(path).with_context(|| format!("Cannot read {}", path.display()))
+}
+
+fn kind(meta: &fs::Metadata) -> Kind {
+    if meta.is_dir() {
+        Kind::Dir
+    } else if meta.is_symlink() {
+        Kind::Symlink
+    } else {
+        Kind::File
+    }
+}
+
+fn usage(meta: &fs::Metadata, seen: &mut Seen) -> u64 {
+    if meta.nlink() > 1 && !meta.is_dir() && !seen.insert((meta.dev(), meta.ino())) {
+        return 0;
+    }
+    meta.blocks() * 512
+}
+
+/// A file from `largest`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct LargeFile {
+    pub size: u64,
+    pub path: PathId,
+    /// Relative to the store path.
+    pub file: PathBuf,
+}
+
+/// The `limit` largest files of every path in the graph, largest first, and
+/// how many paths could not be read. A file hardlinked into several paths
+/// (as `auto-optimise-store` does) is listed once.
+pub fn largest(graph: &StorePathGraph, limit: usize) -> (Vec<LargeFile>, usize) {
+    let mut seen = Seen::new();
+    let mut heap = BinaryHeap::new();
+    let mut unreadable = 0;
+    for (id, p) in (0..).zip(graph.paths()) {
+        let Ok(tree) = Tree::scan_with(Path::new(&p.path), &mut seen) else {
+            unreadable += 1;
+            continue;
+        };
+        for (file, entry) in tree.files() {
+            if entry.size == 0 {
+                continue;
+            }
+            heap.push(Reverse(LargeFile {
+                size: entry.size,
+                path: id,
+                file,
+            }));
+            if heap.len() > limit {
+                heap.pop();
+            }
+        }
+    }
+    let files = heap
+        .into_sorted_vec()
+        .into_iter()
+        .map(|Reverse(f)| f)
+        .collect();
+    (files, unreadable)
+}
+
+/// The report for the TUI overlay.
+pub fn largest_lines(
+    graph: &StorePathGraph,
+    files: &[LargeFile],
+    unreadable: usize,
+) -> Vec<String> {
+    let mut header = format!(
+        "{} largest files in {} paths",
+        files.len(),
+        graph.paths().len()
+    );
+    if unreadable > 0 {
+        header.push_str(&format!(" ({unreadable} not on disk)"));
+    }
+    let mut lines = vec![header, String::new()];
+    lines.extend(files.iter().map(|f| {
+        let name = graph.path(f.path).short_name();
+        let file = if f.file.as_os_str().is_empty() {
+            name.to_string()
+        } else {
+            format!("{name}/{}", f.file.display())
+        };
+        format!("{:>10}  {file}", bytesize::ByteSize(f.size))
+    }));
+    lines
+}
+
+/// What `FileIndex::find` looks for in file names relative to their store path.
+#[derive(Debug, Clone)]
+pub enum FileQuery {
+    /// `*` and `?` as in `--what-if`; without a `/` it only has to match the
+    /// last component, so `ssh` finds `bin/ssh`.
+    Glob(String),
+    /// Searched for anywhere in the relative name; anchor with `^` and `$`.
+    Regex(Regex),
+}
+
+impl FileQuery {
+    /// `re:` starts a regex, anything else is a glob.
+    pub fn parse(query: &str) -> Result<Self> {
+        let query = query.trim();
+        match query.strip_prefix("re:") {
+            Some(re) => Ok(Self::Regex(
+                Regex::new(re).with_context(|| format!("Invalid regex {re}"))?,
+            )),
+            None => Ok(Self::Glob(query.trim_matches('/').to_string())),
+        }
+    }
+
+    pub fn matches(&self, file: &Path) -> bool {
+        let file = file.to_string_lossy();
+        match self {
+            Self::Glob(glob) if glob.contains('/') => glob_match(glob, &file),
+            Self::Glob(glob) => glob_match(glob, file.rsplit('/').next().unwrap_or(&file)),
+            Self::Regex(re) => re.is_match(&file),
+        }
+    }
+}
+
+/// The names of the files and symlinks in every path of the graph, walked
+/// once so each file search doesn't go back to the disk.
+#[derive(Debug, Clone, Default)]
+pub struct FileIndex {
+    /// In graph order, each sorted; `None` for paths that are not on disk.
+    names: Vec<Option<Vec<PathBuf>>>,
+}
+
+impl FileIndex {
+    pub fn new(graph: &StorePathGraph) -> Self {
+        let names = graph
+            .paths()
+            .iter()
+            .map(|p| file_names(Path::new(&p.path)).ok())
+            .collect();
+        Self { names }
+    }
+
+    /// How many paths could not be read.
+    pub fn unreadable(&self) -> usize {
+        self.names.iter().filter(|n| n.is_none()).count()
+    }
+
+    /// Files and symlinks whose names match `query`, by path in graph order
+    /// and sorted within one. A local `nix-locate` over just the loaded
+    /// closure.
+    pub fn find(&self, query: &FileQuery) -> Vec<(PathId, Vec<PathBuf>)> {
+        let mut found = Vec::new();
+        for (id, names) in (0..).zip(&self.names) {
+            let files: Vec<PathBuf> = names
+                .iter()
+                .flatten()
+                .filter(|file| query.matches(file))
+                .cloned()
+                .collect();
+            if !files.is_empty() {
+                found.push((id, files));
+            }
+        }
+        found
+    }
+}
+
+/// Every name below `root` that is not a directory, sorted; none if `root`
+/// is a single file.
+fn file_names(root: &Path) -> Result<Vec<PathBuf>> {
+    let mut names = Vec::new();
+    if !metadata(root)?.is_dir() {
+        return Ok(names);
+    }
+    let mut dirs = vec![PathBuf::new()];
+    while let Some(rel) = dirs.pop() {
+        let full = root.join(&rel);
+        let read_error = || format!("Cannot read {}", full.display());
+        for entry in fs::read_dir(&full).with_context(read_error)? {
+            let entry = entry.with_context(read_error)?;
+            let name = rel.join(entry.file_name());
+            if entry.file_type().with_context(read_error)?.is_dir() {
+                dirs.push(name);
+            } else {
+                names.push(name);
+            }
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store_path::testing::{TestGraph, path_in};
+
+    #[test]
+    fn sizes_like_du() {
+        let store = tempfile::tempdir().unwrap();
+        let p = store.path().join(format!("{:a<32}-pkg", ""));
+        fs::create_dir_all(p.join("share/doc")).unwrap();
+        fs::create_dir_all(p.join("bin")).unwrap();
+        fs::write(p.join("share/doc/big"), vec![1u8; 64 * 1024]).unwrap();
+        fs::write(p.join("bin/tool"), vec![1u8; 8 * 1024]).unwrap();
+        fs::hard_link(p.join("bin/tool"), p.join("bin/tool-link")).unwrap();
+        std::os::unix::fs::symlink("tool", p.join("bin/alias")).unwrap();
+
+        let tree = Tree::scan(&p).unwrap();
+        let names = |dir: &str| -> Vec<(String, Kind)> {
+            tree.entries(Path::new(dir))
+                .iter()
+                .map(|e| (e.name.clone(), e.kind))
+                .collect()
+        };
+        assert_eq!(
+            names(""),
+            [("share".into(), Kind::Dir), ("bin".into(), Kind::Dir)]
+        );
+        let bin = tree.entries(Path::new("bin"));
+        assert_eq!(bin[0].name, "tool");
+        let tool = bin[0].size;
+        assert!(tool >= 8 * 1024, "{tool}");
+        // The second name of a hardlinked file is free, as with du.
+        assert_eq!(bin.iter().find(|e| e.name == "tool-link").unwrap().size, 0);
+        assert_eq!(
+            bin.iter().find(|e| e.name == "alias").unwrap().kind,
+            Kind::Symlink
+        );
+        let share = tree.entries(Path::new(""))[0].size;
+        assert!(share >= 64 * 1024 + tool);
+        assert!(tree.size >= share + tool);
+        assert!(tree.entries(Path::new("bin/tool")).is_empty());
+
+        let dir = store.path().to_str().unwrap();
+        let mut graph = TestGraph::new(dir);
+        graph
+            .add(p.to_str().unwrap(), 0, &[])
+            .add(&path_in(dir, 'b', "gone"), 0, &[]);
+        let graph = graph.build(&[]);
+        let (files, unreadable) = largest(&graph, 2);
+        let found: Vec<&Path> = files.iter().map(|f| f.file.as_path()).collect();
+        assert_eq!(found, [Path::new("share/doc/big"), Path::new("bin/tool")]);
+        assert_eq!(unreadable, 1);
+        let lines = largest_lines(&graph, &files, unreadable);
+        assert_eq!(lines[0], "2 largest files in 2 paths (1 not on disk)");
+        assert!(lines[2].ends_with("  pkg/share/doc/big"), "{lines:#?}");
+    }
+
+    #[test]
+    fn finds_files_by_name() {
+        let query = |q: &str, file: &str| FileQuery::parse(q).unwrap().matches(Path::new(file));
+        assert!(query("ssh", "bin/ssh"));
+        assert!(!query("ssh", "bin/ssh-keygen"));
+        assert!(query("bin/ssh*", "bin/ssh-keygen"));
+        assert!(query("/lib/libz.so*", "lib/libz.so.1.3"));
+        assert!(!query("lib/libz.so*", "share/lib/libz.so"));
+        assert!(query(r"re:^lib/libz\.so(\.\d+)*$", "lib/libz.so.1"));
+        assert!(!query(r"re:^lib/libz\.so(\.\d+)*$", "lib/libz.so.1.a"));
+        assert!(query("re:python3", "lib/python3.12/os.py"));
+        assert!(FileQuery::parse("re:(").is_err());
+
+        let store = tempfile::tempdir().unwrap();
+        let dir = store.path().to_str().unwrap();
+        let mut graph = TestGraph::new(dir);
+        for (c, name, files) in [
+            ('a', "openssh", &["bin/ssh", "bin/scp"][..]),
+            ('b', "python3", &["bin/python3", "lib/libpython3.so"]),
+            ('c', "gone", &[]),
+        ] {
+            let path = PathBuf::from(path_in(dir, c, name));
+            for file in files {
+                fs::create_dir_all(path.join(file).parent().unwrap()).unwrap();
+                fs::write(path.join(file), "").unwrap();
+            }
+            if c == 'b' {
+                std::os::unix::fs::symlink("python3", path.join("bin/python")).unwrap();
+            }
+            graph.add(path.to_str().unwrap(), 0, &[]);
+        }
+        let graph = graph.build(&[]);
+        let index = FileIndex::new(&graph);
+        assert_eq!(index.unreadable(), 1);
+        let found = index.find(&FileQuery::parse("bin/*").unwrap());
+        let found: Vec<(PathId, Vec<&str>)> = found
+            .iter()
+            .map(|(id, files)| (*id, files.iter().map(|f| f.to_str().unwrap()).collect()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (0, vec!["bin/scp", "bin/ssh"]),
+                (1, vec!["bin/python", "bin/python3"]),
+            ]
+        );
+    }
+}
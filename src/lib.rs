@@ -1,8 +1,22 @@
+pub mod backend;
+pub mod binary_cache;
 pub mod cli;
 pub mod clipboard;
+pub mod closure_info;
+pub mod daemon;
+pub mod db;
 pub mod diff;
 pub mod dot;
+pub mod duplicates;
+pub mod elf;
+pub mod files;
 pub mod nix;
 pub mod path_stats;
+pub mod pattern;
+pub mod precise;
+pub mod snapshot;
+pub mod split;
 pub mod store_path;
 pub mod ui;
+pub mod what_if;
+pub mod why;
@@ -1,20 +1,16 @@
-mod cli;
-mod clipboard;
-mod diff;
-mod dot;
-mod nix;
-mod path_stats;
-mod store_path;
-mod ui;
-
 use anyhow::Result;
 use crossterm::{
     event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyEventKind},
     execute,
     terminal::{EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode},
 };
+use nix_tree::{
+    cli, closure_info, diff, dot, duplicates, nix, path_stats, pattern, snapshot, split,
+    store_path, ui, what_if, why,
+};
 use ratatui::{Terminal, backend::CrosstermBackend};
-use std::io;
+use std::io::{self, Write};
+use std::path::Path;
 use std::time::Duration;
 
 #[tokio::main]
@@ -31,59 +27,161 @@ async fn main() -> Result<()> {
         return Ok(());
     }
 
-    let mut paths = if config.paths.is_empty() {
+    let mut paths = if config.paths.is_empty() && config.graph_files.is_empty() {
         nix::get_default_roots().await?
     } else {
         config.paths
     };
 
-    // Resolve symlinks for paths outside the Nix store
-    for path in &mut paths {
-        if !path.starts_with("/nix/store/") {
-            if let Ok(resolved) = tokio::fs::canonicalize(&path).await {
-                *path = resolved.to_string_lossy().to_string();
-            }
-        }
-    }
-
-    let opts = nix::QueryOptions {
+    let mut opts = nix::QueryOptions {
         store: config.store,
         nix_options: config.nix_options,
         file: config.file,
         derivation: config.derivation,
         impure: config.impure,
+        store_dir: config
+            .store_dir
+            .or_else(|| std::env::var("NIX_STORE_DIR").ok())
+            .map(|dir| dir.trim_end_matches('/').to_string()),
+        backend: None,
     };
 
+    // Resolve symlinks for paths outside the Nix store. why's <to> names a
+    // path in the closure, so it stays as typed.
+    let installables = if config.why { 1 } else { paths.len() };
+    for path in paths.iter_mut().take(installables) {
+        if nix::store_path_of(path, opts.store_dir()).is_none()
+            && let Ok(resolved) = tokio::fs::canonicalize(&path).await
+        {
+            *path = resolved.to_string_lossy().to_string();
+        }
+    }
+
+    opts.backend = config.backend.open(&opts)?;
+
     if config.diff {
-        let [old, new] = paths.as_slice() else {
-            anyhow::bail!("--diff requires exactly two installables");
+        if config.graph_files.len() + paths.len() != 2 {
+            anyhow::bail!("--diff requires exactly two installables or graph files");
+        }
+        let mut graphs = Vec::with_capacity(2);
+        for file in &config.graph_files {
+            graphs.push(load_graph_file(file)?);
+        }
+        for path in &paths {
+            graphs.push(nix::query_path_info(std::slice::from_ref(path), true, &opts).await?);
+        }
+        diff::write(&graphs[0], &graphs[1], &mut io::stdout().lock())?;
+        return Ok(());
+    }
+
+    if config.why {
+        let [from, to] = &paths[..] else {
+            anyhow::bail!("why requires exactly two arguments: <from> <to>");
+        };
+        let graph = match &config.graph_files[..] {
+            [] => nix::query_path_info(std::slice::from_ref(from), true, &opts).await?,
+            [file] => load_graph_file(file)?,
+            _ => anyhow::bail!("why takes at most one graph file"),
         };
-        let old = nix::query_path_info(std::slice::from_ref(old), true, &opts).await?;
-        let new = nix::query_path_info(std::slice::from_ref(new), true, &opts).await?;
-        diff::write(&old, &new, &mut io::stdout().lock())?;
+        let from = match (&config.graph_files[..], &graph.root_ids()[..]) {
+            // The installable's only output, whatever it was spelled as.
+            ([], &[root]) => root,
+            ([], roots) => match why::resolve(&graph, from) {
+                Ok(id) if roots.contains(&id) => id,
+                _ => {
+                    let outputs: Vec<&str> =
+                        roots.iter().map(|&r| graph.path(r).path.as_str()).collect();
+                    anyhow::bail!(
+                        "'{from}' has several outputs, use one of:\n  {}",
+                        outputs.join("\n  ")
+                    )
+                }
+            },
+            _ => why::resolve(&graph, from)?,
+        };
+        let to = why::resolve(&graph, to)?;
+        let limit = match config.limit {
+            _ if config.all => usize::MAX,
+            Some(limit) => limit,
+            None => why::LIMIT,
+        };
+        why::write(&graph, from, to, limit, &mut io::stdout().lock())?;
+        return Ok(());
+    }
+
+    let graph = if let Some(file) = config.graph_files.first() {
+        if config.graph_files.len() > 1 || !paths.is_empty() {
+            anyhow::bail!("Only one graph file and no installables can be browsed without --diff");
+        }
+        load_graph_file(file)?
+    } else {
+        let report = config.dot
+            || config.duplicates
+            || config.split
+            || config.save_snapshot.is_some()
+            || !config.what_if.is_empty();
+        if !report {
+            println!("Loading store paths...");
+        }
+        nix::query_path_info(&paths, true, &opts).await?
+    };
+
+    if let Some(file) = &config.save_snapshot {
+        snapshot::save(&graph, Path::new(file))?;
+        eprintln!("Saved {} paths to {file}", graph.paths().len());
+        return Ok(());
+    }
+
+    if !config.what_if.is_empty() {
+        let cut = pattern::select(&graph, &config.what_if);
+        if cut.is_empty() {
+            anyhow::bail!("No paths in the closure match the --what-if patterns");
+        }
+        let report = path_stats::WhatIf::new(&graph, &cut);
+        what_if::write(&graph, &report, &mut io::stdout().lock())?;
         return Ok(());
     }
 
-    if !config.dot {
-        println!("Loading store paths...");
+    if config.split {
+        let mut out = io::stdout().lock();
+        for (i, root) in graph.root_ids().into_iter().enumerate() {
+            if i > 0 {
+                writeln!(out)?;
+            }
+            let advice = split::Advice::new(&graph, root)?;
+            split::write(&graph, &advice, &mut out)?;
+        }
+        return Ok(());
     }
-    let graph = nix::query_path_info(&paths, true, &opts).await?;
 
     if config.dot {
         dot::write(&graph, &mut io::stdout().lock())?;
         return Ok(());
     }
 
-    println!("Calculating sizes...");
-    let stats = path_stats::calculate_stats(&graph);
+    if !config.duplicates {
+        println!("Calculating sizes...");
+    }
+    let jobs = config.jobs.unwrap_or_else(path_stats::default_jobs);
+    let stats = path_stats::calculate_stats_with_jobs(&graph, jobs);
+
+    if config.duplicates {
+        let found = duplicates::find(&graph, &stats);
+        duplicates::write(&graph, &found, &mut io::stdout().lock())?;
+        return Ok(());
+    }
 
     run_tui(graph, stats).await
 }
 
-async fn run_tui(
-    graph: store_path::StorePathGraph,
-    stats: std::collections::HashMap<String, path_stats::PathStats>,
-) -> Result<()> {
+fn load_graph_file(file: &cli::GraphFile) -> Result<store_path::StorePathGraph> {
+    match file {
+        cli::GraphFile::Snapshot(f) => snapshot::load(Path::new(f)),
+        cli::GraphFile::ClosureInfo(f) => closure_info::load(Path::new(f)),
+    }
+}
+
+async fn run_tui(graph: store_path::StorePathGraph, stats: path_stats::Stats) -> Result<()> {
     enable_raw_mode()?;
     let mut stdout = io::stdout();
     execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
@@ -106,12 +204,16 @@ async fn run_tui(
 async fn run_app(
     terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
     graph: store_path::StorePathGraph,
-    stats: std::collections::HashMap<String, path_stats::PathStats>,
+    stats: path_stats::Stats,
 ) -> Result<()> {
     let mut app = ui::App::new(graph, stats);
     let mut needs_render = true;
 
     loop {
+        if app.poll_background() {
+            needs_render = true;
+        }
+
         // Only render when needed
         if needs_render {
             terminal.draw(|f| ui::render_frame(f, &app))?;
@@ -1,16 +1,129 @@
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 use serde::Deserialize;
+use serde_json::Value;
 use std::process::Stdio;
+use std::sync::Arc;
 use tokio::process::Command;
 
-use crate::store_path::{StorePath, StorePathGraph};
+use crate::backend::{self, BoxFuture, StoreBackend};
+use crate::store_path::{DEFAULT_STORE_DIR, StorePath, StorePathGraph};
 
+/// One entry of `nix path-info --json`. Fields whose type changed between
+/// versions are kept as raw JSON and flattened in `into_store_path`.
 #[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct NixPathInfo {
-    #[serde(rename = "narSize")]
-    nar_size: u64,
+    /// Only in the list schema; the map schema uses it as the key.
+    path: Option<String>,
+    /// `false` marks a missing path in the list schema.
+    valid: Option<bool>,
+    /// Absent only for invalid paths; a valid one without it is an error
+    /// rather than a path of size 0.
+    nar_size: Option<u64>,
+    #[serde(default)]
     references: Vec<String>,
     signatures: Option<Vec<String>>,
+    deriver: Option<String>,
+    registration_time: Option<u64>,
+    nar_hash: Option<Value>,
+    ca: Option<Value>,
+    ultimate: Option<bool>,
+    download_size: Option<u64>,
+    closure_download_size: Option<u64>,
+}
+
+impl NixPathInfo {
+    fn into_store_path(self, path: String, store_dir: &str) -> Result<StorePath> {
+        let (hash, name) = StorePath::parse(&path)?;
+        let nar_size = self
+            .nar_size
+            .with_context(|| format!("nix path-info gave no narSize for '{path}'"))?;
+        Ok(StorePath {
+            path,
+            hash,
+            name,
+            nar_size,
+            references: self
+                .references
+                .iter()
+                .map(|r| in_store_dir(r, store_dir))
+                .collect(),
+            signatures: self.signatures.unwrap_or_default(),
+            deriver: self.deriver.map(|d| in_store_dir(&d, store_dir)),
+            registration_time: self.registration_time,
+            nar_hash: self.nar_hash.and_then(json_string),
+            ca: self.ca.and_then(json_string),
+            ultimate: self.ultimate,
+            download_size: self.download_size,
+            closure_download_size: self.closure_download_size,
+        })
+    }
+}
+
+/// Layouts `nix path-info --json` has used for its top level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PathInfoSchema {
+    /// Nix before 2.19, and Lix: `[{"path": …, …}]`, with
+    /// `{"path": …, "valid": false}` for paths not in the store.
+    List,
+    /// Nix 2.19 and later: `{"<path>": {…}}`, with `null` for paths not in
+    /// the store. Some versions print keys and references without the store
+    /// directory.
+    Map,
+}
+
+impl PathInfoSchema {
+    fn detect(json: &Value) -> Result<Self> {
+        match json {
+            Value::Array(_) => Ok(PathInfoSchema::List),
+            Value::Object(_) => Ok(PathInfoSchema::Map),
+            _ => bail!("Unrecognised nix path-info JSON: expected an array or an object"),
+        }
+    }
+}
+
+/// `(path, info)` for every entry of `nix path-info --json` output, whichever
+/// schema it is in, with `None` for paths that are not valid. Paths come back
+/// absolute even when nix printed bare `<hash>-<name>`s.
+fn parse_path_info(json: &[u8], store_dir: &str) -> Result<Vec<(String, Option<NixPathInfo>)>> {
+    let value: Value =
+        serde_json::from_slice(json).context("Failed to parse nix path-info JSON")?;
+    let entries = match PathInfoSchema::detect(&value)? {
+        PathInfoSchema::List => Vec::<NixPathInfo>::deserialize(value)?
+            .into_iter()
+            .map(|info| {
+                let path = info.path.clone().context("path-info entry has no path")?;
+                let valid = info.valid != Some(false);
+                Ok((path, valid.then_some(info)))
+            })
+            .collect::<Result<Vec<_>>>()?,
+        PathInfoSchema::Map => serde_json::Map::<String, Value>::deserialize(value)?
+            .into_iter()
+            .map(|(path, info)| Ok((path, Option::<NixPathInfo>::deserialize(info)?)))
+            .collect::<Result<Vec<_>>>()?,
+    };
+    Ok(entries
+        .into_iter()
+        .map(|(path, info)| (in_store_dir(&path, store_dir), info))
+        .collect())
+}
+
+fn in_store_dir(path: &str, store_dir: &str) -> String {
+    if path.starts_with('/') {
+        path.to_string()
+    } else {
+        format!("{store_dir}/{path}")
+    }
+}
+
+/// Hashes and content addresses are strings in most versions and structured
+/// objects in some; the latter are kept as their JSON text.
+fn json_string(value: Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::String(s) => Some(s),
+        other => Some(other.to_string()),
+    }
 }
 
 #[derive(Debug, Default, Clone)]
@@ -20,6 +133,17 @@ pub struct QueryOptions {
     pub file: Option<String>,
     pub derivation: bool,
     pub impure: bool,
+    /// Store directory installables are recognised in; `None` means
+    /// `/nix/store`. Graphs still take theirs from the paths they load.
+    pub store_dir: Option<String>,
+    /// Where path info is loaded from; `None` picks `backend::auto`.
+    pub backend: Option<Arc<dyn StoreBackend>>,
+}
+
+impl QueryOptions {
+    pub fn store_dir(&self) -> &str {
+        self.store_dir.as_deref().unwrap_or(DEFAULT_STORE_DIR)
+    }
 }
 
 /// `--file`/`--derivation` are subcommand flags, so this builds up to and
@@ -51,26 +175,28 @@ fn path_info_cmd(opts: &QueryOptions) -> Command {
     cmd
 }
 
-async fn run_path_info<T: serde::de::DeserializeOwned>(mut cmd: Command) -> Result<T> {
+async fn run_path_info(
+    mut cmd: Command,
+    opts: &QueryOptions,
+) -> Result<Vec<(String, Option<NixPathInfo>)>> {
     let output = cmd.output().await.context("Failed to run nix path-info")?;
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
         anyhow::bail!("nix path-info failed: {stderr}");
     }
-    serde_json::from_slice(&output.stdout).context("Failed to parse nix path-info JSON")
+    parse_path_info(&output.stdout, opts.store_dir())
 }
 
 async fn resolve_paths(paths: &[String], opts: &QueryOptions) -> Result<Vec<String>> {
     let mut cmd = path_info_cmd(opts);
     cmd.args(paths);
 
-    // For paths not in the store nix emits `"<path>": null` with exit 0, so
-    // the value side must tolerate null instead of forcing NixPathInfo.
-    let map: std::collections::HashMap<String, Option<serde_json::Value>> =
-        run_path_info(cmd).await?;
+    // For paths not in the store nix still exits 0, marking them as missing
+    // in the JSON instead.
+    let entries = run_path_info(cmd, opts).await?;
 
-    let mut resolved = Vec::with_capacity(map.len());
-    for (path, info) in map {
+    let mut resolved = Vec::with_capacity(entries.len());
+    for (path, info) in entries {
         if info.is_none() {
             anyhow::bail!(
                 "'{path}' is not in the store (garbage-collected, or pass --derivation for unbuilt outputs)"
@@ -81,15 +207,46 @@ async fn resolve_paths(paths: &[String], opts: &QueryOptions) -> Result<Vec<Stri
     Ok(resolved)
 }
 
-pub async fn query_path_info(
+/// Shells out to `nix path-info`; the only backend that can evaluate flake
+/// references and `--file` expressions, and works for any `--store` nix does.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CliBackend;
+
+impl StoreBackend for CliBackend {
+    fn resolve<'a>(
+        &'a self,
+        installables: &'a [String],
+        opts: &'a QueryOptions,
+    ) -> BoxFuture<'a, Result<Vec<String>>> {
+        Box::pin(resolve_paths(installables, opts))
+    }
+
+    fn path_info<'a>(
+        &'a self,
+        paths: &'a [String],
+        opts: &'a QueryOptions,
+    ) -> BoxFuture<'a, Result<Vec<StorePath>>> {
+        Box::pin(cli_path_info(paths, false, opts))
+    }
+
+    fn closure<'a>(
+        &'a self,
+        paths: &'a [String],
+        opts: &'a QueryOptions,
+    ) -> BoxFuture<'a, Result<Vec<StorePath>>> {
+        Box::pin(cli_path_info(paths, true, opts))
+    }
+}
+
+async fn cli_path_info(
     paths: &[String],
     recursive: bool,
     opts: &QueryOptions,
-) -> Result<StorePathGraph> {
-    // First resolve any flake references to store paths
-    let resolved_paths = resolve_paths(paths, opts).await?;
-
-    // resolved_paths are store paths; --file would misinterpret them as attrs.
+) -> Result<Vec<StorePath>> {
+    if paths.is_empty() {
+        return Ok(Vec::new());
+    }
+    // paths are store paths; --file would misinterpret them as attrs.
     let mut cmd = path_info_cmd(&QueryOptions {
         file: None,
         ..opts.clone()
@@ -97,32 +254,63 @@ pub async fn query_path_info(
     if recursive {
         cmd.arg("--recursive");
     }
-    cmd.args(&resolved_paths);
+    cmd.args(paths);
 
-    let path_info_map: std::collections::HashMap<String, NixPathInfo> = run_path_info(cmd).await?;
+    let entries = run_path_info(cmd, opts).await?;
 
-    let mut graph = StorePathGraph::new();
+    let mut store_paths = Vec::with_capacity(entries.len());
+    for (path, info) in entries {
+        let Some(info) = info else {
+            bail!("'{path}' is not in the store");
+        };
+        store_paths.push(info.into_store_path(path, opts.store_dir())?);
+    }
+    Ok(store_paths)
+}
 
-    for (path, info) in path_info_map {
-        let (hash, name) = StorePath::parse(&path)?;
+pub async fn query_path_info(
+    paths: &[String],
+    recursive: bool,
+    opts: &QueryOptions,
+) -> Result<StorePathGraph> {
+    let backend = match &opts.backend {
+        Some(backend) => backend.clone(),
+        None => backend::auto(opts),
+    };
 
-        let store_path = StorePath {
-            path: path.clone(),
-            hash,
-            name,
-            nar_size: info.nar_size,
-            references: info.references,
-            signatures: info.signatures.unwrap_or_default(),
-        };
+    // First resolve any flake references to store paths
+    let resolved_paths = backend.resolve(paths, opts).await?;
 
-        graph.add_path(store_path);
-    }
+    let store_paths = if recursive {
+        backend.closure(&resolved_paths, opts).await?
+    } else {
+        backend.path_info(&resolved_paths, opts).await?
+    };
 
     // Use the resolved paths as roots
-    graph.roots = resolved_paths;
-    graph.disambiguate_names();
+    Ok(build_graph(store_paths, resolved_paths, opts.store_dir()))
+}
 
-    Ok(graph)
+/// The top-level store path for an installable that is already a store path
+/// or a file inside one, e.g. `<store_dir>/<hash>-foo/bin/foo`.
+pub fn store_path_of(installable: &str, store_dir: &str) -> Option<String> {
+    let rest = installable.strip_prefix(store_dir)?.strip_prefix('/')?;
+    let base = rest.split('/').next()?;
+    let path = format!("{store_dir}/{base}");
+    StorePath::parse_in(&path, store_dir).ok()?;
+    Some(path)
+}
+
+fn build_graph(store_paths: Vec<StorePath>, roots: Vec<String>, store_dir: &str) -> StorePathGraph {
+    let mut graph = StorePathGraph::new();
+    for store_path in store_paths {
+        graph.add_path(store_path);
+    }
+    graph.roots = roots;
+    graph.store_dir = store_dir.to_string();
+    graph.detect_store_dir();
+    graph.disambiguate_names();
+    graph
 }
 
 pub async fn get_default_roots() -> Result<Vec<String>> {
@@ -146,3 +334,107 @@ pub async fn get_default_roots() -> Result<Vec<String>> {
 
     Ok(roots)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{PathInfoSchema, parse_path_info};
+
+    const HELLO: &str = "/nix/store/0c0c9a2pbhvmb0bnzdb1bp6p5aj6ph4w-hello-2.12.1";
+    const GLIBC: &str = "/nix/store/1rz4g4znpzjwh1xymhjpm42vipw92pr7-glibc-2.40-66";
+    const MISSING: &str = "/nix/store/3qbh4vzqgmwf1kh5m5kjgmggr2x3jbyd-missing";
+
+    fn fixture(name: &str) -> Vec<u8> {
+        let path = format!(
+            "{}/tests/fixtures/path-info/{name}",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        std::fs::read(path).unwrap()
+    }
+
+    /// Every fixture describes the same store: hello → glibc (→ glibc), plus
+    /// one path that is not valid. They are hand-written, not captured; see
+    /// tests/fixtures/path-info/README.md.
+    fn check(name: &str, schema: PathInfoSchema) {
+        let json = fixture(name);
+        let value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(PathInfoSchema::detect(&value).unwrap(), schema, "{name}");
+
+        let mut entries = parse_path_info(&json, "/nix/store").unwrap();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        let paths: Vec<&str> = entries.iter().map(|(p, _)| p.as_str()).collect();
+        assert_eq!(paths, [HELLO, GLIBC, MISSING], "{name}");
+        assert!(entries[2].1.is_none(), "{name}");
+
+        let mut infos = entries
+            .into_iter()
+            .filter_map(|(path, info)| Some(info?.into_store_path(path, "/nix/store").unwrap()));
+        let hello = infos.next().unwrap();
+        assert_eq!(hello.name, "hello-2.12.1", "{name}");
+        assert_eq!(hello.nar_size, 226560, "{name}");
+        assert_eq!(hello.references, [GLIBC, HELLO], "{name}");
+        assert_eq!(hello.registration_time, Some(1700000000), "{name}");
+        assert!(
+            hello
+                .deriver
+                .as_deref()
+                .is_some_and(|d| d.starts_with("/nix/store/") && d.ends_with(".drv")),
+            "{name}"
+        );
+        assert!(hello.nar_hash.unwrap().starts_with("sha256"), "{name}");
+
+        let glibc = infos.next().unwrap();
+        assert_eq!(glibc.references, [GLIBC], "{name}");
+        assert_eq!(glibc.signatures.len(), 1, "{name}");
+    }
+
+    #[test]
+    fn nix_2_18_list() {
+        check("nix-2.18.json", PathInfoSchema::List);
+
+        // It does not say whether a path was built or substituted.
+        let entries = parse_path_info(&fixture("nix-2.18.json"), "/nix/store").unwrap();
+        assert!(
+            entries
+                .iter()
+                .flat_map(|(_, i)| i)
+                .all(|i| i.ultimate.is_none())
+        );
+    }
+
+    #[test]
+    fn nix_2_24_map() {
+        check("nix-2.24.json", PathInfoSchema::Map);
+    }
+
+    #[test]
+    fn lix_list() {
+        check("lix-2.91.json", PathInfoSchema::List);
+    }
+
+    #[test]
+    fn store_dir_less_map() {
+        check("store-dir-less.json", PathInfoSchema::Map);
+
+        let entries = parse_path_info(&fixture("store-dir-less.json"), "/nix/store").unwrap();
+        let (path, info) = entries.into_iter().find(|(p, _)| p == HELLO).unwrap();
+        let hello = info.unwrap().into_store_path(path, "/nix/store").unwrap();
+        assert_eq!(hello.ultimate, Some(true));
+        assert!(hello.ca.unwrap().contains(r#""method":"nar""#));
+    }
+
+    #[test]
+    fn rejects_other_json() {
+        let err = parse_path_info(b"42", "/nix/store").unwrap_err();
+        assert!(err.to_string().contains("Unrecognised"), "{err}");
+        assert!(parse_path_info(b"[{\"narSize\": 1}]", "/nix/store").is_err());
+
+        let json = format!(r#"{{"{HELLO}": {{"references": []}}}}"#);
+        let entries = parse_path_info(json.as_bytes(), "/nix/store").unwrap();
+        let (path, info) = entries.into_iter().next().unwrap();
+        let err = info
+            .unwrap()
+            .into_store_path(path, "/nix/store")
+            .unwrap_err();
+        assert!(err.to_string().contains("no narSize"), "{err}");
+    }
+}
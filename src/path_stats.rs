@@ -1,5 +1,8 @@
-use crate::store_path::StorePathGraph;
-use std::collections::{HashMap, HashSet};
+use crate::store_path::{PathId, StorePathGraph};
+use std::collections::HashMap;
+use std::ops::Index;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 /// Adjacency list over dense integer ids so closure walks avoid hashing
 /// 90-byte store-path strings on every edge.
@@ -10,20 +13,12 @@ pub struct IndexedGraph {
 
 impl IndexedGraph {
     pub fn new(graph: &StorePathGraph) -> Self {
-        let n = graph.paths.len();
-        let mut nar_size = Vec::with_capacity(n);
-        let mut refs = Vec::with_capacity(n);
-        for p in &graph.paths {
-            nar_size.push(p.nar_size);
-            refs.push(
-                p.references
-                    .iter()
-                    .filter_map(|r| graph.index_of(r))
-                    .map(|i| i as u32)
-                    .collect(),
-            );
+        Self {
+            nar_size: graph.paths().iter().map(|p| p.nar_size).collect(),
+            refs: (0..graph.paths().len() as PathId)
+                .map(|id| graph.references_of(id).to_vec())
+                .collect(),
         }
-        Self { nar_size, refs }
     }
 
     /// `seen[i] == generation` marks visited; bumping `generation` resets in O(1).
@@ -50,12 +45,63 @@ impl IndexedGraph {
         size
     }
 
-    /// Walk from `roots` summing nar sizes, optionally never entering `skip`
-    /// so the result is the closure that would remain if `skip` (and
-    /// everything only it kept alive) were removed.
-    fn closure_size_from(&self, roots: &[u32], skip: Option<u32>, seen: &mut [bool]) -> u64 {
+    /// `closure_size` of each of `ids`, spread over `jobs` threads. Ids are
+    /// handed out in blocks from a shared counter, so a thread that drew a run
+    /// of huge closures does not hold the others up; each thread owns its
+    /// `seen`/`stack` buffers, so the walks never contend.
+    fn closure_sizes(&self, ids: &[u32], jobs: usize) -> Vec<u64> {
+        const BLOCK: usize = 256;
+        let n = ids.len();
+        let next = AtomicUsize::new(0);
+        let work = || {
+            let mut seen = vec![0u32; self.nar_size.len()];
+            let mut stack = Vec::new();
+            let mut generation = 0u32;
+            let mut blocks = Vec::new();
+            loop {
+                let start = next.fetch_add(BLOCK, Ordering::Relaxed);
+                if start >= n {
+                    break;
+                }
+                let sizes: Vec<u64> = ids[start..(start + BLOCK).min(n)]
+                    .iter()
+                    .map(|&i| {
+                        generation += 1;
+                        self.closure_size(i, &mut seen, generation, &mut stack)
+                    })
+                    .collect();
+                blocks.push((start, sizes));
+            }
+            blocks
+        };
+
+        let jobs = jobs.clamp(1, n.div_ceil(BLOCK).max(1));
+        let blocks = if jobs == 1 {
+            work()
+        } else {
+            std::thread::scope(|s| {
+                let workers: Vec<_> = (0..jobs).map(|_| s.spawn(work)).collect();
+                workers
+                    .into_iter()
+                    .flat_map(|w| w.join().expect("closure size worker panicked"))
+                    .collect()
+            })
+        };
+
+        let mut sizes = vec![0u64; n];
+        for (start, block) in blocks {
+            sizes[start..start + block.len()].copy_from_slice(&block);
+        }
+        sizes
+    }
+
+    /// Walk from `roots` summing nar sizes, never entering `skip`, so the
+    /// result is the closure that would remain if `skip` (and everything only
+    /// it kept alive) were removed. Afterwards `seen` holds that closure plus
+    /// `skip`.
+    fn closure_size_from(&self, roots: &[u32], skip: &[u32], seen: &mut [bool]) -> u64 {
         seen.fill(false);
-        if let Some(s) = skip {
+        for &s in skip {
             seen[s as usize] = true;
         }
         let mut stack = Vec::with_capacity(roots.len());
@@ -79,6 +125,226 @@ impl IndexedGraph {
     }
 }
 
+/// Strongly connected components of the reference graph and the DAG they
+/// condense to. Every member of a cycle has the same closure, and a component
+/// with at most one outgoing edge in the DAG can add its own size to its
+/// successor's closure without any overlap, so closure sizes are only walked
+/// for components that fan out, and then over the (smaller) DAG.
+pub struct Components {
+    /// Component of every path index.
+    comp: Vec<u32>,
+    /// Members per component, in reverse topological order: every component
+    /// only refers to components before it.
+    members: Vec<Vec<u32>>,
+    /// One node per component, carrying the members' summed nar size.
+    dag: IndexedGraph,
+}
+
+impl Components {
+    pub fn new(graph: &StorePathGraph) -> Self {
+        Self::from_indexed(&IndexedGraph::new(graph))
+    }
+
+    /// Tarjan's algorithm with an explicit call stack.
+    fn from_indexed(idx: &IndexedGraph) -> Self {
+        let n = idx.nar_size.len();
+        let mut index = vec![NONE; n];
+        let mut low = vec![0u32; n];
+        let mut on_stack = vec![false; n];
+        let mut stack = Vec::new();
+        let mut call: Vec<(u32, usize)> = Vec::new();
+        let mut counter = 0u32;
+        let mut comp = vec![NONE; n];
+        let mut members: Vec<Vec<u32>> = Vec::new();
+
+        for start in 0..n as u32 {
+            if index[start as usize] != NONE {
+                continue;
+            }
+            index[start as usize] = counter;
+            low[start as usize] = counter;
+            counter += 1;
+            stack.push(start);
+            on_stack[start as usize] = true;
+            call.push((start, 0));
+
+            while let Some((v, edge)) = call.last_mut() {
+                let v = *v;
+                if let Some(&w) = idx.refs[v as usize].get(*edge) {
+                    *edge += 1;
+                    if index[w as usize] == NONE {
+                        index[w as usize] = counter;
+                        low[w as usize] = counter;
+                        counter += 1;
+                        stack.push(w);
+                        on_stack[w as usize] = true;
+                        call.push((w, 0));
+                    } else if on_stack[w as usize] {
+                        low[v as usize] = low[v as usize].min(index[w as usize]);
+                    }
+                    continue;
+                }
+
+                call.pop();
+                if let Some(&(u, _)) = call.last() {
+                    low[u as usize] = low[u as usize].min(low[v as usize]);
+                }
+                if low[v as usize] == index[v as usize] {
+                    let id = members.len() as u32;
+                    let mut component = Vec::new();
+                    while let Some(w) = stack.pop() {
+                        on_stack[w as usize] = false;
+                        comp[w as usize] = id;
+                        component.push(w);
+                        if w == v {
+                            break;
+                        }
+                    }
+                    members.push(component);
+                }
+            }
+        }
+
+        let dag = IndexedGraph {
+            nar_size: members
+                .iter()
+                .map(|m| m.iter().map(|&v| idx.nar_size[v as usize]).sum())
+                .collect(),
+            refs: members
+                .iter()
+                .enumerate()
+                .map(|(c, m)| {
+                    let mut refs: Vec<u32> = m
+                        .iter()
+                        .flat_map(|&v| &idx.refs[v as usize])
+                        .map(|&w| comp[w as usize])
+                        .filter(|&d| d != c as u32)
+                        .collect();
+                    refs.sort_unstable();
+                    refs.dedup();
+                    refs
+                })
+                .collect(),
+        };
+
+        Self { comp, members, dag }
+    }
+
+    /// Closure size of every path, by component: fan-out components are
+    /// walked over the DAG (in parallel), then chains and leaves are filled in
+    /// sinks-first from what their single successor already has.
+    fn closure_sizes(&self, jobs: usize) -> Vec<u64> {
+        let k = self.members.len();
+        let wide: Vec<u32> = (0..k as u32)
+            .filter(|&c| self.dag.refs[c as usize].len() > 1)
+            .collect();
+        let mut sizes = vec![0u64; k];
+        for (&c, size) in wide.iter().zip(self.dag.closure_sizes(&wide, jobs)) {
+            sizes[c as usize] = size;
+        }
+        for c in 0..k {
+            match self.dag.refs[c].as_slice() {
+                [] => sizes[c] = self.dag.nar_size[c],
+                [next] => sizes[c] = self.dag.nar_size[c] + sizes[*next as usize],
+                _ => {}
+            }
+        }
+        self.comp.iter().map(|&c| sizes[c as usize]).collect()
+    }
+
+    /// Depth and height of every path, counted in components so a cycle is
+    /// one step and `min_depth <= max_depth` holds throughout.
+    fn layers(&self, roots: &[u32]) -> Layers {
+        let k = self.members.len();
+        let mut min_depth = vec![NONE; k];
+        let mut max_depth = vec![NONE; k];
+        let mut queue = std::collections::VecDeque::new();
+        for &r in roots {
+            let c = self.comp[r as usize];
+            if min_depth[c as usize] == NONE {
+                min_depth[c as usize] = 0;
+                max_depth[c as usize] = 0;
+                queue.push_back(c);
+            }
+        }
+        while let Some(c) = queue.pop_front() {
+            for &d in &self.dag.refs[c as usize] {
+                if min_depth[d as usize] == NONE {
+                    min_depth[d as usize] = min_depth[c as usize] + 1;
+                    queue.push_back(d);
+                }
+            }
+        }
+        // Referrers come after what they refer to, so walking components
+        // backwards settles every component before its references.
+        for c in (0..k).rev() {
+            if max_depth[c] == NONE {
+                continue;
+            }
+            for &d in &self.dag.refs[c] {
+                let d = d as usize;
+                if max_depth[d] == NONE || max_depth[d] < max_depth[c] + 1 {
+                    max_depth[d] = max_depth[c] + 1;
+                }
+            }
+        }
+        let mut height = vec![0u32; k];
+        for c in 0..k {
+            height[c] = self.dag.refs[c]
+                .iter()
+                .map(|&d| height[d as usize] + 1)
+                .max()
+                .unwrap_or(0);
+        }
+
+        let per_path = |v: &[u32]| -> Vec<Option<u32>> {
+            self.comp
+                .iter()
+                .map(|&c| Some(v[c as usize]).filter(|&d| d != NONE))
+                .collect()
+        };
+        Layers {
+            min_depth: per_path(&min_depth),
+            max_depth: per_path(&max_depth),
+            height: self.comp.iter().map(|&c| height[c as usize]).collect(),
+        }
+    }
+
+    /// Every reference cycle (component with more than one path), members in
+    /// graph order. Self-references do not count; nix records those for
+    /// almost every path.
+    pub fn cycles(&self) -> Vec<Vec<PathId>> {
+        self.members
+            .iter()
+            .filter(|m| m.len() > 1)
+            .map(|m| {
+                let mut m = m.clone();
+                m.sort_unstable();
+                m
+            })
+            .collect()
+    }
+}
+
+/// How deep each path sits below the roots and how deep the references below
+/// it go, per path index. Depths are `None` for paths no root reaches.
+pub struct Layers {
+    /// Fewest references from a root to the path.
+    pub min_depth: Vec<Option<u32>>,
+    /// Most references from a root to the path's strongly connected
+    /// component; a reference between components always goes from a smaller
+    /// max depth to a larger one, and the members of a cycle share one.
+    pub max_depth: Vec<Option<u32>>,
+    /// Longest chain of references below the path.
+    pub height: Vec<u32>,
+}
+
+impl Layers {
+    pub fn new(graph: &StorePathGraph) -> Self {
+        Components::new(graph).layers(&graph.root_ids())
+    }
+}
+
 /// Reusable buffers + cached context closure for added-size queries from the
 /// status bar, so scrolling within one parent only pays one full walk.
 pub struct AddedSize {
@@ -91,7 +357,7 @@ pub struct AddedSize {
 impl AddedSize {
     pub fn new(graph: &StorePathGraph) -> Self {
         let idx = IndexedGraph::new(graph);
-        let seen = vec![false; graph.paths.len()];
+        let seen = vec![false; graph.paths().len()];
         Self {
             idx,
             seen,
@@ -102,66 +368,376 @@ impl AddedSize {
 
     /// Added sizes for every `item` relative to `context`, computed in one pass
     /// so the cached context total is reused across the batch.
-    pub fn for_items(
-        &mut self,
-        graph: &StorePathGraph,
-        items: &[String],
-        context: &[String],
-    ) -> HashMap<String, u64> {
+    pub fn for_items(&mut self, items: &[PathId], context: &[PathId]) -> HashMap<PathId, u64> {
         items
             .iter()
-            .map(|p| (p.clone(), self.for_path(graph, p, context)))
+            .map(|&id| (id, self.for_path(id, context)))
             .collect()
     }
 
-    pub fn for_path(&mut self, graph: &StorePathGraph, path: &str, context: &[String]) -> u64 {
-        let roots: Vec<u32> = context
+    pub fn for_path(&mut self, id: PathId, context: &[PathId]) -> u64 {
+        if context != self.context_roots {
+            self.context_total = self.idx.closure_size_from(context, &[], &mut self.seen);
+            self.context_roots = context.to_vec();
+        }
+        let without = self
+            .idx
+            .closure_size_from(&self.context_roots, &[id], &mut self.seen);
+        self.context_total.saturating_sub(without)
+    }
+}
+
+/// What cutting a set of paths at once would do to the root closures: every
+/// reference to a cut path is dropped, so it and everything only it kept
+/// alive disappear.
+#[derive(Debug, Clone, Default)]
+pub struct WhatIf {
+    /// The cut paths, largest first.
+    pub cut: Vec<PathId>,
+    /// Each root's closure size before and after the cut.
+    pub roots: Vec<(PathId, u64, u64)>,
+    /// Size of all root closures together, before and after.
+    pub before: u64,
+    pub after: u64,
+    /// Paths that were reachable from a root and no longer are, not counting
+    /// the cut ones, largest first.
+    pub unreachable: Vec<PathId>,
+}
+
+impl WhatIf {
+    pub fn new(graph: &StorePathGraph, cut: &[PathId]) -> Self {
+        let idx = IndexedGraph::new(graph);
+        let n = graph.paths().len();
+        let skip = cut.to_vec();
+        let roots = graph.root_ids();
+
+        let mut reachable = vec![false; n];
+        let mut remaining = vec![false; n];
+        let before = idx.closure_size_from(&roots, &[], &mut reachable);
+        let after = idx.closure_size_from(&roots, &skip, &mut remaining);
+        let mut is_cut = vec![false; n];
+        for &s in &skip {
+            is_cut[s as usize] = true;
+        }
+
+        let mut seen = vec![false; n];
+        let per_root = roots
             .iter()
-            .filter_map(|p| graph.index_of(p))
-            .map(|i| i as u32)
+            .map(|&r| {
+                let before = idx.closure_size_from(&[r], &[], &mut seen);
+                let after = idx.closure_size_from(&[r], &skip, &mut seen);
+                (r, before, after)
+            })
             .collect();
-        if roots != self.context_roots {
-            self.context_total = self.idx.closure_size_from(&roots, None, &mut self.seen);
-            self.context_roots = roots;
+
+        let by_size = |mut ids: Vec<PathId>| -> Vec<PathId> {
+            ids.sort_by_key(|&i| std::cmp::Reverse(graph.path(i).nar_size));
+            ids
+        };
+        let unreachable = (0..n as u32)
+            .filter(|&i| reachable[i as usize] && !remaining[i as usize])
+            .collect();
+        Self {
+            cut: by_size(skip),
+            roots: per_root,
+            before,
+            after,
+            unreachable: by_size(unreachable),
         }
-        let Some(target) = graph.index_of(path) else {
-            return 0;
+    }
+}
+
+/// Everything that transitively refers to a path: what a rebuild of it would
+/// also rebuild.
+#[derive(Debug, Clone, Default)]
+pub struct ReverseClosure {
+    /// Transitive referrers, nearest first, without the path itself.
+    pub paths: Vec<PathId>,
+    /// Their summed NAR size.
+    pub size: u64,
+    /// The roots the path is reachable from, including itself if it is one.
+    pub roots: Vec<PathId>,
+}
+
+impl ReverseClosure {
+    /// Breadth-first over `immediate_parents`, so no referrer index has to be
+    /// built per query.
+    pub fn new(graph: &StorePathGraph, stats: &Stats, id: PathId) -> Self {
+        let mut seen = vec![false; graph.paths().len()];
+        seen[id as usize] = true;
+        let mut queue = std::collections::VecDeque::from([id]);
+        let mut paths = Vec::new();
+        while let Some(p) = queue.pop_front() {
+            for &parent in &stats[p].immediate_parents {
+                if !std::mem::replace(&mut seen[parent as usize], true) {
+                    paths.push(parent);
+                    queue.push_back(parent);
+                }
+            }
+        }
+        let size = paths.iter().map(|&p| graph.path(p).nar_size).sum();
+        let roots = graph
+            .root_ids()
+            .into_iter()
+            .filter(|&r| seen[r as usize])
+            .collect();
+        Self { paths, size, roots }
+    }
+}
+
+/// Dominator tree of the reference graph, rooted at a virtual node above
+/// `graph.roots`. A path's dominator subtree is exactly what becomes
+/// unreachable without it, so retained sizes for every path fall out of one
+/// pass instead of one closure walk per path.
+pub struct Dominators {
+    /// Immediate dominator per path index; `None` for paths dominated only by
+    /// the virtual root (i.e. reachable from more than one root, or a root)
+    /// and for paths no root reaches.
+    idom: Vec<Option<u32>>,
+    retained: Vec<u64>,
+}
+
+const NONE: u32 = u32::MAX;
+
+impl Dominators {
+    /// Lengauer–Tarjan with path compression (the "simple" variant,
+    /// O(E log V)), iterative throughout so deep closures cannot overflow
+    /// the stack.
+    pub fn new(graph: &StorePathGraph) -> Self {
+        let idx = IndexedGraph::new(graph);
+        let n = graph.paths().len();
+        let root = n as u32;
+        let roots = graph.root_ids();
+        let succ = |v: u32| -> &[u32] {
+            if v == root {
+                &roots
+            } else {
+                &idx.refs[v as usize]
+            }
         };
-        let without =
-            self.idx
-                .closure_size_from(&self.context_roots, Some(target as u32), &mut self.seen);
-        self.context_total.saturating_sub(without)
+
+        // Depth-first preorder from the virtual root.
+        let mut dfnum = vec![NONE; n + 1];
+        let mut vertex = Vec::with_capacity(n + 1);
+        let mut parent = vec![NONE; n + 1];
+        let mut stack = vec![(root, NONE)];
+        while let Some((v, p)) = stack.pop() {
+            if dfnum[v as usize] != NONE {
+                continue;
+            }
+            dfnum[v as usize] = vertex.len() as u32;
+            vertex.push(v);
+            parent[v as usize] = p;
+            for &w in succ(v).iter().rev() {
+                if dfnum[w as usize] == NONE {
+                    stack.push((w, v));
+                }
+            }
+        }
+
+        let mut pred: Vec<Vec<u32>> = vec![Vec::new(); n + 1];
+        for &v in &vertex {
+            for &w in succ(v) {
+                pred[w as usize].push(v);
+            }
+        }
+
+        let mut semi = vec![NONE; n + 1];
+        let mut ancestor = vec![NONE; n + 1];
+        let mut best = vec![NONE; n + 1];
+        let mut idom = vec![NONE; n + 1];
+        let mut samedom = vec![NONE; n + 1];
+        let mut bucket: Vec<Vec<u32>> = vec![Vec::new(); n + 1];
+        let mut path = Vec::new();
+
+        for &w in vertex.iter().skip(1).rev() {
+            let p = parent[w as usize];
+            let mut s = p;
+            for &v in &pred[w as usize] {
+                let candidate = if dfnum[v as usize] <= dfnum[w as usize] {
+                    v
+                } else {
+                    let b = eval(v, &mut ancestor, &mut best, &semi, &dfnum, &mut path);
+                    semi[b as usize]
+                };
+                if dfnum[candidate as usize] < dfnum[s as usize] {
+                    s = candidate;
+                }
+            }
+            semi[w as usize] = s;
+            bucket[s as usize].push(w);
+            ancestor[w as usize] = p;
+            best[w as usize] = w;
+
+            for v in std::mem::take(&mut bucket[p as usize]) {
+                let y = eval(v, &mut ancestor, &mut best, &semi, &dfnum, &mut path);
+                if semi[y as usize] == semi[v as usize] {
+                    idom[v as usize] = p;
+                } else {
+                    samedom[v as usize] = y;
+                }
+            }
+        }
+        for &w in vertex.iter().skip(1) {
+            if samedom[w as usize] != NONE {
+                idom[w as usize] = idom[samedom[w as usize] as usize];
+            }
+        }
+
+        // A dominator precedes everything it dominates in preorder, so a
+        // reverse sweep has each subtree summed before it is added upwards.
+        let mut retained = vec![0u64; n + 1];
+        for &v in vertex.iter().skip(1).rev() {
+            retained[v as usize] += idx.nar_size[v as usize];
+            retained[idom[v as usize] as usize] += retained[v as usize];
+        }
+        retained.truncate(n);
+
+        Self {
+            idom: idom[..n]
+                .iter()
+                .map(|&d| (d != NONE && d != root).then_some(d))
+                .collect(),
+            retained,
+        }
+    }
+
+    /// Bytes freed if `id` were removed from the roots' closure.
+    pub fn retained_size(&self, id: PathId) -> u64 {
+        self.retained[id as usize]
+    }
+
+    /// The closest path every route from the roots to `id` goes through.
+    pub fn immediate_dominator(&self, id: PathId) -> Option<PathId> {
+        self.idom[id as usize]
+    }
+}
+
+/// Link-eval forest lookup: the vertex with the smallest semidominator on the
+/// forest path above `v`, compressing that path on the way out.
+fn eval(
+    v: u32,
+    ancestor: &mut [u32],
+    best: &mut [u32],
+    semi: &[u32],
+    dfnum: &[u32],
+    path: &mut Vec<u32>,
+) -> u32 {
+    path.clear();
+    let mut u = v;
+    while ancestor[u as usize] != NONE && ancestor[ancestor[u as usize] as usize] != NONE {
+        path.push(u);
+        u = ancestor[u as usize];
+    }
+    for &x in path.iter().rev() {
+        let a = ancestor[x as usize] as usize;
+        let x = x as usize;
+        if dfnum[semi[best[a] as usize] as usize] < dfnum[semi[best[x] as usize] as usize] {
+            best[x] = best[a];
+        }
+        ancestor[x] = ancestor[a];
+    }
+    if ancestor[v as usize] == NONE {
+        v
+    } else {
+        best[v as usize]
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct PathStats {
     pub closure_size: u64,
-    pub immediate_parents: Vec<String>,
+    pub immediate_parents: Vec<PathId>,
+    /// See `Dominators::retained_size`.
+    pub retained_size: u64,
+    pub immediate_dominator: Option<PathId>,
+    /// Every path in the reference cycle this one is part of (itself
+    /// included), shared between the members.
+    pub cycle: Option<Arc<[PathId]>>,
+    /// See `Layers`; reference cycles count as one step.
+    pub min_depth: Option<u32>,
+    pub max_depth: Option<u32>,
+    pub height: u32,
 }
 
-pub fn calculate_stats(graph: &StorePathGraph) -> HashMap<String, PathStats> {
-    let mut stats = HashMap::with_capacity(graph.paths.len());
-    let mut referrers = graph.build_referrers();
+/// `PathStats` for every path in a graph, indexed by `PathId`.
+#[derive(Debug, Clone, Default)]
+pub struct Stats(Vec<PathStats>);
 
-    let idx = IndexedGraph::new(graph);
-    let mut seen = vec![0u32; graph.paths.len()];
-    let mut stack = Vec::new();
+impl Stats {
+    pub fn get(&self, id: PathId) -> Option<&PathStats> {
+        self.0.get(id as usize)
+    }
 
-    for (i, path) in graph.paths.iter().enumerate() {
-        let closure_size = idx.closure_size(i as u32, &mut seen, i as u32 + 1, &mut stack);
-        let immediate_parents = referrers.remove(&path.path).unwrap_or_default();
+    /// Lookup by store path, for callers that only have the string.
+    pub fn for_path(&self, graph: &StorePathGraph, path: &str) -> Option<&PathStats> {
+        self.get(graph.id_of(path)?)
+    }
 
-        stats.insert(
-            path.path.clone(),
-            PathStats {
-                closure_size,
-                immediate_parents,
-            },
-        );
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (PathId, &PathStats)> {
+        (0..).zip(&self.0)
+    }
+}
+
+impl Index<PathId> for Stats {
+    type Output = PathStats;
+
+    fn index(&self, id: PathId) -> &PathStats {
+        &self.0[id as usize]
+    }
+}
+
+/// Thread count `calculate_stats` uses when not told otherwise.
+pub fn default_jobs() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+pub fn calculate_stats(graph: &StorePathGraph) -> Stats {
+    calculate_stats_with_jobs(graph, default_jobs())
+}
+
+/// `calculate_stats` with the closure walks split over `jobs` threads; the
+/// result does not depend on `jobs`.
+pub fn calculate_stats_with_jobs(graph: &StorePathGraph, jobs: usize) -> Stats {
+    let referrers = graph.build_referrer_ids();
+
+    let dominators = Dominators::new(graph);
+    let components = Components::new(graph);
+    let closure_sizes = components.closure_sizes(jobs);
+    let layers = components.layers(&graph.root_ids());
+    let mut cycles: Vec<Option<Arc<[PathId]>>> = vec![None; graph.paths().len()];
+    for cycle in components.cycles() {
+        let shared: Arc<[PathId]> = cycle.into();
+        for &p in shared.iter() {
+            cycles[p as usize] = Some(shared.clone());
+        }
     }
 
-    stats
+    let stats = referrers
+        .into_iter()
+        .zip(cycles)
+        .enumerate()
+        .map(|(i, (immediate_parents, cycle))| PathStats {
+            closure_size: closure_sizes[i],
+            immediate_parents,
+            retained_size: dominators.retained[i],
+            immediate_dominator: dominators.idom[i],
+            cycle,
+            min_depth: layers.min_depth[i],
+            max_depth: layers.max_depth[i],
+            height: layers.height[i],
+        })
+        .collect();
+
+    Stats(stats)
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -169,6 +745,14 @@ pub enum SortOrder {
     Alphabetical,
     ClosureSize,
     AddedSize,
+    RetainedSize,
+    /// Most recently registered first; paths without a registration time
+    /// (binary caches, old snapshots) go last.
+    RegistrationTime,
+    /// Deepest first, by the longest chain from a root.
+    Depth,
+    /// Tallest first, by the longest chain below.
+    Height,
 }
 
 impl SortOrder {
@@ -176,7 +760,11 @@ impl SortOrder {
         match self {
             SortOrder::Alphabetical => SortOrder::ClosureSize,
             SortOrder::ClosureSize => SortOrder::AddedSize,
-            SortOrder::AddedSize => SortOrder::Alphabetical,
+            SortOrder::AddedSize => SortOrder::RetainedSize,
+            SortOrder::RetainedSize => SortOrder::RegistrationTime,
+            SortOrder::RegistrationTime => SortOrder::Depth,
+            SortOrder::Depth => SortOrder::Height,
+            SortOrder::Height => SortOrder::Alphabetical,
         }
     }
 
@@ -185,142 +773,772 @@ impl SortOrder {
             SortOrder::Alphabetical => "name",
             SortOrder::ClosureSize => "closure size",
             SortOrder::AddedSize => "added size",
+            SortOrder::RetainedSize => "retained size",
+            SortOrder::RegistrationTime => "registration time",
+            SortOrder::Depth => "depth",
+            SortOrder::Height => "height",
         }
     }
 }
 
 pub fn sort_paths(
-    paths: &mut [String],
+    paths: &mut [PathId],
     graph: &StorePathGraph,
-    stats: &HashMap<String, PathStats>,
+    stats: &Stats,
     order: SortOrder,
-    added: Option<&HashMap<String, u64>>,
+    added: Option<&HashMap<PathId, u64>>,
 ) {
     match order {
         SortOrder::Alphabetical => {
-            paths.sort_by(|a, b| {
-                let na = graph.get_path(a).map(|p| p.name.as_str()).unwrap_or(a);
-                let nb = graph.get_path(b).map(|p| p.name.as_str()).unwrap_or(b);
-                na.cmp(nb)
-            });
+            paths.sort_by(|&a, &b| graph.path(a).name.cmp(&graph.path(b).name));
         }
         SortOrder::ClosureSize => {
-            paths.sort_by_key(|p| std::cmp::Reverse(stats.get(p).map_or(0, |s| s.closure_size)));
+            paths.sort_by_key(|&p| std::cmp::Reverse(stats[p].closure_size));
         }
         SortOrder::AddedSize => {
             // Without a context (e.g. referrers pane) added size is undefined;
             // closure size is the next best stable order.
-            paths.sort_by_key(|p| {
+            paths.sort_by_key(|&p| {
                 std::cmp::Reverse(
                     added
-                        .and_then(|m| m.get(p).copied())
-                        .unwrap_or_else(|| stats.get(p).map_or(0, |s| s.closure_size)),
+                        .and_then(|m| m.get(&p).copied())
+                        .unwrap_or(stats[p].closure_size),
                 )
             });
         }
+        SortOrder::RetainedSize => {
+            paths.sort_by_key(|&p| std::cmp::Reverse(stats[p].retained_size));
+        }
+        SortOrder::RegistrationTime => {
+            paths.sort_by_key(|&p| std::cmp::Reverse(graph.path(p).registration_time));
+        }
+        SortOrder::Depth => {
+            paths.sort_by_key(|&p| std::cmp::Reverse((stats[p].max_depth, stats[p].min_depth)));
+        }
+        SortOrder::Height => {
+            paths.sort_by_key(|&p| std::cmp::Reverse(stats[p].height));
+        }
     }
 }
 
-// Trie-like structure for efficient path storage
+/// Simple runs through one reference cycle enumerated for `WhyDepends`
+/// before it gives up on counting that cycle exactly.
+const CYCLE_SEGMENTS: usize = 100_000;
+
+/// Every simple reference chain from the roots to one target, counted and
+/// enumerable by rank, shortest first, so the why-depends view can page
+/// through millions of chains without materialising them.
+///
+/// A simple chain passes through each reference cycle (strongly connected
+/// component) in one contiguous run, so the cycles are expanded into their
+/// simple internal runs ("segments") and chains become paths in the DAG of
+/// segments. Counting is a DP over that DAG, per chain length. Cycles are
+/// small in practice (an `out`/`dev` pair); one with more than
+/// `CYCLE_SEGMENTS` runs is cut short and the count becomes a lower bound,
+/// which `is_exact` reports.
 #[derive(Debug, Clone)]
-struct Treeish {
-    node: String,
-    children: Vec<Treeish>,
+pub struct WhyDepends {
+    target: u32,
+    /// A single path, or a simple run of paths through one cycle.
+    segments: Vec<Vec<u32>>,
+    /// Segments a chain can continue with after each segment; empty for
+    /// those that end at the target. Every successor has a smaller index.
+    succ: Vec<Vec<u32>>,
+    /// Segments starting at a source (usually a root).
+    starts: Vec<u32>,
+    /// `counts[s][i]`: chains from segment `s` to the target that are
+    /// `min_len[s] + i` paths long. Saturates rather than overflows; no one
+    /// pages through 2^128 chains.
+    min_len: Vec<u32>,
+    counts: Vec<Vec<u128>>,
+    /// All chains by length, from `total_min_len` on.
+    total_min_len: u32,
+    by_len: Vec<u128>,
+    total: u128,
+    exact: bool,
 }
 
-impl Treeish {
-    fn new(node: String) -> Self {
-        Treeish {
-            node,
-            children: Vec::new(),
+impl WhyDepends {
+    /// Chains from the graph's roots to `target`.
+    pub fn new(graph: &StorePathGraph, target: PathId) -> Self {
+        Self::from_sources(graph, graph.root_ids(), target)
+    }
+
+    /// Chains from `source` to `target`, for "why does this path depend on
+    /// that one" when neither is a root.
+    pub fn between(graph: &StorePathGraph, source: PathId, target: PathId) -> Self {
+        Self::from_sources(graph, vec![source], target)
+    }
+
+    fn from_sources(graph: &StorePathGraph, sources: Vec<PathId>, target: PathId) -> Self {
+        let mut idx = IndexedGraph::new(graph);
+        // A path listed twice as a reference is still one way to go.
+        for refs in &mut idx.refs {
+            refs.sort_unstable();
+            refs.dedup();
+        }
+        let comps = Components::from_indexed(&idx);
+        let n = graph.paths().len();
+
+        // Paths that reach the target...
+        let mut referrers: Vec<Vec<u32>> = vec![Vec::new(); n];
+        for (v, refs) in idx.refs.iter().enumerate() {
+            for &r in refs {
+                referrers[r as usize].push(v as u32);
+            }
+        }
+        let mut reaches = vec![false; n];
+        reaches[target as usize] = true;
+        let mut stack = vec![target];
+        while let Some(v) = stack.pop() {
+            for &u in &referrers[v as usize] {
+                if !reaches[u as usize] {
+                    reaches[u as usize] = true;
+                    stack.push(u);
+                }
+            }
+        }
+        // ...and that a source reaches without passing the target.
+        let mut sources: Vec<u32> = sources
+            .into_iter()
+            .filter(|&r| reaches[r as usize])
+            .collect();
+        sources.sort_unstable();
+        sources.dedup();
+        let mut relevant = vec![false; n];
+        let mut stack = sources.clone();
+        for &r in &sources {
+            relevant[r as usize] = true;
+        }
+        while let Some(v) = stack.pop() {
+            if v == target {
+                continue;
+            }
+            for &w in &idx.refs[v as usize] {
+                if reaches[w as usize] && !relevant[w as usize] {
+                    relevant[w as usize] = true;
+                    stack.push(w);
+                }
+            }
+        }
+
+        let mut region: HashMap<u32, Vec<u32>> = HashMap::new();
+        for v in (0..n as u32).filter(|&v| relevant[v as usize]) {
+            region.entry(comps.comp[v as usize]).or_default().push(v);
         }
+        let mut order: Vec<u32> = region.keys().copied().collect();
+        order.sort_unstable();
+
+        let mut chains = Self {
+            target,
+            segments: Vec::new(),
+            succ: Vec::new(),
+            starts: Vec::new(),
+            min_len: Vec::new(),
+            counts: Vec::new(),
+            total_min_len: 0,
+            by_len: Vec::new(),
+            total: 0,
+            exact: true,
+        };
+        // Segments starting at each path.
+        let mut starting: HashMap<u32, Vec<u32>> = HashMap::new();
+        let leaves = |v: u32| -> bool {
+            v == target
+                || idx.refs[v as usize].iter().any(|&w| {
+                    relevant[w as usize] && comps.comp[w as usize] != comps.comp[v as usize]
+                })
+        };
+        // Components are numbered sinks first, so every segment a chain can
+        // continue with already exists when a component is expanded.
+        for c in order {
+            let members = &region[&c];
+            for &start in members {
+                let entered = sources.contains(&start)
+                    || referrers[start as usize]
+                        .iter()
+                        .any(|&u| relevant[u as usize] && comps.comp[u as usize] != c);
+                if !entered {
+                    continue;
+                }
+                let mut runs = Vec::new();
+                chains.exact &= simple_runs(start, target, members, &idx, &mut runs);
+                for run in runs.into_iter().filter(|r| leaves(*r.last().unwrap())) {
+                    let id = chains.add_segment(run, &idx, &comps, &relevant, &starting);
+                    starting.entry(start).or_default().push(id);
+                }
+            }
+        }
+
+        let mut starts: Vec<u32> = sources
+            .iter()
+            .flat_map(|r| starting.get(r).into_iter().flatten().copied())
+            .collect();
+        starts.sort_unstable_by_key(|&s| (chains.min_len[s as usize], s));
+        (chains.total_min_len, chains.by_len) = chains.sum_counts(&starts);
+        chains.starts = starts;
+        chains.total = chains
+            .by_len
+            .iter()
+            .fold(0u128, |acc, &c| acc.saturating_add(c));
+        chains
+    }
+
+    /// Add `run` with its successors and counts, which need every segment
+    /// after it to exist already.
+    fn add_segment(
+        &mut self,
+        run: Vec<u32>,
+        idx: &IndexedGraph,
+        comps: &Components,
+        relevant: &[bool],
+        starting: &HashMap<u32, Vec<u32>>,
+    ) -> u32 {
+        let last = *run.last().unwrap();
+        let mut succ: Vec<u32> = Vec::new();
+        if last != self.target {
+            for &w in &idx.refs[last as usize] {
+                if relevant[w as usize] && comps.comp[w as usize] != comps.comp[last as usize] {
+                    succ.extend(starting.get(&w).into_iter().flatten());
+                }
+            }
+            succ.sort_unstable_by_key(|&s| (self.min_len[s as usize], s));
+            succ.dedup();
+        }
+        let (min, counts) = if last == self.target {
+            (0, vec![1])
+        } else {
+            self.sum_counts(&succ)
+        };
+        self.min_len.push(min + run.len() as u32);
+        self.counts.push(counts);
+        self.segments.push(run);
+        self.succ.push(succ);
+        self.segments.len() as u32 - 1
+    }
+
+    /// Chains from any of `segments` by length, as `(min_len, counts)`.
+    fn sum_counts(&self, segments: &[u32]) -> (u32, Vec<u128>) {
+        let Some(min) = segments.iter().map(|&s| self.min_len[s as usize]).min() else {
+            return (0, Vec::new());
+        };
+        let mut counts: Vec<u128> = Vec::new();
+        for &s in segments {
+            let offset = (self.min_len[s as usize] - min) as usize;
+            let own = &self.counts[s as usize];
+            if counts.len() < offset + own.len() {
+                counts.resize(offset + own.len(), 0);
+            }
+            for (i, &c) in own.iter().enumerate() {
+                counts[offset + i] = counts[offset + i].saturating_add(c);
+            }
+        }
+        (min, counts)
+    }
+
+    fn count(&self, segment: u32, len: u32) -> u128 {
+        let s = segment as usize;
+        len.checked_sub(self.min_len[s])
+            .and_then(|i| self.counts[s].get(i as usize))
+            .copied()
+            .unwrap_or(0)
     }
 
-    fn with_children(node: String, children: Vec<Treeish>) -> Self {
-        Treeish { node, children }
+    /// Number of simple chains from the roots to the target.
+    pub fn total(&self) -> u128 {
+        self.total
     }
 
-    // Convert Treeish to paths
-    fn to_paths(&self) -> Vec<Vec<String>> {
-        if self.children.is_empty() {
-            vec![vec![self.node.clone()]]
+    /// False if a reference cycle had too many simple runs through it to
+    /// count them all; `total` is then a lower bound.
+    pub fn is_exact(&self) -> bool {
+        self.exact
+    }
+
+    /// The chain at `rank` (0-based, shortest first), root first and target
+    /// last.
+    pub fn chain(&self, mut rank: u128) -> Option<Vec<PathId>> {
+        if rank >= self.total {
+            return None;
+        }
+        let mut len = self.total_min_len;
+        for &c in &self.by_len {
+            if rank < c {
+                break;
+            }
+            rank -= c;
+            len += 1;
+        }
+        let mut chain = Vec::with_capacity(len as usize);
+        let mut candidates = &self.starts;
+        loop {
+            let mut picked = None;
+            for &c in candidates {
+                let n = self.count(c, len);
+                if rank < n {
+                    picked = Some(c);
+                    break;
+                }
+                rank -= n;
+            }
+            let segment = picked.expect("rank is below the sum of the candidates' counts");
+            let run = &self.segments[segment as usize];
+            chain.extend_from_slice(run);
+            len -= run.len() as u32;
+            if *run.last().unwrap() == self.target {
+                return Some(chain);
+            }
+            candidates = &self.succ[segment as usize];
+        }
+    }
+
+    /// Up to `len` chains starting at rank `start`.
+    pub fn page(&self, start: u128, len: usize) -> Vec<Vec<PathId>> {
+        (0..len as u128)
+            .map_while(|i| self.chain(start.checked_add(i)?))
+            .collect()
+    }
+}
+
+/// Every simple run from `start` within `members` (one component), stopping
+/// at `target`, into `runs`. False if it gave up after `CYCLE_SEGMENTS`.
+fn simple_runs(
+    start: u32,
+    target: u32,
+    members: &[u32],
+    idx: &IndexedGraph,
+    runs: &mut Vec<Vec<u32>>,
+) -> bool {
+    let mut run = vec![start];
+    let mut next = vec![0usize];
+    runs.push(run.clone());
+    while let Some(&v) = run.last() {
+        let edge = next.last_mut().unwrap();
+        let refs = if v == target {
+            &[][..]
         } else {
-            let mut paths = Vec::new();
-            for child in &self.children {
-                for mut path in child.to_paths() {
-                    path.insert(0, self.node.clone());
-                    paths.push(path);
+            &idx.refs[v as usize]
+        };
+        match refs.get(*edge) {
+            Some(&w) => {
+                *edge += 1;
+                if members.contains(&w) && !run.contains(&w) {
+                    if runs.len() == CYCLE_SEGMENTS {
+                        return false;
+                    }
+                    run.push(w);
+                    next.push(0);
+                    runs.push(run.clone());
                 }
             }
-            paths
+            None => {
+                run.pop();
+                next.pop();
+            }
         }
     }
+    true
 }
 
-/// Find all paths from roots to the target path using bottom-up approach
-pub fn why_depends(graph: &StorePathGraph, target: &str) -> Vec<Vec<String>> {
-    // Early exit if target is not in the graph
-    if graph.get_path(target).is_none() {
-        return Vec::new();
+#[cfg(test)]
+mod tests {
+    use super::{
+        Components, Dominators, IndexedGraph, ReverseClosure, WhyDepends, calculate_stats,
+        calculate_stats_with_jobs,
+    };
+    use crate::store_path::testing::{TestGraph, p};
+    use crate::store_path::{PathId, StorePathGraph};
+
+    fn id(g: &StorePathGraph, name: &str) -> PathId {
+        g.id_of(&p(name)).unwrap()
     }
 
-    // Memoization cache
-    let mut cache: HashMap<String, Option<Treeish>> = HashMap::new();
+    fn ids(g: &StorePathGraph, names: &[&str]) -> Vec<PathId> {
+        names.iter().map(|n| id(g, n)).collect()
+    }
 
-    // Bottom-up traversal to build Treeish
-    fn build_treeish(
-        graph: &StorePathGraph,
-        node: &str,
-        target: &str,
-        cache: &mut HashMap<String, Option<Treeish>>,
-        visited: &mut HashSet<String>,
-    ) -> Option<Treeish> {
-        // Check cache first
-        if let Some(cached) = cache.get(node) {
-            return cached.clone();
+    fn graph(edges: &[(&str, &[&str])], roots: &[&str]) -> StorePathGraph {
+        let mut graph = TestGraph::default();
+        for (i, (name, refs)) in edges.iter().enumerate() {
+            graph.add_named(name, 1 << i, refs);
         }
+        graph.build(&roots.iter().map(|r| p(r)).collect::<Vec<_>>())
+    }
 
-        // Prevent cycles
-        if !visited.insert(node.to_string()) {
-            return None;
+    #[test]
+    fn diamond() {
+        // root → a, b → c → d, with d also referring back to itself.
+        let g = graph(
+            &[
+                ("root", &["a", "b"]),
+                ("a", &["c"]),
+                ("b", &["c"]),
+                ("c", &["d"]),
+                ("d", &["d"]),
+            ],
+            &["root"],
+        );
+        let dom = Dominators::new(&g);
+        let idom = |n: &str| dom.immediate_dominator(id(&g, n));
+        assert_eq!(idom("root"), None);
+        assert_eq!(idom("a"), Some(id(&g, "root")));
+        assert_eq!(idom("c"), Some(id(&g, "root")));
+        assert_eq!(idom("d"), Some(id(&g, "c")));
+        assert_eq!(dom.retained_size(id(&g, "root")), 0b11111);
+        assert_eq!(dom.retained_size(id(&g, "a")), 0b00010);
+        assert_eq!(dom.retained_size(id(&g, "c")), 0b11000);
+    }
+
+    #[test]
+    fn shared_between_roots() {
+        let g = graph(
+            &[
+                ("r1", &["x"]),
+                ("r2", &["x"]),
+                ("x", &["y"]),
+                ("y", &[]),
+                ("stray", &["x"]),
+            ],
+            &["r1", "r2"],
+        );
+        let dom = Dominators::new(&g);
+        assert_eq!(dom.immediate_dominator(id(&g, "x")), None);
+        assert_eq!(dom.immediate_dominator(id(&g, "y")), Some(id(&g, "x")));
+        assert_eq!(dom.retained_size(id(&g, "r1")), 1);
+        assert_eq!(dom.retained_size(id(&g, "x")), 0b01100);
+        // Unreachable from the roots: nothing to retain.
+        assert_eq!(dom.retained_size(id(&g, "stray")), 0);
+    }
+
+    /// `n` paths with up to three references each (cycles included); the
+    /// first three are the roots.
+    fn random_graph(seed: &mut u64, n: u64) -> StorePathGraph {
+        let mut next = |n: u64| {
+            *seed ^= *seed << 13;
+            *seed ^= *seed >> 7;
+            *seed ^= *seed << 17;
+            *seed % n
+        };
+        let names: Vec<String> = (0..n).map(|i| format!("n{i}")).collect();
+        let mut g = TestGraph::default();
+        for name in &names {
+            let refs: Vec<&str> = (0..next(4))
+                .map(|_| names[next(n) as usize].as_str())
+                .collect();
+            g.add_named(name, 1 + next(1000), &refs);
         }
+        g.build(&names.iter().take(3).map(|name| p(name)).collect::<Vec<_>>())
+    }
 
-        let result = if node == target {
-            Some(Treeish::new(node.to_string()))
-        } else if let Some(store_path) = graph.get_path(node) {
-            let mut child_trees = Vec::new();
+    /// Retained size is by definition the closure lost when skipping a path;
+    /// check that against the walk `AddedSize` uses, on graphs with cycles.
+    #[test]
+    fn matches_brute_force() {
+        let mut seed = 0x2545_f491_4f6c_dd1du64;
+        for _ in 0..20 {
+            let n = 40;
+            let g = random_graph(&mut seed, n);
 
-            for reference in &store_path.references {
-                if let Some(tree) = build_treeish(graph, reference, target, cache, visited) {
-                    child_trees.push(tree);
-                }
+            let dom = Dominators::new(&g);
+            let idx = IndexedGraph::new(&g);
+            let roots: Vec<u32> = (0..3).collect();
+            let mut seen = vec![false; n as usize];
+            let total = idx.closure_size_from(&roots, &[], &mut seen);
+            for (i, path) in g.paths().iter().enumerate() {
+                let mut reachable_roots = roots.clone();
+                reachable_roots.retain(|&r| r != i as u32);
+                let without = idx.closure_size_from(&reachable_roots, &[i as u32], &mut seen);
+                assert_eq!(
+                    dom.retained_size(i as u32),
+                    total - without,
+                    "{}",
+                    path.name
+                );
             }
+        }
+    }
 
-            if child_trees.is_empty() {
-                None
-            } else {
-                Some(Treeish::with_children(node.to_string(), child_trees))
+    #[test]
+    fn parallel_stats_match_serial() {
+        let mut seed = 0x9e37_79b9_7f4a_7c15u64;
+        let g = random_graph(&mut seed, 2000);
+        let serial = calculate_stats_with_jobs(&g, 1);
+        for jobs in [2, 3, 8, 64] {
+            let parallel = calculate_stats_with_jobs(&g, jobs);
+            assert_eq!(parallel.len(), serial.len());
+            for (id, s) in serial.iter() {
+                assert_eq!(parallel[id].closure_size, s.closure_size, "{jobs} jobs");
             }
-        } else {
-            None
-        };
+        }
+    }
 
-        visited.remove(node);
-        cache.insert(node.to_string(), result.clone());
-        result
+    #[test]
+    fn cycles_are_condensed() {
+        // root → out ⇄ dev → lib, with lib referring to itself.
+        let g = graph(
+            &[
+                ("root", &["out"]),
+                ("out", &["dev"]),
+                ("dev", &["out", "lib"]),
+                ("lib", &["lib"]),
+            ],
+            &["root"],
+        );
+        assert_eq!(Components::new(&g).cycles(), [ids(&g, &["out", "dev"])]);
+
+        let stats = calculate_stats(&g);
+        assert_eq!(stats[id(&g, "out")].closure_size, 0b1110);
+        assert_eq!(stats[id(&g, "dev")].closure_size, 0b1110);
+        assert_eq!(stats[id(&g, "root")].closure_size, 0b1111);
+        let cycle = stats[id(&g, "dev")].cycle.as_ref().unwrap();
+        assert_eq!(cycle.len(), 2);
+        assert!(stats[id(&g, "lib")].cycle.is_none());
     }
 
-    // Build trees from roots
-    let mut all_paths = Vec::new();
-    for root in &graph.roots {
-        let mut visited = HashSet::new();
-        if let Some(tree) = build_treeish(graph, root, target, &mut cache, &mut visited) {
-            let paths = tree.to_paths();
-            all_paths.extend(paths);
+    #[test]
+    fn condensed_sizes_match_direct_walks() {
+        let mut seed = 0x1234_5678_9abc_def1u64;
+        for n in [1, 2, 10, 300] {
+            let g = random_graph(&mut seed, n);
+            let idx = IndexedGraph::new(&g);
+            let mut seen = vec![0u32; n as usize];
+            let mut stack = Vec::new();
+            let sizes = Components::new(&g).closure_sizes(1);
+            for i in 0..n as u32 {
+                assert_eq!(
+                    sizes[i as usize],
+                    idx.closure_size(i, &mut seen, i + 1, &mut stack)
+                );
+            }
         }
     }
 
-    // Limit output (no sorting, to match Haskell implementation)
-    all_paths.truncate(1000);
-    all_paths
+    #[test]
+    fn why_depends_counts_and_unranks() {
+        // Two ways through each of three layers, plus a shortcut root → t.
+        let g = graph(
+            &[
+                ("root", &["a1", "b1", "t"]),
+                ("a1", &["a2", "b2"]),
+                ("b1", &["a2", "b2"]),
+                ("a2", &["a3", "b3"]),
+                ("b2", &["a3", "b3"]),
+                ("a3", &["t"]),
+                ("b3", &["t"]),
+                ("t", &[]),
+                ("unrelated", &["t"]),
+            ],
+            &["root"],
+        );
+        let chains = WhyDepends::new(&g, id(&g, "t"));
+        assert_eq!(chains.total(), 9);
+        assert_eq!(chains.chain(0).unwrap(), ids(&g, &["root", "t"]));
+        let all = chains.page(0, 100);
+        assert_eq!(all.len(), 9);
+        assert!(all.windows(2).all(|w| w[0] != w[1]));
+        assert!(all[1..].iter().all(|c| c.len() == 5));
+        assert_eq!(chains.page(7, 100).len(), 2);
+        assert_eq!(chains.chain(9), None);
+    }
+
+    #[test]
+    fn why_depends_between() {
+        let g = graph(
+            &[
+                ("root", &["python", "openssl"]),
+                ("python", &["libffi", "openssl"]),
+                ("libffi", &["openssl"]),
+                ("openssl", &[]),
+            ],
+            &["root"],
+        );
+        let chains = WhyDepends::between(&g, id(&g, "python"), id(&g, "openssl"));
+        assert_eq!(chains.total(), 2);
+        assert_eq!(chains.chain(0).unwrap(), ids(&g, &["python", "openssl"]));
+        assert_eq!(
+            chains.chain(1).unwrap(),
+            ids(&g, &["python", "libffi", "openssl"])
+        );
+        let none = WhyDepends::between(&g, id(&g, "openssl"), id(&g, "python"));
+        assert_eq!(none.total(), 0);
+    }
+
+    #[test]
+    fn why_depends_breaks_cycles() {
+        let g = graph(
+            &[
+                ("root", &["out"]),
+                ("out", &["dev", "lib"]),
+                ("dev", &["out", "lib"]),
+                ("lib", &[]),
+            ],
+            &["root"],
+        );
+        let chains = WhyDepends::new(&g, id(&g, "lib"));
+        assert_eq!(chains.total(), 2);
+        assert_eq!(chains.chain(0).unwrap(), ids(&g, &["root", "out", "lib"]));
+        assert_eq!(
+            chains.chain(1).unwrap(),
+            ids(&g, &["root", "out", "dev", "lib"])
+        );
+    }
+
+    #[test]
+    fn why_depends_through_a_cycle() {
+        let g = graph(
+            &[
+                ("root", &["a", "b"]),
+                ("a", &["b", "t"]),
+                ("b", &["a", "t"]),
+                ("t", &[]),
+            ],
+            &["root"],
+        );
+        let chains = WhyDepends::new(&g, id(&g, "t"));
+        assert_eq!(chains.total(), 4);
+        assert!(chains.is_exact());
+        assert_eq!(
+            chains.page(0, 10),
+            [
+                ids(&g, &["root", "a", "t"]),
+                ids(&g, &["root", "b", "t"]),
+                ids(&g, &["root", "a", "b", "t"]),
+                ids(&g, &["root", "b", "a", "t"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn why_depends_matches_brute_force() {
+        fn simple_chains(
+            g: &StorePathGraph,
+            chain: &mut Vec<PathId>,
+            t: PathId,
+            out: &mut Vec<Vec<PathId>>,
+        ) {
+            let v = *chain.last().unwrap();
+            if v == t {
+                out.push(chain.clone());
+                return;
+            }
+            for &w in g.references_of(v) {
+                if !chain.contains(&w) {
+                    chain.push(w);
+                    simple_chains(g, chain, t, out);
+                    chain.pop();
+                }
+            }
+        }
+        let mut seed = 0x2545_f491_4f6c_dd1d;
+        for _ in 0..30 {
+            let g = random_graph(&mut seed, 9);
+            let mut roots = g.root_ids();
+            roots.sort_unstable();
+            roots.dedup();
+            for t in 0..g.paths().len() as PathId {
+                let mut expected = Vec::new();
+                for &r in &roots {
+                    simple_chains(&g, &mut vec![r], t, &mut expected);
+                }
+                // Random references may repeat; a chain is a list of paths.
+                expected.sort();
+                expected.dedup();
+                let chains = WhyDepends::new(&g, t);
+                assert_eq!(chains.total(), expected.len() as u128);
+                let mut all = chains.page(0, expected.len() + 1);
+                assert!(all.windows(2).all(|w| w[0].len() <= w[1].len()));
+                all.sort();
+                assert_eq!(all, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn why_depends_without_cap() {
+        // 2^100 chains through 100 two-wide layers.
+        let names: Vec<[String; 2]> = (0..100)
+            .map(|i| [format!("a{i}"), format!("b{i}")])
+            .collect();
+        let next: Vec<Vec<&str>> = (0..100)
+            .map(|i| match names.get(i + 1) {
+                Some([a, b]) => vec![a.as_str(), b.as_str()],
+                None => vec!["t"],
+            })
+            .collect();
+        let mut edges: Vec<(&str, &[&str])> = vec![("root", &["a0", "b0"])];
+        for (pair, next) in names.iter().zip(&next) {
+            edges.push((&pair[0], next));
+            edges.push((&pair[1], next));
+        }
+        edges.push(("t", &[]));
+        let mut g = TestGraph::default();
+        for (name, refs) in edges {
+            g.add_named(name, 0, refs);
+        }
+        let g = g.build(&[p("root")]);
+
+        let chains = WhyDepends::new(&g, id(&g, "t"));
+        assert_eq!(chains.total(), 1 << 100);
+        let last = chains.chain(chains.total() - 1).unwrap();
+        assert_eq!(last.len(), 102);
+        assert!(
+            last[1..101]
+                .iter()
+                .all(|&c| g.path(c).name.starts_with('b'))
+        );
+    }
+
+    #[test]
+    fn reverse_closure() {
+        let g = graph(
+            &[
+                ("r1", &["app"]),
+                ("r2", &["lib"]),
+                ("app", &["lib", "app"]),
+                ("lib", &["zlib"]),
+                ("zlib", &[]),
+                ("r3", &[]),
+            ],
+            &["r1", "r2", "r3"],
+        );
+        let stats = calculate_stats(&g);
+        let rc = ReverseClosure::new(&g, &stats, id(&g, "lib"));
+        let mut paths = rc.paths.clone();
+        paths.sort();
+        assert_eq!(paths, ids(&g, &["r1", "r2", "app"]));
+        assert_eq!(rc.size, 0b111);
+        assert_eq!(rc.roots, ids(&g, &["r1", "r2"]));
+
+        let rc = ReverseClosure::new(&g, &stats, id(&g, "r1"));
+        assert!(rc.paths.is_empty());
+        assert_eq!(rc.roots, ids(&g, &["r1"]));
+    }
+
+    #[test]
+    fn depth_and_height() {
+        // wrapper → interpreter → toolchain, plus a shortcut and a cycle.
+        let g = graph(
+            &[
+                ("root", &["wrapper", "toolchain"]),
+                ("wrapper", &["interpreter"]),
+                ("interpreter", &["toolchain", "out"]),
+                ("toolchain", &[]),
+                ("out", &["dev"]),
+                ("dev", &["out"]),
+                ("stray", &["root"]),
+            ],
+            &["root"],
+        );
+        let stats = calculate_stats(&g);
+        let layer = |n: &str| {
+            let s = &stats[id(&g, n)];
+            (s.min_depth, s.max_depth, s.height)
+        };
+        assert_eq!(layer("root"), (Some(0), Some(0), 3));
+        assert_eq!(layer("toolchain"), (Some(1), Some(3), 0));
+        assert_eq!(layer("interpreter"), (Some(2), Some(2), 1));
+        assert_eq!(layer("out"), (Some(3), Some(3), 0));
+        assert_eq!(layer("dev"), layer("out"));
+        assert_eq!(layer("stray"), (None, None, 4));
+    }
 }
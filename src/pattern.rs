@@ -0,0 +1,117 @@
+use crate::store_path::{PathId, StorePathGraph};
+
+/// Selects store paths by name: a glob with `*` and `?` (`*-doc`, `perl*`),
+/// a bare package name that matches any version of it (`perl` for
+/// `perl-5.38.2`), or, starting with `/`, a glob over the full store path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern(String);
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Self {
+        Self(pattern.trim().trim_end_matches('/').to_string())
+    }
+
+    /// Comma-separated patterns, as `--what-if '*-doc,*-man'` takes them.
+    pub fn parse_list(patterns: &str) -> Vec<Self> {
+        patterns
+            .split(',')
+            .filter(|p| !p.trim().is_empty())
+            .map(Self::new)
+            .collect()
+    }
+
+    /// `name` is the name part of the store path, without the hash.
+    pub fn matches(&self, name: &str, path: &str) -> bool {
+        if self.0.starts_with('/') {
+            glob_match(&self.0, path)
+        } else if self.0.contains(['*', '?']) {
+            glob_match(&self.0, name)
+        } else {
+            name == self.0 || is_package(name, &self.0)
+        }
+    }
+}
+
+impl std::fmt::Display for Pattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Every path in the graph matched by any of `patterns`, in graph order.
+pub fn select(graph: &StorePathGraph, patterns: &[Pattern]) -> Vec<PathId> {
+    (0..graph.paths().len() as PathId)
+        .filter(|&id| {
+            let p = graph.path(id);
+            let name = graph.original_name(p);
+            patterns.iter().any(|pat| pat.matches(name, &p.path))
+        })
+        .collect()
+}
+
+/// Whether `name` is `pname` followed by a version, e.g. `openssl-3.0.13`
+/// (or one of its outputs, `openssl-3.0.13-dev`) for `openssl`.
+pub fn is_package(name: &str, pname: &str) -> bool {
+    name.strip_prefix(pname)
+        .and_then(|rest| rest.strip_prefix('-'))
+        .is_some_and(|rest| rest.starts_with(|c: char| c.is_ascii_digit()))
+}
+
+/// `*` matches any run of characters, `?` any single one. Greedy with
+/// backtracking to the last `*`, so linear in practice.
+pub(crate) fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((sp, st)) => {
+                    p = sp + 1;
+                    t = st + 1;
+                    star = Some((sp, st + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn globs() {
+        assert!(glob_match("*-doc", "gnumake-4.4.1-doc"));
+        assert!(!glob_match("*-doc", "gnumake-4.4.1-docs"));
+        assert!(glob_match("perl*", "perl5.38.2-XML-Parser-2.46"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a?c*e", "abcde"));
+        assert!(!glob_match("a?c", "ac"));
+        assert!(glob_match("*a*b", "xaxxab"));
+    }
+
+    #[test]
+    fn pattern_kinds() {
+        let p = "/nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-perl-5.38.2";
+        assert!(Pattern::new("perl").matches("perl-5.38.2", p));
+        assert!(!Pattern::new("perl").matches("perl5.38.2-XML-Parser-2.46", p));
+        assert!(!Pattern::new("perl").matches("perlbench-1", p));
+        assert!(Pattern::new("/nix/store/*-perl-*").matches("perl-5.38.2", p));
+        assert_eq!(
+            Pattern::parse_list("*-doc, *-man,"),
+            [Pattern::new("*-doc"), Pattern::new("*-man")]
+        );
+    }
+}
@@ -0,0 +1,270 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use crate::store_path::{HASH_LEN, PathId, StorePathGraph};
+
+/// Bytes shown on either side of a hit, as `nix why-depends --precise` does;
+/// fewer at the start of a chunk `walk` reads.
+const MARGIN: usize = 32;
+
+/// Bytes `walk` reads from a file at a time.
+const CHUNK: usize = 64 * 1024;
+
+/// One place inside a store path that embeds another path's hash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hit {
+    /// Relative to the scanned path; empty when the path is itself the file
+    /// or symlink.
+    pub file: PathBuf,
+    /// Found in a symlink's target rather than in a file's contents.
+    pub symlink: bool,
+    /// The bytes around the hash, unprintable ones as `.`.
+    pub snippet: String,
+}
+
+/// Every occurrence of `hash` in the files under `path` and in the targets of
+/// its symlinks, in file-name order, and the files that could not be read.
+pub fn scan(path: &Path, hash: &str) -> Result<(Vec<Hit>, Vec<Unreadable>)> {
+    let mut hits = Vec::new();
+    let unreadable = walk(path, |rel, bytes, symlink| {
+        hits.extend(find(bytes, hash.as_bytes()).map(|at| Hit {
+            file: rel.to_path_buf(),
+            symlink,
+            snippet: snippet(bytes, at, hash.len()),
+        }));
+    })?;
+    Ok((hits, unreadable))
+}
+
+/// A file or symlink under a scanned path that could not be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unreadable {
+    /// Relative to the scanned path.
+    pub file: PathBuf,
+    pub error: String,
+}
+
+/// Call `f` with the relative name, full name and metadata of every file
+/// and symlink under `path`, in file-name order. An error from `f` or from
+/// reading a directory below `path` is recorded and the walk goes on; only
+/// `path` itself being unreadable fails it.
+pub fn visit(
+    path: &Path,
+    mut f: impl FnMut(&Path, &Path, &fs::Metadata) -> io::Result<()>,
+) -> Result<Vec<Unreadable>> {
+    fs::symlink_metadata(path).with_context(|| format!("Cannot read {}", path.display()))?;
+    let mut unreadable = Vec::new();
+    let mut stack = vec![PathBuf::new()];
+    while let Some(rel) = stack.pop() {
+        let full = if rel.as_os_str().is_empty() {
+            path.to_path_buf()
+        } else {
+            path.join(&rel)
+        };
+        let result = fs::symlink_metadata(&full).and_then(|meta| {
+            if !meta.is_dir() {
+                return f(&rel, &full, &meta);
+            }
+            let mut names = fs::read_dir(&full)?
+                .map(|e| e.map(|e| e.file_name()))
+                .collect::<io::Result<Vec<_>>>()?;
+            names.sort();
+            stack.extend(names.iter().rev().map(|n| rel.join(n)));
+            Ok(())
+        });
+        if let Err(e) = result {
+            unreadable.push(Unreadable {
+                file: rel,
+                error: e.to_string(),
+            });
+        }
+    }
+    Ok(unreadable)
+}
+
+/// Call `f` with the relative name and contents of every file under `path`,
+/// and with the target of every symlink (`true` as the last argument), in
+/// file-name order. Files are read `CHUNK` bytes at a time, each chunk
+/// passed with the last `HASH_LEN - 1` bytes of the one before it, so every
+/// hash is seen whole exactly once.
+pub fn walk(path: &Path, mut f: impl FnMut(&Path, &[u8], bool)) -> Result<Vec<Unreadable>> {
+    let mut buf = Vec::with_capacity(CHUNK + HASH_LEN);
+    visit(path, |rel, full, meta| {
+        if meta.is_symlink() {
+            let target = fs::read_link(full)?;
+            f(rel, target.as_os_str().as_encoded_bytes(), true);
+            return Ok(());
+        }
+        let mut file = fs::File::open(full)?;
+        buf.clear();
+        loop {
+            let overlap = buf.len().min(HASH_LEN - 1);
+            buf.drain(..buf.len() - overlap);
+            if file.by_ref().take(CHUNK as u64).read_to_end(&mut buf)? == 0 {
+                return Ok(());
+            }
+            f(rel, &buf, false);
+        }
+    })
+}
+
+fn find<'a>(haystack: &'a [u8], needle: &'a [u8]) -> impl Iterator<Item = usize> + 'a {
+    haystack
+        .windows(needle.len())
+        .enumerate()
+        .filter(move |(_, w)| *w == needle)
+        .map(|(i, _)| i)
+}
+
+fn snippet(bytes: &[u8], at: usize, len: usize) -> String {
+    let start = at.saturating_sub(MARGIN);
+    let end = (at + len + MARGIN).min(bytes.len());
+    bytes[start..end]
+        .iter()
+        .map(|&b| {
+            if b.is_ascii_graphic() || b == b' ' {
+                b as char
+            } else {
+                '.'
+            }
+        })
+        .collect()
+}
+
+/// The report for the why-depends modal: for each reference along `chain`,
+/// the files of the referrer that mention the referenced path.
+pub fn lines(graph: &StorePathGraph, chain: &[PathId]) -> Vec<String> {
+    let mut lines = Vec::new();
+    for edge in chain.windows(2) {
+        let (from, to) = (graph.path(edge[0]), graph.path(edge[1]));
+        if !lines.is_empty() {
+            lines.push(String::new());
+        }
+        lines.push(format!("{} → {}", from.short_name(), to.short_name()));
+        let hash = hash_of(graph, edge[1]);
+        let (hits, unreadable) = match scan(Path::new(&from.path), hash) {
+            Ok(scan) => scan,
+            Err(e) => {
+                lines.push(format!("  {e:#}"));
+                continue;
+            }
+        };
+        if hits.is_empty() {
+            lines.push(format!("  no file mentions {hash}"));
+        }
+        lines.extend(hits.iter().map(|hit| {
+            let arrow = if hit.symlink { " ->" } else { ":" };
+            format!("  {}{arrow} {}", display(&hit.file), hit.snippet)
+        }));
+        lines.extend(
+            unreadable
+                .iter()
+                .map(|u| format!("  {}: cannot read: {}", display(&u.file), u.error)),
+        );
+    }
+    lines
+}
+
+/// A file name relative to a store path, `.` for the path itself.
+pub(crate) fn display(file: &Path) -> String {
+    if file.as_os_str().is_empty() {
+        ".".into()
+    } else {
+        file.display().to_string()
+    }
+}
+
+/// The hash part of a path's base name, which is what its referrers embed.
+pub(crate) fn hash_of(graph: &StorePathGraph, id: PathId) -> &str {
+    let p = graph.path(id);
+    graph
+        .base_name(&p.path)
+        .split_once('-')
+        .map_or(p.hash.as_str(), |(hash, _)| hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store_path::testing::TestGraph;
+
+    const LIB_HASH: &str = "1b9p07z77phvv2hf6gm9f28syp39f1ag";
+
+    #[test]
+    fn finds_hash_in_files_and_symlinks() {
+        let store = tempfile::tempdir().unwrap();
+        let lib = store.path().join(format!("{LIB_HASH}-lib"));
+        let app = store.path().join(format!("{:a<32}-app", ""));
+        fs::create_dir_all(lib.join("lib")).unwrap();
+        fs::write(lib.join("lib/libfoo.so"), b"\x7fELF").unwrap();
+        fs::create_dir_all(app.join("bin")).unwrap();
+        fs::create_dir_all(app.join("share/doc")).unwrap();
+        let mut binary = b"\x7fELF\x00\x01".to_vec();
+        binary.extend_from_slice(lib.join("lib/libfoo.so").to_str().unwrap().as_bytes());
+        binary.extend_from_slice(b"\x00\x00");
+        fs::write(app.join("bin/app"), &binary).unwrap();
+        fs::write(app.join("share/doc/README"), "no references here").unwrap();
+        std::os::unix::fs::symlink(lib.join("lib"), app.join("lib")).unwrap();
+
+        let (hits, unreadable) = scan(&app, LIB_HASH).unwrap();
+        assert_eq!(unreadable, []);
+        let files: Vec<(&Path, bool)> =
+            hits.iter().map(|h| (h.file.as_path(), h.symlink)).collect();
+        assert_eq!(
+            files,
+            [(Path::new("bin/app"), false), (Path::new("lib"), true)]
+        );
+        assert!(hits[0].snippet.starts_with(".ELF.."), "{}", hits[0].snippet);
+        assert!(hits[0].snippet.contains(LIB_HASH));
+        assert!(hits[0].snippet.ends_with("-lib/lib/libfoo.so.."));
+
+        // A store path that is a single file.
+        let script = store.path().join(format!("{:b<32}-script", ""));
+        fs::write(&script, format!("exec {}/bin/x", lib.display())).unwrap();
+        assert_eq!(scan(&script, LIB_HASH).unwrap().0[0].file, Path::new(""));
+
+        let path = |p: &Path| p.to_str().unwrap().to_string();
+        let mut graph = TestGraph::new(&path(store.path()));
+        graph
+            .add(&path(&app), 0, &[path(&lib)])
+            .add(&path(&lib), 0, &[]);
+        let graph = graph.build(&[]);
+        let report = lines(&graph, &[0, 1]);
+        assert_eq!(report[0], "app → lib");
+        assert!(report[1].starts_with("  bin/app: .ELF.."), "{report:#?}");
+        assert!(report[2].starts_with("  lib -> /"), "{report:#?}");
+
+        let report = lines(&graph, &[1, 0]);
+        assert_eq!(report[1], format!("  no file mentions {:a<32}", ""));
+
+        // Paths that are not on disk, e.g. from a binary cache.
+        fs::remove_dir_all(&app).unwrap();
+        let report = lines(&graph, &[0, 1]);
+        assert!(report[1].starts_with("  Cannot read "), "{report:#?}");
+    }
+
+    #[test]
+    fn streams_files_in_overlapping_chunks() {
+        let store = tempfile::tempdir().unwrap();
+        let dir = store.path().join(format!("{:a<32}-app", ""));
+        fs::create_dir_all(&dir).unwrap();
+        // One hash across the first chunk boundary, one right after it and
+        // one at the end of the file.
+        let mut bytes = vec![b'.'; 2 * CHUNK + 100];
+        for at in [CHUNK - 10, CHUNK + 40, bytes.len() - HASH_LEN] {
+            bytes[at..at + HASH_LEN].copy_from_slice(LIB_HASH.as_bytes());
+        }
+        fs::write(dir.join("big"), &bytes).unwrap();
+        // Opening a socket fails; the files after it are still scanned.
+        std::os::unix::net::UnixListener::bind(dir.join("a-socket")).unwrap();
+        fs::write(dir.join("z"), LIB_HASH).unwrap();
+
+        let (hits, unreadable) = scan(&dir, LIB_HASH).unwrap();
+        let files: Vec<&Path> = hits.iter().map(|h| h.file.as_path()).collect();
+        assert_eq!(files, ["big", "big", "big", "z"].map(Path::new));
+        assert_eq!(unreadable.len(), 1);
+        assert_eq!(unreadable[0].file, Path::new("a-socket"));
+    }
+}
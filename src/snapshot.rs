@@ -0,0 +1,110 @@
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::store_path::StorePathGraph;
+
+/// Bumped whenever the on-disk layout changes incompatibly.
+const VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct Snapshot<G> {
+    version: u32,
+    graph: G,
+}
+
+/// Serialize a loaded graph (paths, references, sizes, signatures, roots) so
+/// it can be browsed later on a machine without the store.
+pub fn write(graph: &StorePathGraph, out: impl Write) -> Result<()> {
+    serde_json::to_writer(
+        out,
+        &Snapshot {
+            version: VERSION,
+            graph,
+        },
+    )
+    .context("Failed to write snapshot")
+}
+
+pub fn read(input: impl Read) -> Result<StorePathGraph> {
+    #[derive(Deserialize)]
+    struct Header {
+        version: u32,
+    }
+    let value: serde_json::Value =
+        serde_json::from_reader(input).context("Snapshot is not valid JSON")?;
+    let header = Header::deserialize(&value).context("Not a nix-tree snapshot")?;
+    if header.version != VERSION {
+        bail!(
+            "Unsupported snapshot version {} (expected {VERSION})",
+            header.version
+        );
+    }
+    let snapshot: Snapshot<StorePathGraph> =
+        serde_json::from_value(value).context("Malformed snapshot")?;
+    Ok(snapshot.graph)
+}
+
+pub fn save(graph: &StorePathGraph, path: &Path) -> Result<()> {
+    let file =
+        File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    let mut out = BufWriter::new(file);
+    write(graph, &mut out)?;
+    out.flush()?;
+    Ok(())
+}
+
+pub fn load(path: &Path) -> Result<StorePathGraph> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    read(BufReader::new(file)).with_context(|| format!("Failed to load {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::store_path::testing::{p, store_path};
+    use crate::store_path::{StorePath, StorePathGraph};
+
+    #[test]
+    fn round_trip_rebuilds_index() {
+        let mut graph = StorePathGraph::new();
+        let app_refs = vec![p("lib"), p("app"), p("missing")];
+        for (name, refs) in [("app", app_refs), ("lib", vec![])] {
+            graph.add_path(StorePath {
+                signatures: vec!["cache.example.org-1:c2ln".to_string()],
+                registration_time: Some(1_700_000_000),
+                ultimate: Some(true),
+                ..store_path(&p(name), 42, &refs)
+            });
+        }
+        graph.roots = vec![p("app")];
+
+        let mut buf = Vec::new();
+        super::write(&graph, &mut buf).unwrap();
+        let loaded = super::read(buf.as_slice()).unwrap();
+
+        assert_eq!(loaded.roots, graph.roots);
+        assert_eq!(loaded.paths(), graph.paths());
+        assert_eq!(loaded.id_of(&p("lib")), Some(1));
+        assert_eq!(loaded.get_references(&p("app"))[0].name, "lib");
+
+        // References are written back out, sorted, self and missing ones
+        // included.
+        let json: serde_json::Value = serde_json::from_slice(&buf).unwrap();
+        assert_eq!(
+            json["graph"]["paths"][0]["references"],
+            serde_json::json!([p("app"), p("lib"), p("missing")])
+        );
+        let mut again = Vec::new();
+        super::write(&loaded, &mut again).unwrap();
+        assert_eq!(again, buf);
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let err = super::read(&br#"{"version": 99, "graph": {}}"#[..]).unwrap_err();
+        assert!(err.to_string().contains("version 99"), "{err}");
+        assert!(super::read(&b"[]"[..]).is_err());
+    }
+}
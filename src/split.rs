@@ -0,0 +1,359 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::path_stats::AddedSize;
+use crate::precise::{Unreadable, display, hash_of, walk};
+use crate::store_path::{HASH_LEN, NIX32, PathId, StorePathGraph};
+
+/// What a file inside a store path is, as far as splitting outputs goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum FileKind {
+    Doc,
+    Man,
+    Include,
+    PkgConfig,
+    Cmake,
+    Bin,
+    SharedLib,
+    Other,
+}
+
+impl FileKind {
+    /// By the file's name relative to the store path.
+    pub fn classify(file: &Path) -> Self {
+        let file = file.to_string_lossy();
+        let under = |dir: &str| file.starts_with(dir);
+        let name = file.rsplit('/').next().unwrap_or(&file);
+        if under("share/doc/") || under("doc/") {
+            Self::Doc
+        } else if under("share/man/") || under("man/") {
+            Self::Man
+        } else if under("include/") {
+            Self::Include
+        } else if under("lib/pkgconfig/") || under("share/pkgconfig/") {
+            Self::PkgConfig
+        } else if name.ends_with(".cmake") || under("lib/cmake/") || under("share/cmake/") {
+            Self::Cmake
+        } else if under("bin/") || under("sbin/") {
+            Self::Bin
+        } else if under("lib/") && (name.ends_with(".so") || name.contains(".so.")) {
+            Self::SharedLib
+        } else {
+            Self::Other
+        }
+    }
+
+    /// The output the file would move to; `None` for what the package needs
+    /// at run time.
+    pub fn output(self) -> Option<&'static str> {
+        match self {
+            Self::Doc => Some("doc"),
+            Self::Man => Some("man"),
+            Self::Include | Self::PkgConfig | Self::Cmake => Some("dev"),
+            Self::Bin | Self::SharedLib | Self::Other => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Doc => "share/doc",
+            Self::Man => "share/man",
+            Self::Include => "include",
+            Self::PkgConfig => "lib/pkgconfig",
+            Self::Cmake => "cmake",
+            Self::Bin => "bin",
+            Self::SharedLib => "lib/*.so",
+            Self::Other => "other",
+        }
+    }
+}
+
+/// One reference of the analysed path and the files that hold it.
+#[derive(Debug, Clone)]
+pub struct Reference {
+    pub path: PathId,
+    /// In file-name order; empty if no file spells out the hash (nix still
+    /// records it, e.g. from the build's declared references).
+    pub files: Vec<(PathBuf, FileKind)>,
+    /// `AddedSize` of the reference within the analysed path's closure,
+    /// for references `split_outputs` has an answer for; 0 otherwise.
+    pub saving: u64,
+}
+
+impl Reference {
+    /// The outputs that would take the reference along, if every file that
+    /// holds it is documentation or development material.
+    pub fn split_outputs(&self) -> Option<Vec<&'static str>> {
+        if self.files.is_empty() {
+            return None;
+        }
+        let mut outputs = Vec::new();
+        for (_, kind) in &self.files {
+            let output = kind.output()?;
+            if !outputs.contains(&output) {
+                outputs.push(output);
+            }
+        }
+        outputs.sort_unstable();
+        Some(outputs)
+    }
+
+    fn kinds(&self) -> Vec<FileKind> {
+        let mut kinds: Vec<FileKind> = self.files.iter().map(|(_, k)| *k).collect();
+        kinds.sort_unstable();
+        kinds.dedup();
+        kinds
+    }
+}
+
+/// Where the references of one path live, and which of them only
+/// documentation or development files hold.
+#[derive(Debug, Clone)]
+pub struct Advice {
+    pub path: PathId,
+    /// In the order of the path's references.
+    pub references: Vec<Reference>,
+    /// Files that could not be scanned; references only they hold are
+    /// missed.
+    pub unreadable: Vec<Unreadable>,
+}
+
+impl Advice {
+    /// Scan the files of `path` on disk for the hashes of its references.
+    pub fn new(graph: &StorePathGraph, path: PathId) -> Result<Self> {
+        let refs = graph.references_of(path);
+        let by_hash: HashMap<&[u8], usize> = refs
+            .iter()
+            .enumerate()
+            .map(|(i, &r)| (hash_of(graph, r).as_bytes(), i))
+            .collect();
+        let mut files = vec![Vec::new(); refs.len()];
+        let unreadable = walk(Path::new(&graph.path(path).path), |file, bytes, _| {
+            // Called once per chunk, in file order: a file is only recorded
+            // for a reference if it was not the last one recorded.
+            for_each_hash(bytes, |hash| {
+                if let Some(&i) = by_hash.get(hash)
+                    && files[i].last().is_none_or(|(f, _)| f != file)
+                {
+                    files[i].push((file.to_path_buf(), FileKind::classify(file)));
+                }
+            });
+        })?;
+
+        let mut added = AddedSize::new(graph);
+        let references = refs
+            .iter()
+            .zip(files)
+            .map(|(&r, files)| {
+                let mut reference = Reference {
+                    path: r,
+                    files,
+                    saving: 0,
+                };
+                if reference.split_outputs().is_some() {
+                    reference.saving = added.for_path(r, &[path]);
+                }
+                reference
+            })
+            .collect();
+        Ok(Self {
+            path,
+            references,
+            unreadable,
+        })
+    }
+
+    /// References only documentation or development files hold, largest
+    /// saving first.
+    pub fn splittable(&self) -> Vec<&Reference> {
+        let mut refs: Vec<&Reference> = self
+            .references
+            .iter()
+            .filter(|r| r.split_outputs().is_some())
+            .collect();
+        refs.sort_by_key(|r| std::cmp::Reverse(r.saving));
+        refs
+    }
+}
+
+/// Call `f` with every window of `bytes` that looks like a store path hash.
+/// Like nix's reference scanner, a byte outside the base-32 alphabet skips
+/// every window containing it.
+fn for_each_hash(bytes: &[u8], mut f: impl FnMut(&[u8])) {
+    let mut i = 0;
+    while i + HASH_LEN <= bytes.len() {
+        let window = &bytes[i..i + HASH_LEN];
+        match window.iter().rposition(|b| !NIX32.as_bytes().contains(b)) {
+            Some(bad) => i += bad + 1,
+            None => {
+                f(window);
+                i += 1;
+            }
+        }
+    }
+}
+
+/// The report for `--split` and the TUI overlay.
+pub fn lines(graph: &StorePathGraph, advice: &Advice) -> Vec<String> {
+    let name = |p: PathId| graph.path(p).short_name();
+    let splittable = advice.splittable();
+    let mut lines = vec![format!(
+        "{}: {} references, {} only held by documentation or development files",
+        name(advice.path),
+        advice.references.len(),
+        splittable.len()
+    )];
+    lines.push(String::new());
+    if splittable.is_empty() {
+        lines.push("  Nothing to split off".to_string());
+    }
+    for r in &splittable {
+        let mut files: Vec<String> = r
+            .files
+            .iter()
+            .take(3)
+            .map(|(f, _)| f.display().to_string())
+            .collect();
+        if r.files.len() > 3 {
+            files.push(format!("+{} more", r.files.len() - 3));
+        }
+        lines.push(format!(
+            "{:>12}  {} → {}: {}",
+            format!("-{}", bytesize::ByteSize(r.saving)),
+            name(r.path),
+            r.split_outputs().unwrap_or_default().join(", "),
+            files.join(", ")
+        ));
+    }
+
+    lines.push(String::new());
+    lines.push("References:".to_string());
+    for r in &advice.references {
+        let kinds: Vec<&str> = r.kinds().into_iter().map(FileKind::as_str).collect();
+        let held_by = if kinds.is_empty() {
+            "no file".to_string()
+        } else {
+            kinds.join(", ")
+        };
+        lines.push(format!("  {held_by:<28} {}", name(r.path)));
+    }
+    if !advice.unreadable.is_empty() {
+        lines.push(String::new());
+        lines.push("Not scanned:".to_string());
+        for u in &advice.unreadable {
+            lines.push(format!("  {}: {}", display(&u.file), u.error));
+        }
+    }
+    lines
+}
+
+pub fn write(graph: &StorePathGraph, advice: &Advice, out: &mut impl Write) -> io::Result<()> {
+    for line in lines(graph, advice) {
+        writeln!(out, "{line}")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store_path::testing::TestGraph;
+    use std::fs;
+
+    #[test]
+    fn classifies_files() {
+        let kind = |f: &str| FileKind::classify(Path::new(f));
+        assert_eq!(kind("share/doc/foo/README"), FileKind::Doc);
+        assert_eq!(kind("share/man/man1/foo.1.gz"), FileKind::Man);
+        assert_eq!(kind("include/foo.h"), FileKind::Include);
+        assert_eq!(kind("lib/pkgconfig/foo.pc"), FileKind::PkgConfig);
+        assert_eq!(kind("lib/cmake/Foo/FooConfig.cmake"), FileKind::Cmake);
+        assert_eq!(kind("share/foo/FindFoo.cmake"), FileKind::Cmake);
+        assert_eq!(kind("bin/foo"), FileKind::Bin);
+        assert_eq!(kind("lib/libfoo.so.1.2"), FileKind::SharedLib);
+        assert_eq!(kind("lib/libfoo.a"), FileKind::Other);
+        assert_eq!(kind("etc/foo.conf"), FileKind::Other);
+    }
+
+    #[test]
+    fn finds_hashes_like_nix() {
+        let mut found = Vec::new();
+        let text = format!("/nix/store/{:a<32}-x:{:b<31}!", "", "");
+        for_each_hash(text.as_bytes(), |h| found.push(h.to_vec()));
+        assert_eq!(found, [b"a".repeat(32)]);
+    }
+
+    #[test]
+    fn advises_splitting_dev_and_doc_references() {
+        let store = tempfile::tempdir().unwrap();
+        let mut graph = TestGraph::new(store.path().to_str().unwrap());
+        for (c, name, size, refs) in [
+            (
+                'l',
+                "libfoo",
+                1,
+                vec![
+                    graph.path('g', "glibc"),
+                    graph.path('p', "python3"),
+                    graph.path('x', "groff"),
+                ],
+            ),
+            ('g', "glibc", 100, vec![]),
+            ('p', "python3", 1000, vec![graph.path('g', "glibc")]),
+            ('x', "groff", 50, vec![]),
+        ] {
+            graph.add(&graph.path(c, name), size, &refs);
+        }
+        let lib = graph.path('l', "libfoo");
+        for dir in ["lib/pkgconfig", "share/man/man1"] {
+            fs::create_dir_all(format!("{lib}/{dir}")).unwrap();
+        }
+        fs::write(
+            format!("{lib}/lib/libfoo.so.1"),
+            format!("\0{}/lib/libc.so.6\0", graph.path('g', "glibc")),
+        )
+        .unwrap();
+        fs::write(
+            format!("{lib}/lib/pkgconfig/foo.pc"),
+            format!("python={}/bin/python3\n", graph.path('p', "python3")),
+        )
+        .unwrap();
+        fs::write(
+            format!("{lib}/share/man/man1/foo.1"),
+            // Mentioned twice, in different chunks of the walk.
+            format!(
+                ".\\\" {groff}/bin/groff\n{}{groff}/bin/groff\n",
+                ".".repeat(100_000),
+                groff = graph.path('x', "groff")
+            ),
+        )
+        .unwrap();
+
+        let graph = graph.build(&[]);
+        let advice = Advice::new(&graph, 0).unwrap();
+        let splittable: Vec<(PathId, Vec<&str>, u64)> = advice
+            .splittable()
+            .iter()
+            .map(|r| (r.path, r.split_outputs().unwrap(), r.saving))
+            .collect();
+        // glibc stays: the library needs it, and python3 refers to it too.
+        assert_eq!(splittable, [(2, vec!["dev"], 1000), (3, vec!["man"], 50)]);
+
+        let lines = lines(&graph, &advice);
+        assert_eq!(
+            lines[0],
+            "libfoo: 3 references, 2 only held by documentation or development files"
+        );
+        assert_eq!(
+            lines[2],
+            "     -1000 B  python3 → dev: lib/pkgconfig/foo.pc"
+        );
+        assert!(
+            lines.contains(&format!("  {:<28} glibc", "lib/*.so")),
+            "{lines:#?}"
+        );
+        assert_eq!(advice.references[2].files.len(), 1);
+    }
+}
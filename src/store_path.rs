@@ -1,9 +1,86 @@
-use anyhow::{Result, bail};
-use serde::{Deserialize, Serialize};
+use hashbrown::HashTable;
+use hashbrown::hash_table::Entry;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::HashMap;
 use std::fmt;
+use std::hash::{BuildHasher, RandomState};
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+/// Store directory when nothing says otherwise (`--store-dir`,
+/// `NIX_STORE_DIR`, or the paths a backend hands back).
+pub const DEFAULT_STORE_DIR: &str = "/nix/store";
+
+/// nix's base-32 alphabet: digits and lowercase letters minus `e`, `o`, `u`
+/// and `t`.
+pub(crate) const NIX32: &str = "0123456789abcdfghijklmnpqrsvwxyz";
+pub(crate) const HASH_LEN: usize = 32;
+/// nix refuses names that would push the base name past 255 bytes.
+const MAX_NAME_LEN: usize = 211;
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "+-._?=".contains(c)
+}
+
+/// Why a string is not a store path, precise enough to point at the offending
+/// character.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StorePathParseError {
+    /// Not of the form `<absolute store dir>/<hash>-<name>`, or not in the
+    /// expected store directory.
+    BadPrefix {
+        path: String,
+    },
+    BadHashLength {
+        len: usize,
+    },
+    /// `position` is a byte offset into the hash.
+    BadHashChar {
+        ch: char,
+        position: usize,
+    },
+    EmptyName,
+    NameTooLong {
+        len: usize,
+    },
+    /// `position` is a byte offset into the name.
+    BadNameChar {
+        ch: char,
+        position: usize,
+    },
+}
+
+impl fmt::Display for StorePathParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadPrefix { path } => write!(f, "Invalid store path: {path}"),
+            Self::BadHashLength { len } => {
+                write!(
+                    f,
+                    "Invalid store path hash length: {len} (expected {HASH_LEN})"
+                )
+            }
+            Self::BadHashChar { ch, position } => write!(
+                f,
+                "Invalid character {ch:?} at position {position} of store path hash"
+            ),
+            Self::EmptyName => write!(f, "Store path has an empty name"),
+            Self::NameTooLong { len } => write!(
+                f,
+                "Store path name is {len} bytes long (at most {MAX_NAME_LEN} allowed)"
+            ),
+            Self::BadNameChar { ch, position } => write!(
+                f,
+                "Invalid character {ch:?} at position {position} of store path name"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for StorePathParseError {}
+
+/// One store path and its path info. Everything after `signatures` is only as
+/// complete as the backend that produced it: all of them are optional in nix's
+/// own JSON, and snapshots written before they existed lack them entirely.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct StorePath {
     pub path: String,
     pub hash: String,
@@ -11,31 +88,87 @@ pub struct StorePath {
     pub nar_size: u64,
     pub references: Vec<String>,
     pub signatures: Vec<String>,
+    /// Full store path of the `.drv` that built this path.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deriver: Option<String>,
+    /// Seconds since the epoch at which the path became valid locally.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub registration_time: Option<u64>,
+    /// As nix prints it, e.g. `sha256-…` or `sha256:…`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nar_hash: Option<String>,
+    /// Content address (`fixed:…`, `text:…`) for content-addressed paths.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca: Option<String>,
+    /// Built locally (`true`) or substituted (`false`); `None` where the
+    /// source does not say, e.g. a binary cache or an older nix.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ultimate: Option<bool>,
+    /// Compressed NAR size in the binary cache the path was queried from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub download_size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub closure_download_size: Option<u64>,
 }
 
 impl StorePath {
-    pub fn parse(path: &str) -> Result<(String, String)> {
+    /// Split `<store dir>/<hash>-<name>` into hash and name. Any absolute
+    /// store directory is accepted; the graph records which one it was.
+    pub fn parse(path: &str) -> Result<(String, String), StorePathParseError> {
         let path = path.trim();
+        let bad_prefix = || StorePathParseError::BadPrefix {
+            path: path.to_string(),
+        };
 
-        if !path.starts_with("/nix/store/") {
-            bail!("Invalid store path: {path}");
+        let (dir, base_name) = path.rsplit_once('/').ok_or_else(bad_prefix)?;
+        if !dir.starts_with('/') {
+            return Err(bad_prefix());
         }
 
-        let without_prefix = path.strip_prefix("/nix/store/").unwrap();
-        let parts: Vec<&str> = without_prefix.splitn(2, '-').collect();
+        // The hash is fixed-width, so anything short of `<32 chars>-` is a
+        // length problem rather than a missing name.
+        let (hash, name) = match base_name.split_once('-') {
+            Some((hash, name)) => (hash, name),
+            None => (base_name, ""),
+        };
+        if hash.len() != HASH_LEN {
+            return Err(StorePathParseError::BadHashLength { len: hash.len() });
+        }
+        if let Some((position, ch)) = hash.char_indices().find(|(_, c)| !NIX32.contains(*c)) {
+            return Err(StorePathParseError::BadHashChar { ch, position });
+        }
 
-        if parts.len() != 2 {
-            bail!("Invalid store path format: {path}");
+        if name.is_empty() {
+            return Err(StorePathParseError::EmptyName);
+        }
+        if name.len() > MAX_NAME_LEN {
+            return Err(StorePathParseError::NameTooLong { len: name.len() });
+        }
+        if let Some((position, ch)) = name.char_indices().find(|(_, c)| !is_name_char(*c)) {
+            return Err(StorePathParseError::BadNameChar { ch, position });
         }
 
-        let hash = parts[0].to_string();
-        let name = parts[1].to_string();
+        Ok((hash.to_string(), name.to_string()))
+    }
 
-        if hash.len() != 32 {
-            bail!("Invalid store path hash length: {hash}");
+    /// `parse`, but also requiring the path to live directly in `store_dir`.
+    pub fn parse_in(path: &str, store_dir: &str) -> Result<(String, String), StorePathParseError> {
+        let in_dir = path
+            .trim()
+            .strip_prefix(store_dir)
+            .and_then(|rest| rest.strip_prefix('/'))
+            .is_some_and(|base_name| !base_name.contains('/'));
+        if !in_dir {
+            return Err(StorePathParseError::BadPrefix {
+                path: path.trim().to_string(),
+            });
         }
+        Self::parse(path)
+    }
 
-        Ok((hash, name))
+    /// The directory this path lives in, e.g. `/nix/store`.
+    pub fn store_dir(&self) -> &str {
+        self.path.rsplit_once('/').map_or("", |(dir, _)| dir)
     }
 
     pub fn short_name(&self) -> &str {
@@ -53,11 +186,202 @@ impl fmt::Display for StorePath {
     }
 }
 
+/// A path as a `StorePathGraph` holds it: the `StorePath` without its
+/// references, which the graph keeps as ids (`references_of`) and spells out
+/// on request (`references`).
+#[derive(Debug, Clone, Default, Serialize, PartialEq, Eq, Hash)]
+pub struct PathInfo {
+    pub path: String,
+    pub hash: String,
+    pub name: String,
+    pub nar_size: u64,
+    pub signatures: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deriver: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub registration_time: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nar_hash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ca: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ultimate: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub download_size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub closure_download_size: Option<u64>,
+}
+
+impl PathInfo {
+    /// The directory this path lives in, e.g. `/nix/store`.
+    pub fn store_dir(&self) -> &str {
+        self.path.rsplit_once('/').map_or("", |(dir, _)| dir)
+    }
+
+    pub fn short_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_signed(&self) -> bool {
+        !self.signatures.is_empty()
+    }
+}
+
+impl fmt::Display for PathInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path)
+    }
+}
+
+impl From<StorePath> for (PathInfo, Vec<String>) {
+    fn from(path: StorePath) -> Self {
+        let StorePath {
+            path,
+            hash,
+            name,
+            nar_size,
+            references,
+            signatures,
+            deriver,
+            registration_time,
+            nar_hash,
+            ca,
+            ultimate,
+            download_size,
+            closure_download_size,
+        } = path;
+        let info = PathInfo {
+            path,
+            hash,
+            name,
+            nar_size,
+            signatures,
+            deriver,
+            registration_time,
+            nar_hash,
+            ca,
+            ultimate,
+            download_size,
+            closure_download_size,
+        };
+        (info, references)
+    }
+}
+
+/// Dense index of a path in `StorePathGraph::paths`. Everything downstream of
+/// loading (stats, the TUI's lists and history, the graph's own references)
+/// refers to paths by id, so each path string is held once, by its
+/// `PathInfo` in the graph.
+pub type PathId = u32;
+
+/// Paths interned by their store path: the index finds an id by hashing the
+/// path strings in place, and references are kept as ids, resolved as paths
+/// are added.
 #[derive(Debug, Clone)]
 pub struct StorePathGraph {
-    pub paths: Vec<StorePath>,
+    paths: Vec<PathInfo>,
     pub roots: Vec<String>,
-    index: HashMap<String, usize>,
+    pub store_dir: String,
+    /// Ids, hashed by `paths[id].path`.
+    index: HashTable<PathId>,
+    hasher: RandomState,
+    /// Per path, what it refers to other than itself, in the order it lists
+    /// them.
+    references: Vec<Vec<PathId>>,
+    self_references: Vec<bool>,
+    /// References to paths not in the graph (yet), by path: the referrer and
+    /// where in its list the reference was.
+    dangling: HashMap<String, Vec<(PathId, u32)>>,
+    /// For referrers with dangling references, the list positions of those
+    /// already resolved, so a late arrival is put in its place; and how many
+    /// are still dangling.
+    positions: HashMap<PathId, (Vec<u32>, usize)>,
+}
+
+/// Serialized form of `StorePathGraph`; the index and ids are derived, so
+/// they are rebuilt on load rather than stored.
+#[derive(Deserialize)]
+struct GraphData {
+    paths: Vec<StorePath>,
+    roots: Vec<String>,
+    /// Absent from snapshots written before it was recorded.
+    #[serde(default = "default_store_dir")]
+    store_dir: String,
+}
+
+fn default_store_dir() -> String {
+    DEFAULT_STORE_DIR.to_string()
+}
+
+impl From<GraphData> for StorePathGraph {
+    fn from(data: GraphData) -> Self {
+        let mut graph = StorePathGraph::new();
+        for path in data.paths {
+            graph.add_path(path);
+        }
+        graph.roots = data.roots;
+        graph.store_dir = data.store_dir;
+        graph
+    }
+}
+
+impl<'de> Deserialize<'de> for StorePathGraph {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        GraphData::deserialize(deserializer).map(Self::from)
+    }
+}
+
+impl Serialize for StorePathGraph {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct Graph<'a> {
+            paths: Paths<'a>,
+            roots: &'a [String],
+            store_dir: &'a str,
+        }
+        /// The paths with their references spelled out again, one at a time,
+        /// in the shape of a `StorePath`.
+        struct Paths<'a>(&'a StorePathGraph);
+        #[derive(Serialize)]
+        struct Entry<'a> {
+            #[serde(flatten)]
+            info: &'a PathInfo,
+            references: Vec<&'a str>,
+        }
+        impl Serialize for Paths<'_> {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                let graph = self.0;
+                let mut dangling: HashMap<PathId, Vec<&str>> = HashMap::new();
+                for (path, referrers) in &graph.dangling {
+                    for (referrer, _) in referrers {
+                        dangling.entry(*referrer).or_default().push(path);
+                    }
+                }
+                serializer.collect_seq((0..).zip(&graph.paths).map(|(id, p)| {
+                    let mut references: Vec<&str> = graph
+                        .references_of(id)
+                        .iter()
+                        .map(|&r| graph.path(r).path.as_str())
+                        .chain(dangling.remove(&id).into_iter().flatten())
+                        .collect();
+                    if graph.self_references[id as usize] {
+                        references.push(&p.path);
+                    }
+                    references.sort_unstable();
+                    Entry {
+                        info: p,
+                        references,
+                    }
+                }))
+            }
+        }
+        Graph {
+            paths: Paths(self),
+            roots: &self.roots,
+            store_dir: &self.store_dir,
+        }
+        .serialize(serializer)
+    }
 }
 
 impl Default for StorePathGraph {
@@ -71,50 +395,180 @@ impl StorePathGraph {
         Self {
             paths: Vec::new(),
             roots: Vec::new(),
-            index: HashMap::new(),
+            store_dir: default_store_dir(),
+            index: HashTable::new(),
+            hasher: RandomState::new(),
+            references: Vec::new(),
+            self_references: Vec::new(),
+            dangling: HashMap::new(),
+            positions: HashMap::new(),
         }
     }
 
+    /// Add `path`, resolving its references to the paths already added;
+    /// references to paths added later are resolved then.
     pub fn add_path(&mut self, path: StorePath) {
-        self.index.insert(path.path.clone(), self.paths.len());
+        let (path, references): (PathInfo, Vec<String>) = path.into();
+        let id = self.paths.len() as PathId;
+        if let Some(referrers) = self.dangling.remove(path.path.as_str()) {
+            for (referrer, at) in referrers {
+                self.resolve(referrer, at, id);
+            }
+        }
+
+        let mut ids = Vec::with_capacity(references.len());
+        let mut positions = Vec::new();
+        let mut dangling = 0;
+        let mut self_reference = false;
+        for (at, reference) in (0..).zip(references) {
+            if reference == path.path {
+                self_reference = true;
+            } else if let Some(r) = self.id_of(&reference) {
+                ids.push(r);
+                positions.push(at);
+            } else {
+                self.dangling.entry(reference).or_default().push((id, at));
+                dangling += 1;
+            }
+        }
+        if dangling > 0 {
+            self.positions.insert(id, (positions, dangling));
+        }
+
+        let hash = self.hasher.hash_one(path.path.as_str());
+        let paths = &self.paths;
+        match self.index.entry(
+            hash,
+            |&i| paths[i as usize].path == path.path,
+            |&i| self.hasher.hash_one(paths[i as usize].path.as_str()),
+        ) {
+            // Added again: the later one wins, like a map insert.
+            Entry::Occupied(mut entry) => *entry.get_mut() = id,
+            Entry::Vacant(entry) => {
+                entry.insert(id);
+            }
+        }
+        self.references.push(ids);
+        self.self_references.push(self_reference);
         self.paths.push(path);
     }
 
-    pub fn get_path(&self, path: &str) -> Option<&StorePath> {
-        self.index.get(path).map(|&i| &self.paths[i])
+    /// `referrer`'s reference number `at` turned out to be `id`.
+    fn resolve(&mut self, referrer: PathId, at: u32, id: PathId) {
+        let references = &mut self.references[referrer as usize];
+        let Some((positions, dangling)) = self.positions.get_mut(&referrer) else {
+            references.push(id);
+            return;
+        };
+        let i = positions.partition_point(|&p| p < at);
+        positions.insert(i, at);
+        references.insert(i, id);
+        *dangling -= 1;
+        if *dangling == 0 {
+            self.positions.remove(&referrer);
+        }
+    }
+
+    /// Take the store directory from the loaded paths themselves, so a graph
+    /// from a chroot store is named correctly without any configuration.
+    /// Leaves `store_dir` alone for an empty graph.
+    pub fn detect_store_dir(&mut self) {
+        if let Some(p) = self.paths.first() {
+            self.store_dir = p.store_dir().to_string();
+        }
+    }
+
+    /// `<hash>-<name>` as in the path, before `disambiguate_names` touched
+    /// the name.
+    pub fn base_name<'a>(&self, path: &'a str) -> &'a str {
+        path.strip_prefix(self.store_dir.as_str())
+            .and_then(|rest| rest.strip_prefix('/'))
+            .unwrap_or(path)
     }
 
+    /// The name as in the path, before `disambiguate_names` may have
+    /// prefixed a hash slice to it.
+    pub fn original_name<'a>(&self, path: &'a PathInfo) -> &'a str {
+        self.base_name(&path.path)
+            .get(path.hash.len() + 1..)
+            .unwrap_or(&path.name)
+    }
+
+    /// Every path, indexed by id.
+    pub fn paths(&self) -> &[PathInfo] {
+        &self.paths
+    }
+
+    pub fn get_path(&self, path: &str) -> Option<&PathInfo> {
+        self.id_of(path).map(|id| self.path(id))
+    }
+
+    pub fn id_of(&self, path: &str) -> Option<PathId> {
+        let hash = self.hasher.hash_one(path);
+        self.index
+            .find(hash, |&i| self.paths[i as usize].path == path)
+            .copied()
+    }
+
+    /// `id_of`, as an index into `paths()`.
     pub fn index_of(&self, path: &str) -> Option<usize> {
-        self.index.get(path).copied()
+        self.id_of(path).map(|id| id as usize)
+    }
+
+    /// The path with id `id`; panics if it is not from this graph.
+    pub fn path(&self, id: PathId) -> &PathInfo {
+        &self.paths[id as usize]
+    }
+
+    /// The paths `id` refers to, except itself and any not in the graph.
+    pub fn references_of(&self, id: PathId) -> &[PathId] {
+        &self.references[id as usize]
     }
 
-    pub fn get_references(&self, path: &str) -> Vec<&StorePath> {
-        if let Some(store_path) = self.get_path(path) {
-            store_path
-                .references
+    /// Ids of the roots that are in the graph, in order.
+    pub fn root_ids(&self) -> Vec<PathId> {
+        self.roots.iter().filter_map(|r| self.id_of(r)).collect()
+    }
+
+    /// The store paths `path` refers to, as `references_of` has them.
+    pub fn references(&self, path: &str) -> Vec<&str> {
+        self.get_references(path)
+            .into_iter()
+            .map(|p| p.path.as_str())
+            .collect()
+    }
+
+    pub fn get_references(&self, path: &str) -> Vec<&PathInfo> {
+        self.id_of(path).map_or_else(Vec::new, |id| {
+            self.references_of(id)
                 .iter()
-                .filter(|ref_path| ref_path != &path) // Filter out self-references
-                .filter_map(|ref_path| self.get_path(ref_path))
+                .map(|&r| self.path(r))
                 .collect()
-        } else {
-            Vec::new()
-        }
+        })
     }
 
-    /// Reverse adjacency list (path → referrers), built in O(V + E).
+    /// Reverse adjacency list (referrers per id), built in O(V + E).
     /// Replaces a per-path linear scan that dominated startup on large closures.
-    pub fn build_referrers(&self) -> HashMap<String, Vec<String>> {
-        let mut referrers: HashMap<String, Vec<String>> = HashMap::with_capacity(self.paths.len());
-        for p in &self.paths {
-            for r in &p.references {
-                if r != &p.path {
-                    referrers.entry(r.clone()).or_default().push(p.path.clone());
-                }
+    pub fn build_referrer_ids(&self) -> Vec<Vec<PathId>> {
+        let mut referrers = vec![Vec::new(); self.paths.len()];
+        for id in 0..self.paths.len() as PathId {
+            for &r in self.references_of(id) {
+                referrers[r as usize].push(id);
             }
         }
         referrers
     }
 
+    /// `build_referrer_ids` by store path, for paths that have referrers.
+    pub fn build_referrers(&self) -> HashMap<String, Vec<String>> {
+        let path = |id: PathId| self.path(id).path.clone();
+        (0..)
+            .zip(self.build_referrer_ids())
+            .filter(|(_, referrers)| !referrers.is_empty())
+            .map(|(id, referrers)| (path(id), referrers.into_iter().map(path).collect()))
+            .collect()
+    }
+
     pub fn disambiguate_names(&mut self) {
         let mut name_counts: std::collections::HashMap<String, usize> =
             std::collections::HashMap::new();
@@ -130,3 +584,198 @@ impl StorePathGraph {
         }
     }
 }
+
+/// Fixtures for tests and benchmarks: made-up store paths and graphs of them.
+#[doc(hidden)]
+pub mod testing {
+    use super::{DEFAULT_STORE_DIR, StorePath, StorePathGraph};
+
+    /// `/nix/store/<a × 32>-<name>`.
+    pub fn p(name: &str) -> String {
+        ph('a', name)
+    }
+
+    /// `/nix/store/<hash × 32>-<name>`, for paths that need hashes of their
+    /// own.
+    pub fn ph(hash: char, name: &str) -> String {
+        path_in(DEFAULT_STORE_DIR, hash, name)
+    }
+
+    /// `<store_dir>/<hash × 32>-<name>`.
+    pub fn path_in(store_dir: &str, hash: char, name: &str) -> String {
+        format!("{store_dir}/{}-{name}", hash.to_string().repeat(32))
+    }
+
+    /// A `StorePath` for `path`, with hash and name taken from it.
+    pub fn store_path(path: &str, nar_size: u64, references: &[String]) -> StorePath {
+        let base_name = path.rsplit_once('/').map_or(path, |(_, base)| base);
+        let (hash, name) = base_name.split_once('-').unwrap();
+        StorePath {
+            path: path.to_string(),
+            hash: hash.to_string(),
+            name: name.to_string(),
+            nar_size,
+            references: references.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    /// Graphs built path by path, by full path or by name.
+    pub struct TestGraph(StorePathGraph);
+
+    impl Default for TestGraph {
+        fn default() -> Self {
+            Self::new(DEFAULT_STORE_DIR)
+        }
+    }
+
+    impl TestGraph {
+        pub fn new(store_dir: &str) -> Self {
+            let mut graph = StorePathGraph::new();
+            graph.store_dir = store_dir.to_string();
+            Self(graph)
+        }
+
+        /// `path_in` the graph's store directory.
+        pub fn path(&self, hash: char, name: &str) -> String {
+            path_in(&self.0.store_dir, hash, name)
+        }
+
+        pub fn add(&mut self, path: &str, nar_size: u64, references: &[String]) -> &mut Self {
+            self.0.add_path(store_path(path, nar_size, references));
+            self
+        }
+
+        /// `add` for the path of `name` with hash `a…`, referring to those
+        /// of `references`.
+        pub fn add_named(&mut self, name: &str, nar_size: u64, references: &[&str]) -> &mut Self {
+            let references: Vec<String> = references.iter().map(|r| self.path('a', r)).collect();
+            self.add(&self.path('a', name), nar_size, &references)
+        }
+
+        pub fn build(self, roots: &[String]) -> StorePathGraph {
+            let mut graph = self.0;
+            graph.roots = roots.to_vec();
+            graph
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{StorePath, StorePathGraph, StorePathParseError as E};
+
+    const HASH: &str = "0c0c9a2pbhvmb0bnzdb1bp6p5aj6ph4w";
+
+    fn parse(path: &str) -> Result<(String, String), E> {
+        StorePath::parse(path)
+    }
+
+    #[test]
+    fn parses_valid_paths() {
+        let (hash, name) = parse(&format!("/nix/store/{HASH}-hello-2.12.1")).unwrap();
+        assert_eq!(hash, HASH);
+        assert_eq!(name, "hello-2.12.1");
+        assert!(parse(&format!("/opt/nix/store/{HASH}-a+b_c.d?e=f")).is_ok());
+        assert!(parse(&format!("/nix/store/{HASH}-{}", "x".repeat(211))).is_ok());
+    }
+
+    #[test]
+    fn rejects_malformed_paths() {
+        let bad_prefix = |p: &str| E::BadPrefix {
+            path: p.to_string(),
+        };
+        assert_eq!(parse("hello"), Err(bad_prefix("hello")));
+        assert_eq!(
+            parse(&format!("store/{HASH}-x")),
+            Err(bad_prefix(&format!("store/{HASH}-x")))
+        );
+        assert_eq!(
+            parse("/nix/store/abc-hello"),
+            Err(E::BadHashLength { len: 3 })
+        );
+        assert_eq!(parse(&format!("/nix/store/{HASH}")), Err(E::EmptyName));
+        assert_eq!(parse(&format!("/nix/store/{HASH}-")), Err(E::EmptyName));
+
+        let upper = HASH.replacen('c', "C", 1);
+        assert_eq!(
+            parse(&format!("/nix/store/{upper}-x")),
+            Err(E::BadHashChar {
+                ch: 'C',
+                position: 1
+            })
+        );
+        for ch in ['e', 'o', 'u', 't'] {
+            let hash = format!("{ch}{}", &HASH[1..]);
+            assert_eq!(
+                parse(&format!("/nix/store/{hash}-x")),
+                Err(E::BadHashChar { ch, position: 0 })
+            );
+        }
+
+        assert_eq!(
+            parse(&format!("/nix/store/{HASH}-foo bar")),
+            Err(E::BadNameChar {
+                ch: ' ',
+                position: 3
+            })
+        );
+        assert_eq!(
+            parse(&format!("/nix/store/{HASH}-{}", "x".repeat(212))),
+            Err(E::NameTooLong { len: 212 })
+        );
+    }
+
+    #[test]
+    fn parse_in_checks_store_dir() {
+        let path = format!("/opt/nix/store/{HASH}-x");
+        assert!(StorePath::parse_in(&path, "/opt/nix/store").is_ok());
+        assert!(matches!(
+            StorePath::parse_in(&path, "/nix/store"),
+            Err(E::BadPrefix { .. })
+        ));
+        assert!(matches!(
+            StorePath::parse_in(&format!("/nix/store/{HASH}-x/bin"), "/nix/store"),
+            Err(E::BadPrefix { .. })
+        ));
+        assert_eq!(
+            E::BadHashChar {
+                ch: 'e',
+                position: 0
+            }
+            .to_string(),
+            "Invalid character 'e' at position 0 of store path hash"
+        );
+    }
+
+    #[test]
+    fn resolves_references_in_order_whenever_paths_arrive() {
+        let p = |name: &str| format!("/nix/store/{HASH}-{name}");
+        let mut graph = StorePathGraph::new();
+        let add = |graph: &mut StorePathGraph, name: &str, refs: &[&str]| {
+            graph.add_path(StorePath {
+                path: p(name),
+                name: name.to_string(),
+                references: refs.iter().map(|r| p(r)).collect(),
+                ..Default::default()
+            })
+        };
+        add(&mut graph, "app", &["late", "app", "lib", "libc", "lib"]);
+        add(&mut graph, "libc", &[]);
+        assert_eq!(graph.references_of(0), [1]);
+        add(&mut graph, "lib", &["libc"]);
+        assert_eq!(graph.references_of(0), [2, 1, 2]);
+        add(&mut graph, "late", &[]);
+        assert_eq!(graph.references_of(0), [3, 2, 1, 2]);
+        assert_eq!(graph.build_referrer_ids()[1], [0, 2]);
+
+        // The same through the string API.
+        assert_eq!(
+            graph.references(&p("app")),
+            [p("late"), p("lib"), p("libc"), p("lib")]
+        );
+        assert_eq!(graph.index_of(&p("lib")), Some(2));
+        assert_eq!(graph.build_referrers()[&p("libc")], [p("app"), p("lib")]);
+        assert!(!graph.build_referrers().contains_key(&p("app")));
+    }
+}
@@ -2,9 +2,16 @@ use anyhow::Result;
 use crossterm::event::{KeyCode, KeyEvent};
 use ratatui::widgets::ListState;
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use crate::path_stats::{AddedSize, PathStats, SortOrder};
-use crate::store_path::StorePathGraph;
+use crate::elf::Linking;
+use crate::files::{FileIndex, FileQuery, Kind, LargeFile, Tree};
+use crate::path_stats::{AddedSize, ReverseClosure, SortOrder, Stats, WhatIf, WhyDepends};
+use crate::pattern::Pattern;
+use crate::split::Advice;
+use crate::store_path::{PathId, StorePathGraph};
+use crate::ui::background::Background;
 use std::cell::RefCell;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -14,9 +21,21 @@ pub enum Pane {
     Next,
 }
 
+/// Chains formatted per page of the why-depends modal; the rest are unranked
+/// on demand.
+const WHY_DEPENDS_PAGE: usize = 200;
+
+/// Files listed by the largest-files overlay.
+const LARGEST_FILES: usize = 200;
+
 pub enum Modal {
     WhyDepends {
-        paths: Vec<Vec<String>>,
+        chains: Box<WhyDepends>,
+        /// Name of the marked path the chains start at, if not the roots.
+        source: Option<String>,
+        /// Rank of `paths[0]`; `selected` indexes into the loaded page.
+        page_start: u128,
+        paths: Vec<Vec<PathId>>,
         formatted_lines: Vec<String>,
         max_line_width: usize,
         selected: usize,
@@ -24,59 +43,162 @@ pub enum Modal {
         horizontal_scroll_state: ratatui::widgets::ScrollbarState,
         horizontal_scroll: usize,
     },
+    /// A pre-formatted text report.
+    Report {
+        kind: Report,
+        lines: Vec<String>,
+        scroll: usize,
+        /// The modal the report was opened from, which `q` goes back to.
+        back: Option<Box<Modal>>,
+    },
+}
+
+/// The Dependencies pane browsing a store path's files instead.
+pub struct FileView {
+    pub path: PathId,
+    /// Shared with `App::trees`.
+    pub tree: Arc<Tree>,
+    /// The directory shown, relative to the store path.
+    pub dir: PathBuf,
+    pub state: ListState,
+}
+
+impl FileView {
+    fn select(&mut self, index: usize) {
+        let len = self.tree.entries(&self.dir).len();
+        self.state.select((len > 0).then(|| index.min(len - 1)));
+    }
+}
+
+/// What the status area below the panes shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatusTab {
+    Info,
+    /// How the current path's ELF files use its references.
+    Linking,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Report {
+    /// What cutting `App::cut` would save.
+    WhatIf,
+    Duplicates,
+    /// Which files along a why-depends chain refer to the next path.
+    Precise,
+    /// References of the current path that only doc or dev files hold.
+    Split,
+    LargestFiles,
+}
+
+/// What background work hands back to the UI thread; the error if it
+/// crashed.
+enum Done {
+    Linking(PathId, Result<Linking, String>),
+    Precise(Vec<PathId>, Result<Vec<String>, String>),
+    Split(PathId, Result<Advice, String>),
+    Tree(PathId, Result<Tree, String>),
+    LargestFiles(Result<(Vec<LargeFile>, usize), String>),
+    FileIndex(Result<FileIndex, String>),
 }
 
 pub struct App {
-    pub graph: StorePathGraph,
-    pub stats: HashMap<String, PathStats>,
+    /// Shared with background work.
+    pub graph: Arc<StorePathGraph>,
+    pub stats: Stats,
     pub sort_order: SortOrder,
     pub active_pane: Pane,
     pub show_help: bool,
     pub searching: bool,
+    /// Typing a pattern of paths to cut; shares `search_query` with search.
+    pub cutting: bool,
+    /// Typing a file name to look for in every path; also shares
+    /// `search_query`.
+    pub finding_files: bool,
     pub search_query: String,
 
     pub previous_state: ListState,
     pub current_state: ListState,
     pub next_state: ListState,
 
-    pub previous_items: Vec<String>,
-    pub current_items: Vec<String>,
-    pub next_items: Vec<String>,
+    pub previous_items: Vec<PathId>,
+    pub current_items: Vec<PathId>,
+    pub next_items: Vec<PathId>,
 
-    pub current_path: Option<String>,
+    pub current_path: Option<PathId>,
+    /// Source for why-depends when set; `w` then shows chains from here to
+    /// the current path instead of from the roots.
+    pub marked: Option<PathId>,
+    /// Paths to remove in the what-if overlay, in the order they were cut.
+    pub cut: Vec<PathId>,
+    /// The Referrers pane shows every transitive referrer, not just the
+    /// immediate ones; `reverse_closure()` is then the current path's.
+    pub show_reverse_closure: bool,
+    /// Of each path the Referrers pane has shown all referrers for, so
+    /// moving back and forth doesn't walk them again.
+    pub reverse_closures: HashMap<PathId, ReverseClosure>,
+    pub status_tab: StatusTab,
+    /// Of each path the Linking tab has been shown for; the error if its
+    /// files could not be read.
+    pub linking: HashMap<PathId, Result<Linking, String>>,
+    /// The path whose ELF files are being read; one at a time, so moving
+    /// quickly through the list doesn't start a scan per path.
+    pub linking_pending: Option<PathId>,
+    /// The chain `p` last scanned the files of, and the report.
+    pub precise: Option<(Vec<PathId>, Vec<String>)>,
+    /// `p` is scanning the files of a chain in the background.
+    pub scanning_chain: bool,
+    /// Of each path `S` was pressed on; the error if its files could not
+    /// be read.
+    pub split: HashMap<PathId, Result<Advice, String>>,
+    /// The path whose files `S` is scanning.
+    pub split_pending: Option<PathId>,
+    pub files: Option<FileView>,
+    /// Of each path `f` was pressed on; the error if it could not be read.
+    pub trees: HashMap<PathId, Result<Arc<Tree>, String>>,
+    /// The path `f` is reading the files of.
+    pub scanning_tree: Option<PathId>,
+    /// What `F` found, and the number of paths not on disk; the closure
+    /// doesn't change, so it is only looked for once.
+    pub largest_files: Option<(Vec<LargeFile>, usize)>,
+    /// `F` is looking through the closure in the background.
+    pub finding_largest_files: bool,
+    /// Matching files of each path the current pane lists, while it shows
+    /// the results of a file search.
+    pub found_files: HashMap<PathId, Vec<PathBuf>>,
+    /// The file names of the closure, read by the first file search.
+    file_index: Option<FileIndex>,
+    /// A file search waiting for `file_index`, with the text it was typed as.
+    pub pending_file_search: Option<(FileQuery, String)>,
 
     // Navigation history: (items, selected_index)
-    pub navigation_history: Vec<(Vec<String>, Option<usize>)>,
+    pub navigation_history: Vec<(Vec<PathId>, Option<usize>)>,
 
     pub modal: Option<Modal>,
     pub status_message: Option<String>,
     added_size: RefCell<AddedSize>,
+    background: Background<Done>,
 
     /// Per-pane added sizes when sorting by AddedSize, so the list column can
     /// show the value the order is based on without recomputing per frame.
-    pub previous_added: HashMap<String, u64>,
-    pub current_added: HashMap<String, u64>,
-    pub next_added: HashMap<String, u64>,
+    pub previous_added: HashMap<PathId, u64>,
+    pub current_added: HashMap<PathId, u64>,
+    pub next_added: HashMap<PathId, u64>,
 }
 
 impl App {
-    pub fn added_size_of(&self, path: &str) -> u64 {
+    pub fn added_size_of(&self, id: PathId) -> u64 {
         let context = self.get_parent_context();
-        self.added_size
-            .borrow_mut()
-            .for_path(&self.graph, path, &context)
+        self.added_size.borrow_mut().for_path(id, &context)
     }
 
     fn sorted(
         &self,
-        mut items: Vec<String>,
-        context: Option<&[String]>,
-    ) -> (Vec<String>, HashMap<String, u64>) {
+        mut items: Vec<PathId>,
+        context: Option<&[PathId]>,
+    ) -> (Vec<PathId>, HashMap<PathId, u64>) {
         let added = match (self.sort_order, context) {
             (SortOrder::AddedSize, Some(ctx)) => {
-                self.added_size
-                    .borrow_mut()
-                    .for_items(&self.graph, &items, ctx)
+                self.added_size.borrow_mut().for_items(&items, ctx)
             }
             _ => HashMap::new(),
         };
@@ -90,33 +212,42 @@ impl App {
         (items, added)
     }
 
-    pub fn get_parent_context(&self) -> Vec<String> {
+    /// All referrers of the current path, while the Referrers pane shows
+    /// them.
+    pub fn reverse_closure(&self) -> Option<&ReverseClosure> {
+        let path = self.current_path.filter(|_| self.show_reverse_closure)?;
+        self.reverse_closures.get(&path)
+    }
+
+    pub fn get_parent_context(&self) -> Vec<PathId> {
         // Get the parent context from navigation history
         // For added size calculation, we need the specific parent we navigated from
         if let Some((parent_items, selected_idx)) = self.navigation_history.last() {
             // If we have a selected parent, use only that as the context
-            if let Some(idx) = selected_idx {
-                if let Some(parent) = parent_items.get(*idx) {
-                    return vec![parent.clone()];
-                }
+            if let Some(idx) = selected_idx
+                && let Some(parent) = parent_items.get(*idx)
+            {
+                return vec![*parent];
             }
             // Fallback to all parent items
             parent_items.clone()
         } else {
             // If no history, use the original roots
-            self.graph.roots.clone()
+            self.graph.root_ids()
         }
     }
 
-    pub fn new(graph: StorePathGraph, stats: HashMap<String, PathStats>) -> Self {
+    pub fn new(graph: StorePathGraph, stats: Stats) -> Self {
         let added_size = RefCell::new(AddedSize::new(&graph));
         let mut app = Self {
-            graph,
+            graph: Arc::new(graph),
             stats,
             sort_order: SortOrder::ClosureSize,
             active_pane: Pane::Current,
             show_help: false,
             searching: false,
+            cutting: false,
+            finding_files: false,
             search_query: String::new(),
             previous_state: ListState::default(),
             current_state: ListState::default(),
@@ -125,20 +256,38 @@ impl App {
             current_items: Vec::new(),
             next_items: Vec::new(),
             current_path: None,
+            marked: None,
+            cut: Vec::new(),
+            show_reverse_closure: false,
+            reverse_closures: HashMap::new(),
+            status_tab: StatusTab::Info,
+            linking: HashMap::new(),
+            linking_pending: None,
+            precise: None,
+            scanning_chain: false,
+            split: HashMap::new(),
+            split_pending: None,
+            files: None,
+            trees: HashMap::new(),
+            scanning_tree: None,
+            largest_files: None,
+            finding_largest_files: false,
+            found_files: HashMap::new(),
+            file_index: None,
+            pending_file_search: None,
             navigation_history: Vec::new(),
             modal: None,
             status_message: None,
             added_size,
+            background: Background::new(),
             previous_added: HashMap::new(),
             current_added: HashMap::new(),
             next_added: HashMap::new(),
         };
 
         // Start with all roots in the current pane
-        app.current_items = app.graph.roots.clone();
-        let roots = app.graph.roots.clone();
-        let items = std::mem::take(&mut app.current_items);
-        (app.current_items, app.current_added) = app.sorted(items, Some(&roots));
+        let roots = app.graph.root_ids();
+        (app.current_items, app.current_added) = app.sorted(roots.clone(), Some(&roots));
 
         if !app.current_items.is_empty() {
             app.current_state.select(Some(0));
@@ -155,55 +304,70 @@ impl App {
         if let Some(modal) = &mut self.modal {
             match modal {
                 Modal::WhyDepends {
+                    chains,
+                    page_start,
                     paths,
-                    formatted_lines,
-                    max_line_width,
                     selected,
-                    vertical_scroll_state,
                     horizontal_scroll_state,
                     horizontal_scroll,
+                    max_line_width,
+                    ..
                 } => {
-                    match key.code {
+                    let rank = *page_start + *selected as u128;
+                    let last = chains.total() - 1;
+                    let seek = match key.code {
                         KeyCode::Char('q') | KeyCode::Esc => {
                             self.modal = None;
+                            None
                         }
                         KeyCode::Char('y') => {
                             if let Some(chain) = paths.get(*selected) {
+                                let chain: Vec<&str> = chain
+                                    .iter()
+                                    .map(|&p| self.graph.path(p).path.as_str())
+                                    .collect();
                                 let text = chain.join(" ");
                                 self.status_message = Some(match crate::clipboard::copy(&text) {
                                     Ok(()) => format!("Copied {} paths", chain.len()),
                                     Err(e) => format!("Clipboard error: {e}"),
                                 });
                             }
+                            None
                         }
-                        KeyCode::Down | KeyCode::Char('j') => {
-                            if *selected < formatted_lines.len().saturating_sub(1) {
-                                *selected += 1;
-                                *vertical_scroll_state = vertical_scroll_state
-                                    .content_length(formatted_lines.len())
-                                    .position(*selected);
-                            }
-                        }
-                        KeyCode::Up | KeyCode::Char('k') => {
-                            if *selected > 0 {
-                                *selected -= 1;
-                                *vertical_scroll_state = vertical_scroll_state
-                                    .content_length(formatted_lines.len())
-                                    .position(*selected);
+                        KeyCode::Down | KeyCode::Char('j') => Some((rank + 1).min(last)),
+                        KeyCode::Up | KeyCode::Char('k') => Some(rank.saturating_sub(1)),
+                        KeyCode::PageDown => Some(rank.saturating_add(10).min(last)),
+                        KeyCode::PageUp => Some(rank.saturating_sub(10)),
+                        KeyCode::Home | KeyCode::Char('g') => Some(0),
+                        KeyCode::End | KeyCode::Char('G') => Some(last),
+                        KeyCode::Char('p') => {
+                            if let Some(chain) = paths.get(*selected).cloned() {
+                                self.show_precise(chain);
                             }
+                            None
                         }
                         KeyCode::Enter => {
-                            if let Some(path) = paths.get(*selected).cloned() {
+                            if let Some(mut path) = paths.get(*selected).cloned() {
+                                // Chains from a marked source are walked to
+                                // from the roots first.
+                                if !self.graph.root_ids().contains(&path[0])
+                                    && let Some(prefix) =
+                                        WhyDepends::new(&self.graph, path[0]).chain(0)
+                                {
+                                    path.splice(..1, prefix);
+                                }
                                 self.modal = None;
                                 self.select_path_from_why_depends(path);
                                 return Ok(false);
                             }
+                            None
                         }
                         KeyCode::Left | KeyCode::Char('h') => {
                             *horizontal_scroll = horizontal_scroll.saturating_sub(5);
                             *horizontal_scroll_state = horizontal_scroll_state
                                 .content_length(*max_line_width)
                                 .position(*horizontal_scroll);
+                            None
                         }
                         KeyCode::Right | KeyCode::Char('l') => {
                             // Don't scroll beyond the longest line
@@ -212,22 +376,34 @@ impl App {
                             *horizontal_scroll_state = horizontal_scroll_state
                                 .content_length(*max_line_width)
                                 .position(*horizontal_scroll);
+                            None
                         }
-                        KeyCode::PageDown => {
-                            let page_size = 10; // Adjust based on your modal height
-                            *selected = (*selected + page_size)
-                                .min(formatted_lines.len().saturating_sub(1));
-                            *vertical_scroll_state = vertical_scroll_state
-                                .content_length(formatted_lines.len())
-                                .position(*selected);
-                        }
-                        KeyCode::PageUp => {
-                            let page_size = 10;
-                            *selected = selected.saturating_sub(page_size);
-                            *vertical_scroll_state = vertical_scroll_state
-                                .content_length(formatted_lines.len())
-                                .position(*selected);
+                        _ => None,
+                    };
+                    if let Some(rank) = seek {
+                        self.seek_why_depends(rank);
+                    }
+                }
+                Modal::Report {
+                    kind,
+                    lines,
+                    scroll,
+                    back,
+                } => {
+                    let last = lines.len().saturating_sub(1);
+                    match key.code {
+                        KeyCode::Char('q') | KeyCode::Esc => self.modal = back.take().map(|m| *m),
+                        KeyCode::Char('u') if *kind == Report::WhatIf => {
+                            self.cut.clear();
+                            self.modal = None;
+                            self.status_message = Some("Cleared cut paths".to_string());
                         }
+                        KeyCode::Down | KeyCode::Char('j') => *scroll = (*scroll + 1).min(last),
+                        KeyCode::Up | KeyCode::Char('k') => *scroll = scroll.saturating_sub(1),
+                        KeyCode::PageDown => *scroll = (*scroll + 10).min(last),
+                        KeyCode::PageUp => *scroll = scroll.saturating_sub(10),
+                        KeyCode::Home | KeyCode::Char('g') => *scroll = 0,
+                        KeyCode::End | KeyCode::Char('G') => *scroll = last,
                         _ => {}
                     }
                 }
@@ -235,12 +411,27 @@ impl App {
             return Ok(false);
         }
 
-        if self.searching {
+        if self.files.is_some() {
+            self.handle_file_key(key);
+            return Ok(false);
+        }
+
+        if self.searching || self.cutting || self.finding_files {
             match key.code {
                 KeyCode::Esc => {
                     self.searching = false;
+                    self.cutting = false;
+                    self.finding_files = false;
                     self.search_query.clear();
                 }
+                KeyCode::Enter if self.cutting => {
+                    self.cutting = false;
+                    self.cut_matching();
+                }
+                KeyCode::Enter if self.finding_files => {
+                    self.finding_files = false;
+                    self.find_files();
+                }
                 KeyCode::Enter => {
                     self.searching = false;
                     self.perform_search();
@@ -264,6 +455,25 @@ impl App {
                 self.search_query.clear();
             }
             KeyCode::Char('w') => self.show_why_depends(),
+            KeyCode::Char('d') => self.jump_to_dominator(),
+            KeyCode::Char('c') => self.show_cycles(),
+            KeyCode::Char('m') => self.toggle_mark(),
+            KeyCode::Char('x') => self.toggle_cut(),
+            KeyCode::Char('X') => {
+                self.cutting = true;
+                self.search_query.clear();
+            }
+            KeyCode::Char('W') => self.show_what_if(),
+            KeyCode::Char('D') => self.show_duplicates(),
+            KeyCode::Char('S') => self.show_split(),
+            KeyCode::Char('R') => self.toggle_reverse_closure(),
+            KeyCode::Tab => self.toggle_status_tab(),
+            KeyCode::Char('f') => self.show_files(),
+            KeyCode::Char('F') => self.show_largest_files(),
+            KeyCode::Char('L') => {
+                self.finding_files = true;
+                self.search_query.clear();
+            }
             KeyCode::Char('y') => self.yank_current_path(),
             KeyCode::Char('r') => self.jump_to_roots(),
             KeyCode::Char('s') => {
@@ -287,8 +497,9 @@ impl App {
 
     fn jump_to_roots(&mut self) {
         self.navigation_history.clear();
-        let roots = self.graph.roots.clone();
-        (self.current_items, self.current_added) = self.sorted(roots.clone(), Some(&roots));
+        let roots = self.graph.root_ids();
+        let (items, added) = self.sorted(roots.clone(), Some(&roots));
+        self.set_current_items(items, added);
         self.current_state = ListState::default();
         if !self.current_items.is_empty() {
             self.current_state.select(Some(0));
@@ -296,8 +507,450 @@ impl App {
         self.update_panes();
     }
 
+    /// Navigate to the current path's immediate dominator along a shortest
+    /// reference chain from the roots, so the panes and history look as if
+    /// the user had walked there.
+    fn jump_to_dominator(&mut self) {
+        let Some(path) = self.current_path else {
+            return;
+        };
+        let Some(dominator) = self.stats[path].immediate_dominator else {
+            self.status_message = Some("Only the roots dominate this path".to_string());
+            return;
+        };
+        if let Some(chain) = WhyDepends::new(&self.graph, dominator).chain(0) {
+            self.select_path_from_why_depends(chain);
+        }
+    }
+
+    /// Mark the current path as the why-depends source, or clear the mark.
+    fn toggle_mark(&mut self) {
+        if self.marked.is_some() {
+            self.marked = None;
+            self.status_message = Some("Cleared why-depends source".to_string());
+        } else if let Some(path) = self.current_path {
+            let name = self.graph.path(path).short_name();
+            self.status_message = Some(format!("Marked {name} as why-depends source"));
+            self.marked = Some(path);
+        }
+    }
+
+    fn toggle_reverse_closure(&mut self) {
+        self.show_reverse_closure = !self.show_reverse_closure;
+        self.status_message = Some(
+            if self.show_reverse_closure {
+                "Showing all transitive referrers"
+            } else {
+                "Showing immediate referrers"
+            }
+            .to_string(),
+        );
+        self.update_panes();
+    }
+
+    /// Browse the current path's files, reading them in the background the
+    /// first time.
+    fn show_files(&mut self) {
+        let Some(path) = self.current_path else {
+            return;
+        };
+        match self.trees.get(&path) {
+            Some(Ok(tree)) => {
+                let mut view = FileView {
+                    path,
+                    tree: Arc::clone(tree),
+                    dir: PathBuf::new(),
+                    state: ListState::default(),
+                };
+                view.select(0);
+                self.files = Some(view);
+            }
+            Some(Err(e)) => self.status_message = Some(e.clone()),
+            None if let Some(pending) = self.scanning_tree => {
+                self.status_message = Some(format!(
+                    "Still reading the files of {}…",
+                    self.graph.path(pending).short_name()
+                ));
+            }
+            None => {
+                self.scanning_tree = Some(path);
+                self.status_message = Some(format!(
+                    "Reading the files of {}…",
+                    self.graph.path(path).short_name()
+                ));
+                let graph = Arc::clone(&self.graph);
+                self.background.spawn(
+                    move || {
+                        let tree = Tree::scan(Path::new(&graph.path(path).path));
+                        Done::Tree(path, tree.map_err(|e| format!("{e:#}")))
+                    },
+                    move |e| Done::Tree(path, Err(e)),
+                );
+            }
+        }
+    }
+
+    fn handle_file_key(&mut self, key: KeyEvent) {
+        let Some(view) = &mut self.files else {
+            return;
+        };
+        let selected = view.state.selected();
+        let cur = selected.unwrap_or(0);
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc | KeyCode::Char('f') => self.files = None,
+            KeyCode::Down | KeyCode::Char('j') => view.select(cur + 1),
+            KeyCode::Up | KeyCode::Char('k') => view.select(cur.saturating_sub(1)),
+            KeyCode::PageDown => view.select(cur + 20),
+            KeyCode::PageUp => view.select(cur.saturating_sub(20)),
+            KeyCode::Home | KeyCode::Char('g') => view.select(0),
+            KeyCode::End | KeyCode::Char('G') => view.select(usize::MAX),
+            KeyCode::Right | KeyCode::Char('l') | KeyCode::Enter => {
+                let entries = view.tree.entries(&view.dir);
+                if let Some(entry) = selected.and_then(|i| entries.get(i))
+                    && entry.kind == Kind::Dir
+                {
+                    view.dir.push(&entry.name);
+                    view.select(0);
+                }
+            }
+            KeyCode::Left | KeyCode::Char('h') => {
+                let Some(name) = view
+                    .dir
+                    .file_name()
+                    .map(|n| n.to_string_lossy().into_owned())
+                else {
+                    self.files = None;
+                    return;
+                };
+                view.dir.pop();
+                let index = view
+                    .tree
+                    .entries(&view.dir)
+                    .iter()
+                    .position(|e| e.name == name);
+                view.select(index.unwrap_or(0));
+            }
+            _ => {}
+        }
+    }
+
+    /// Show which files hold each reference of `chain` over the chain list,
+    /// scanning them in the background first.
+    fn show_precise(&mut self, chain: Vec<PathId>) {
+        if let Some((scanned, lines)) = &self.precise
+            && *scanned == chain
+        {
+            self.modal = Some(Modal::Report {
+                kind: Report::Precise,
+                lines: lines.clone(),
+                scroll: 0,
+                back: self.modal.take().map(Box::new),
+            });
+            return;
+        }
+        if self.scanning_chain {
+            self.status_message = Some("Still scanning the previous chain…".to_string());
+            return;
+        }
+        self.scanning_chain = true;
+        self.status_message = Some(format!("Scanning the files of {} paths…", chain.len()));
+        let graph = Arc::clone(&self.graph);
+        let failed = chain.clone();
+        self.background.spawn(
+            move || {
+                let lines = crate::precise::lines(&graph, &chain);
+                Done::Precise(chain, Ok(lines))
+            },
+            move |e| Done::Precise(failed, Err(e)),
+        );
+    }
+
+    fn show_largest_files(&mut self) {
+        let Some((files, unreadable)) = &self.largest_files else {
+            if !self.finding_largest_files {
+                self.finding_largest_files = true;
+                let graph = Arc::clone(&self.graph);
+                self.background.spawn(
+                    move || Done::LargestFiles(Ok(crate::files::largest(&graph, LARGEST_FILES))),
+                    |e| Done::LargestFiles(Err(e)),
+                );
+            }
+            self.status_message = Some(format!(
+                "Looking for the largest files in {} paths…",
+                self.graph.paths().len()
+            ));
+            return;
+        };
+        if files.is_empty() {
+            self.status_message = Some(format!(
+                "No files found; {unreadable} paths are not on disk"
+            ));
+            return;
+        }
+        self.modal = Some(Modal::Report {
+            kind: Report::LargestFiles,
+            lines: crate::files::largest_lines(&self.graph, files, *unreadable),
+            scroll: 0,
+            back: None,
+        });
+    }
+
+    fn toggle_status_tab(&mut self) {
+        self.status_tab = match self.status_tab {
+            StatusTab::Info => StatusTab::Linking,
+            StatusTab::Linking => StatusTab::Info,
+        };
+        self.update_linking();
+    }
+
+    /// Start reading the current path's ELF files if the Linking tab shows
+    /// it and it has not been read yet.
+    fn update_linking(&mut self) {
+        let (StatusTab::Linking, Some(path), None) =
+            (self.status_tab, self.current_path, self.linking_pending)
+        else {
+            return;
+        };
+        if self.linking.contains_key(&path) {
+            return;
+        }
+        self.linking_pending = Some(path);
+        let graph = Arc::clone(&self.graph);
+        self.background.spawn(
+            move || {
+                Done::Linking(
+                    path,
+                    Linking::new(&graph, path).map_err(|e| format!("{e:#}")),
+                )
+            },
+            move |e| Done::Linking(path, Err(e)),
+        );
+    }
+
+    /// Take in the results of finished background work; true if there were
+    /// any, so the frame needs drawing again.
+    pub fn poll_background(&mut self) -> bool {
+        let mut any = false;
+        while let Some(done) = self.background.try_recv() {
+            self.finish(done);
+            any = true;
+        }
+        any
+    }
+
+    /// Wait for all background work, including what finishing it starts.
+    pub fn wait_background(&mut self) {
+        while let Some(done) = self.background.recv() {
+            self.finish(done);
+        }
+    }
+
+    fn finish(&mut self, done: Done) {
+        match done {
+            Done::Linking(path, linking) => {
+                self.linking.insert(path, linking);
+                self.linking_pending = None;
+                self.update_linking();
+            }
+            Done::Precise(chain, lines) => {
+                self.scanning_chain = false;
+                match lines {
+                    Ok(lines) => self.precise = Some((chain.clone(), lines)),
+                    Err(e) => {
+                        self.status_message = Some(e);
+                        return;
+                    }
+                }
+                // Show it if the chains are still what is open.
+                if matches!(self.modal, Some(Modal::WhyDepends { .. })) {
+                    self.status_message = None;
+                    self.show_precise(chain);
+                } else {
+                    self.status_message = Some("Scanned the chain (p)".to_string());
+                }
+            }
+            Done::Split(path, advice) => {
+                self.split.insert(path, advice);
+                self.split_pending = None;
+                // Show it unless the selection moved or something else was
+                // opened in the meantime.
+                if self.current_path == Some(path) && self.modal.is_none() && self.files.is_none() {
+                    self.status_message = None;
+                    self.show_split();
+                } else {
+                    self.status_message = Some(format!(
+                        "Scanned {} (S)",
+                        self.graph.path(path).short_name()
+                    ));
+                }
+            }
+            Done::Tree(path, tree) => {
+                self.trees.insert(path, tree.map(Arc::new));
+                self.scanning_tree = None;
+                if self.current_path == Some(path) && self.modal.is_none() && self.files.is_none() {
+                    self.status_message = None;
+                    self.show_files();
+                } else {
+                    self.status_message = Some(format!(
+                        "Read the files of {} (f)",
+                        self.graph.path(path).short_name()
+                    ));
+                }
+            }
+            Done::LargestFiles(found) => {
+                self.finding_largest_files = false;
+                match found {
+                    Ok(found) => self.largest_files = Some(found),
+                    Err(e) => {
+                        self.status_message = Some(e);
+                        return;
+                    }
+                }
+                // Show it unless something else was opened in the meantime.
+                if self.modal.is_none() && self.files.is_none() {
+                    self.show_largest_files();
+                } else {
+                    self.status_message = Some("Found the largest files (F)".to_string());
+                }
+            }
+            Done::FileIndex(Ok(index)) => {
+                self.file_index = Some(index);
+                if let Some((query, text)) = self.pending_file_search.take() {
+                    self.show_found_files(&query, &text);
+                }
+            }
+            Done::FileIndex(Err(e)) => {
+                self.pending_file_search = None;
+                self.status_message = Some(e);
+            }
+        }
+    }
+
+    /// Add the current path to the what-if cut set, or take it out again.
+    fn toggle_cut(&mut self) {
+        let Some(path) = self.current_path else {
+            return;
+        };
+        if let Some(i) = self.cut.iter().position(|&p| p == path) {
+            self.cut.remove(i);
+        } else {
+            self.cut.push(path);
+        }
+        self.status_message = Some(format!("{} paths cut (W: what-if)", self.cut.len()));
+    }
+
+    /// Cut every path matching the patterns typed after `X`, then show what
+    /// that would save.
+    fn cut_matching(&mut self) {
+        let patterns = Pattern::parse_list(&std::mem::take(&mut self.search_query));
+        let matched = crate::pattern::select(&self.graph, &patterns);
+        if matched.is_empty() {
+            self.status_message = Some("No paths match".to_string());
+            return;
+        }
+        for path in matched {
+            if !self.cut.contains(&path) {
+                self.cut.push(path);
+            }
+        }
+        self.show_what_if();
+    }
+
+    fn show_what_if(&mut self) {
+        if self.cut.is_empty() {
+            self.status_message =
+                Some("Nothing cut; x cuts the current path, X a pattern".to_string());
+            return;
+        }
+        let report = WhatIf::new(&self.graph, &self.cut);
+        self.modal = Some(Modal::Report {
+            kind: Report::WhatIf,
+            lines: crate::what_if::lines(&self.graph, &report),
+            scroll: 0,
+            back: None,
+        });
+    }
+
+    fn show_duplicates(&mut self) {
+        let found = crate::duplicates::find(&self.graph, &self.stats);
+        if found.is_empty() {
+            self.status_message = Some("No duplicated packages".to_string());
+            return;
+        }
+        self.modal = Some(Modal::Report {
+            kind: Report::Duplicates,
+            lines: crate::duplicates::lines(&self.graph, &found),
+            scroll: 0,
+            back: None,
+        });
+    }
+
+    /// Show the splitting advice for the current path, scanning its files
+    /// in the background the first time.
+    fn show_split(&mut self) {
+        let Some(path) = self.current_path else {
+            return;
+        };
+        match self.split.get(&path) {
+            Some(Ok(advice)) => {
+                self.modal = Some(Modal::Report {
+                    kind: Report::Split,
+                    lines: crate::split::lines(&self.graph, advice),
+                    scroll: 0,
+                    back: None,
+                });
+            }
+            Some(Err(e)) => self.status_message = Some(e.clone()),
+            None if let Some(pending) = self.split_pending => {
+                self.status_message = Some(format!(
+                    "Still scanning {}…",
+                    self.graph.path(pending).short_name()
+                ));
+            }
+            None => {
+                self.split_pending = Some(path);
+                self.status_message = Some(format!(
+                    "Scanning the files of {}…",
+                    self.graph.path(path).short_name()
+                ));
+                let graph = Arc::clone(&self.graph);
+                self.background.spawn(
+                    move || {
+                        Done::Split(
+                            path,
+                            Advice::new(&graph, path).map_err(|e| format!("{e:#}")),
+                        )
+                    },
+                    move |e| Done::Split(path, Err(e)),
+                );
+            }
+        }
+    }
+
+    /// Put every path that is part of a reference cycle into the current
+    /// pane, members of one cycle next to each other.
+    fn show_cycles(&mut self) {
+        let mut cycles: Vec<&[PathId]> = Vec::new();
+        for (id, s) in self.stats.iter() {
+            if let Some(cycle) = s.cycle.as_deref()
+                && cycle.first() == Some(&id)
+            {
+                cycles.push(cycle);
+            }
+        }
+        if cycles.is_empty() {
+            self.status_message = Some("No reference cycles".to_string());
+            return;
+        }
+        self.status_message = Some(format!("{} reference cycles", cycles.len()));
+        self.set_current_items(cycles.concat(), HashMap::new());
+        self.current_state.select(Some(0));
+        self.update_panes();
+    }
+
     fn yank_current_path(&mut self) {
-        if let Some(path) = &self.current_path {
+        if let Some(id) = self.current_path {
+            let path = &self.graph.path(id).path;
             self.status_message = Some(match crate::clipboard::copy(path) {
                 Ok(()) => format!("Copied to clipboard: {path}"),
                 Err(e) => format!("Clipboard error: {e}"),
@@ -322,15 +975,13 @@ impl App {
         if let Some((items, selected_idx)) = self.navigation_history.pop() {
             // Keep the historical order so selected_idx stays valid; only
             // refresh the added-size column for the new (now-popped) parent.
-            self.current_added = if self.sort_order == SortOrder::AddedSize {
+            let added = if self.sort_order == SortOrder::AddedSize {
                 let parent = self.get_parent_context();
-                self.added_size
-                    .borrow_mut()
-                    .for_items(&self.graph, &items, &parent)
+                self.added_size.borrow_mut().for_items(&items, &parent)
             } else {
                 HashMap::new()
             };
-            self.current_items = items;
+            self.set_current_items(items, added);
             self.current_state = ListState::default();
             if let Some(idx) = selected_idx {
                 self.current_state.select(Some(idx));
@@ -349,13 +1000,24 @@ impl App {
 
             // next_added was computed against the very parent we just
             // descended from, so it becomes the new current map verbatim.
-            self.current_items = std::mem::take(&mut self.next_items);
-            self.current_added = std::mem::take(&mut self.next_added);
+            let (items, added) = (
+                std::mem::take(&mut self.next_items),
+                std::mem::take(&mut self.next_added),
+            );
+            self.set_current_items(items, added);
             self.current_state.select(Some(0));
             self.update_panes();
         }
     }
 
+    /// Replace what the current pane lists; whatever it is, it is no longer
+    /// the file search `found_files` describes.
+    fn set_current_items(&mut self, items: Vec<PathId>, added: HashMap<PathId, u64>) {
+        self.current_items = items;
+        self.current_added = added;
+        self.found_files.clear();
+    }
+
     fn select_item(&mut self) {
         // Enter key behavior: update the panes based on selected item
         self.update_panes();
@@ -364,24 +1026,23 @@ impl App {
     fn update_panes(&mut self) {
         // Use the selected item in current_items as the focus
         let selected_idx = self.current_state.selected().unwrap_or(0);
-        if let Some(path) = self.current_items.get(selected_idx).cloned() {
-            self.current_path = Some(path.clone());
-
-            let parents = self
-                .stats
-                .get(&path)
-                .map(|s| s.immediate_parents.clone())
-                .unwrap_or_default();
+        if let Some(&path) = self.current_items.get(selected_idx) {
+            self.current_path = Some(path);
+
+            let parents = if self.show_reverse_closure {
+                self.reverse_closures
+                    .entry(path)
+                    .or_insert_with(|| ReverseClosure::new(&self.graph, &self.stats, path))
+                    .paths
+                    .clone()
+            } else {
+                self.stats[path].immediate_parents.clone()
+            };
             (self.previous_items, self.previous_added) = self.sorted(parents, None);
 
-            let refs = self
-                .graph
-                .get_references(&path)
-                .into_iter()
-                .map(|p| p.path.clone())
-                .collect::<Vec<_>>();
-            (self.next_items, self.next_added) =
-                self.sorted(refs, Some(std::slice::from_ref(&path)));
+            let refs = self.graph.references_of(path).to_vec();
+            (self.next_items, self.next_added) = self.sorted(refs, Some(&[path]));
+            self.update_linking();
 
             // Reset selections in side panes but keep current pane focus
             self.previous_state = ListState::default();
@@ -402,13 +1063,14 @@ impl App {
 
     fn resort_current_pane(&mut self) {
         let parent = self.get_parent_context();
-        let next_ctx = self.current_path.clone().map(|p| vec![p]);
+        let next_ctx = self.current_path.map(|p| [p]);
         let cur = std::mem::take(&mut self.current_items);
         let prev = std::mem::take(&mut self.previous_items);
         let next = std::mem::take(&mut self.next_items);
         (self.current_items, self.current_added) = self.sorted(cur, Some(&parent));
         (self.previous_items, self.previous_added) = self.sorted(prev, None);
-        (self.next_items, self.next_added) = self.sorted(next, next_ctx.as_deref());
+        (self.next_items, self.next_added) =
+            self.sorted(next, next_ctx.as_ref().map(|c| c.as_slice()));
     }
 
     fn perform_search(&mut self) {
@@ -417,84 +1079,180 @@ impl App {
         }
 
         let query = self.search_query.to_lowercase();
-        let mut scored: Vec<(i32, String)> = self
-            .graph
-            .paths
-            .iter()
-            .filter_map(|p| {
-                fuzzy_match(&p.name.to_lowercase(), &query).map(|score| (score, p.path.clone()))
-            })
+        let mut scored: Vec<(i32, PathId)> = (0..)
+            .zip(self.graph.paths())
+            .filter_map(|(id, p)| fuzzy_match(&p.name.to_lowercase(), &query).map(|s| (s, id)))
             .collect();
         // Best match first; ties broken by closure size so the "big thing you
         // probably meant" floats up when many names share a prefix.
         scored.sort_by(|a, b| {
             b.0.cmp(&a.0).then_with(|| {
-                let sa = self.stats.get(&a.1).map_or(0, |s| s.closure_size);
-                let sb = self.stats.get(&b.1).map_or(0, |s| s.closure_size);
+                let sa = self.stats[a.1].closure_size;
+                let sb = self.stats[b.1].closure_size;
                 sb.cmp(&sa)
             })
         });
-        let matching_paths: Vec<String> = scored.into_iter().map(|(_, p)| p).collect();
+        let matching_paths: Vec<PathId> = scored.into_iter().map(|(_, p)| p).collect();
+        self.show_results(matching_paths);
+    }
 
-        if !matching_paths.is_empty() {
-            // Keep relevance order rather than re-sorting by size.
-            self.current_items = matching_paths;
-            self.current_added = HashMap::new();
+    /// Put search results into the current pane, in the given order rather
+    /// than re-sorted by size.
+    fn show_results(&mut self, paths: Vec<PathId>) {
+        if !paths.is_empty() {
+            self.set_current_items(paths, HashMap::new());
             self.current_state.select(Some(0));
             self.active_pane = Pane::Current;
             self.update_panes();
         }
     }
 
-    fn show_why_depends(&mut self) {
-        if let Some(path) = &self.current_path {
-            let paths = crate::path_stats::why_depends(&self.graph, path);
-            if !paths.is_empty() {
-                // Pre-format all lines to avoid recomputing on every render
-                let mut formatted_lines = Vec::new();
-                let mut max_line_width = 0;
-
-                for path in &paths {
-                    let text = path
-                        .iter()
-                        .map(|p| {
-                            // Extract short name from path
-                            p.split('-').skip(1).collect::<Vec<_>>().join("-")
-                        })
-                        .collect::<Vec<_>>()
-                        .join(" → ");
+    /// List the paths shipping a file whose name matches the query, largest
+    /// closure first. The first search reads the file names of the closure
+    /// in the background and runs once they are in.
+    fn find_files(&mut self) {
+        let query = match FileQuery::parse(&self.search_query) {
+            Ok(query) => query,
+            Err(e) => {
+                self.status_message = Some(format!("{e:#}"));
+                return;
+            }
+        };
+        if self.file_index.is_some() {
+            self.show_found_files(&query, &self.search_query.clone());
+            return;
+        }
+        if self.pending_file_search.is_none() {
+            let graph = Arc::clone(&self.graph);
+            self.background.spawn(
+                move || Done::FileIndex(Ok(FileIndex::new(&graph))),
+                |e| Done::FileIndex(Err(e)),
+            );
+        }
+        self.pending_file_search = Some((query, self.search_query.clone()));
+        self.status_message = Some(format!(
+            "Reading the file names of {} paths…",
+            self.graph.paths().len()
+        ));
+    }
 
-                    // Count characters instead of bytes for proper UTF-8 handling
-                    max_line_width = max_line_width.max(text.chars().count());
-                    formatted_lines.push(text);
-                }
+    fn show_found_files(&mut self, query: &FileQuery, text: &str) {
+        let Some(index) = &self.file_index else {
+            return;
+        };
+        let found = index.find(query);
+        if found.is_empty() {
+            let unreadable = index.unreadable();
+            self.status_message = Some(if unreadable == self.graph.paths().len() {
+                format!("No files found; {unreadable} paths are not on disk")
+            } else {
+                format!("No file matches {text}")
+            });
+            return;
+        }
+        let mut paths: Vec<PathId> = found.iter().map(|(p, _)| *p).collect();
+        paths.sort_by_key(|&p| std::cmp::Reverse(self.stats[p].closure_size));
+        self.show_results(paths);
+        self.found_files = found.into_iter().collect();
+    }
 
-                let vertical_scroll_state = ratatui::widgets::ScrollbarState::default()
-                    .content_length(formatted_lines.len())
-                    .position(0);
+    fn show_why_depends(&mut self) {
+        let Some(path) = self.current_path else {
+            return;
+        };
+        let chains = match self.marked {
+            Some(source) => WhyDepends::between(&self.graph, source, path),
+            None => WhyDepends::new(&self.graph, path),
+        };
+        if chains.total() == 0 {
+            if let Some(source) = self.marked {
+                let name = |p: PathId| self.graph.path(p).short_name();
+                self.status_message = Some(format!(
+                    "{} does not depend on {}",
+                    name(source),
+                    name(path)
+                ));
+            }
+            return;
+        }
+        self.modal = Some(Modal::WhyDepends {
+            chains: Box::new(chains),
+            source: self
+                .marked
+                .map(|p| self.graph.path(p).short_name().to_string()),
+            page_start: 0,
+            paths: Vec::new(),
+            formatted_lines: Vec::new(),
+            max_line_width: 0,
+            selected: 0,
+            vertical_scroll_state: ratatui::widgets::ScrollbarState::default(),
+            horizontal_scroll_state: ratatui::widgets::ScrollbarState::default(),
+            horizontal_scroll: 0,
+        });
+        self.seek_why_depends(0);
+    }
 
-                let horizontal_scroll_state =
-                    ratatui::widgets::ScrollbarState::default().content_length(max_line_width);
+    /// Select the chain at `rank` in the why-depends modal, unranking the
+    /// page around it if it isn't loaded yet.
+    fn seek_why_depends(&mut self, rank: u128) {
+        let Some(Modal::WhyDepends {
+            chains,
+            page_start,
+            paths,
+            formatted_lines,
+            max_line_width,
+            selected,
+            vertical_scroll_state,
+            horizontal_scroll_state,
+            ..
+        }) = &mut self.modal
+        else {
+            return;
+        };
 
-                self.modal = Some(Modal::WhyDepends {
-                    paths,
-                    formatted_lines,
-                    max_line_width,
-                    selected: 0,
-                    vertical_scroll_state,
-                    horizontal_scroll_state,
-                    horizontal_scroll: 0,
-                });
-            }
+        let loaded = *page_start..*page_start + paths.len() as u128;
+        if !loaded.contains(&rank) {
+            let page = WHY_DEPENDS_PAGE as u128;
+            *page_start = rank / page * page;
+            *paths = chains.page(*page_start, WHY_DEPENDS_PAGE);
+            // Pre-format the page to avoid recomputing on every render
+            *formatted_lines = paths
+                .iter()
+                .map(|path| {
+                    path.iter()
+                        .map(|&p| {
+                            // Extract short name from path
+                            let base_name = self.graph.base_name(&self.graph.path(p).path);
+                            base_name
+                                .split_once('-')
+                                .map_or(base_name, |(_, name)| name)
+                        })
+                        .collect::<Vec<_>>()
+                        .join(" → ")
+                })
+                .collect();
+            // Count characters instead of bytes for proper UTF-8 handling
+            *max_line_width = formatted_lines
+                .iter()
+                .map(|l| l.chars().count())
+                .max()
+                .unwrap_or(0);
+            *horizontal_scroll_state = horizontal_scroll_state.content_length(*max_line_width);
         }
+        *selected = (rank - *page_start) as usize;
+        let clamp = |n: u128| usize::try_from(n).unwrap_or(usize::MAX);
+        *vertical_scroll_state = vertical_scroll_state
+            .content_length(clamp(chains.total()))
+            .position(clamp(rank));
     }
 
-    fn select_path_from_why_depends(&mut self, path: Vec<String>) {
+    fn select_path_from_why_depends(&mut self, path: Vec<PathId>) {
         // Clear navigation history
         self.navigation_history.clear();
 
-        let roots = self.graph.roots.clone();
-        (self.current_items, self.current_added) = self.sorted(roots.clone(), Some(&roots));
+        let roots = self.graph.root_ids();
+        let (items, added) = self.sorted(roots.clone(), Some(&roots));
+        self.set_current_items(items, added);
 
         // Navigate through the path
         for (i, target) in path.iter().enumerate() {
@@ -539,6 +1297,8 @@ fn fuzzy_match(haystack: &str, query: &str) -> Option<i32> {
 #[cfg(test)]
 mod tests {
     use super::fuzzy_match as fz;
+    use super::*;
+    use crate::store_path::testing::{TestGraph, p};
 
     #[test]
     fn fuzzy_scoring() {
@@ -546,4 +1306,29 @@ mod tests {
         assert!(fz("python3-requests", "req") > fz("ruby-erubis-qt", "req"));
         assert!(fz("glibc-2.40", "gli") > fz("nss-glib-shim", "gli"));
     }
+
+    #[test]
+    fn crashed_background_work_is_reported() {
+        let mut graph = TestGraph::default();
+        graph.add_named("app", 0, &[]);
+        let graph = graph.build(&[p("app")]);
+        let stats = crate::path_stats::calculate_stats(&graph);
+        let mut app = App::new(graph, stats);
+
+        app.linking_pending = Some(0);
+        app.background
+            .spawn(|| panic!("bad ELF file"), |e| Done::Linking(0, Err(e)));
+        app.finding_largest_files = true;
+        app.background
+            .spawn(|| panic!("bad directory"), |e| Done::LargestFiles(Err(e)));
+        app.wait_background();
+
+        assert_eq!(app.linking_pending, None);
+        assert_eq!(
+            app.linking[&0].as_ref().err().unwrap(),
+            "Crashed: bad ELF file"
+        );
+        assert!(!app.finding_largest_files);
+        assert!(app.largest_files.is_none());
+    }
 }
@@ -0,0 +1,83 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+/// Work running on other threads so file scans don't block key handling;
+/// the main loop collects what finished between frames.
+pub struct Background<T> {
+    tx: Sender<T>,
+    rx: Receiver<T>,
+    pending: usize,
+}
+
+impl<T: Send + 'static> Background<T> {
+    pub fn new() -> Self {
+        let (tx, rx) = mpsc::channel();
+        Self { tx, rx, pending: 0 }
+    }
+
+    /// Run `work` on its own thread. If it panics, which parsing arbitrary
+    /// store files may, `failed` makes the result from the panic message, so
+    /// whoever waits for it hears back either way.
+    pub fn spawn(
+        &mut self,
+        work: impl FnOnce() -> T + Send + 'static,
+        failed: impl FnOnce(String) -> T + Send + 'static,
+    ) {
+        self.pending += 1;
+        let tx = self.tx.clone();
+        thread::spawn(move || {
+            let done = panic::catch_unwind(AssertUnwindSafe(work))
+                .unwrap_or_else(|payload| failed(panic_message(payload.as_ref())));
+            let _ = tx.send(done);
+        });
+    }
+
+    /// A finished result, if there is one.
+    pub fn try_recv(&mut self) -> Option<T> {
+        let done = self.rx.try_recv().ok()?;
+        self.pending -= 1;
+        Some(done)
+    }
+
+    /// The next result, waiting for it; `None` once nothing is running.
+    pub fn recv(&mut self) -> Option<T> {
+        if self.pending == 0 {
+            return None;
+        }
+        let done = self.rx.recv().ok()?;
+        self.pending -= 1;
+        Some(done)
+    }
+}
+
+impl<T: Send + 'static> Default for Background<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    let message = payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown cause");
+    format!("Crashed: {message}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_panics() {
+        let mut background = Background::new();
+        background.spawn(|| -> Result<u32, String> { panic!("bad header") }, Err);
+        background.spawn(|| Ok(1), Err);
+        let mut done = vec![background.recv().unwrap(), background.recv().unwrap()];
+        done.sort();
+        assert_eq!(done, [Ok(1), Err("Crashed: bad header".to_string())]);
+        assert_eq!(background.recv(), None);
+    }
+}
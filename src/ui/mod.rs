@@ -1,4 +1,5 @@
 pub mod app;
+pub mod background;
 pub mod pane;
 pub mod widgets;
 
@@ -10,7 +11,7 @@ use ratatui::layout::{Constraint, Layout};
 /// Single source of truth for the per-frame layout so the live TUI, the
 /// snapshot example and the scroll bench cannot drift apart.
 pub fn render_frame(f: &mut Frame, app: &App) {
-    let chunks = Layout::vertical([Constraint::Min(1), Constraint::Length(4)]).split(f.area());
+    let chunks = Layout::vertical([Constraint::Min(1), Constraint::Length(5)]).split(f.area());
 
     pane::render_panes(f, app, chunks[0]);
     widgets::render_status_bar(f, app, chunks[1]);
@@ -19,7 +20,18 @@ pub fn render_frame(f: &mut Frame, app: &App) {
         widgets::render_help(f, f.area());
     }
     if app.searching {
-        widgets::render_search(f, f.area(), &app.search_query);
+        widgets::render_search(f, f.area(), "Search", &app.search_query);
+    }
+    if app.cutting {
+        widgets::render_search(f, f.area(), "Cut paths matching", &app.search_query);
+    }
+    if app.finding_files {
+        widgets::render_search(
+            f,
+            f.area(),
+            "Find file (glob, or re:REGEX)",
+            &app.search_query,
+        );
     }
     widgets::render_modal(f, app, f.area());
 }
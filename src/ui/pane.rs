@@ -6,9 +6,10 @@ use ratatui::{
     widgets::{Block, Borders, List, ListItem},
 };
 
-use crate::path_stats::{PathStats, SortOrder};
-use crate::store_path::StorePathGraph;
-use crate::ui::app::{App, Pane};
+use crate::files::Kind;
+use crate::path_stats::{SortOrder, Stats};
+use crate::store_path::{PathId, StorePathGraph};
+use crate::ui::app::{App, FileView, Pane};
 use std::collections::HashMap;
 
 pub fn render_panes(f: &mut Frame, app: &App, area: Rect) {
@@ -19,10 +20,18 @@ pub fn render_panes(f: &mut Frame, app: &App, area: Rect) {
     ])
     .split(area);
 
+    let referrers_title = match app.reverse_closure() {
+        Some(rc) => format!(
+            "All referrers · {} · {}",
+            rc.paths.len(),
+            bytesize::ByteSize(rc.size)
+        ),
+        _ => "Referrers".to_string(),
+    };
     render_pane(
         f,
         chunks[0],
-        "Referrers",
+        &referrers_title,
         &PaneRenderContext {
             items: &app.previous_items,
             added: &app.previous_added,
@@ -50,6 +59,11 @@ pub fn render_panes(f: &mut Frame, app: &App, area: Rect) {
         },
     );
 
+    if let Some(view) = &app.files {
+        render_files(f, chunks[2], app, view);
+        return;
+    }
+
     render_pane(
         f,
         chunks[2],
@@ -67,12 +81,12 @@ pub fn render_panes(f: &mut Frame, app: &App, area: Rect) {
 }
 
 struct PaneRenderContext<'a> {
-    items: &'a [String],
-    added: &'a HashMap<String, u64>,
+    items: &'a [PathId],
+    added: &'a HashMap<PathId, u64>,
     state: &'a ratatui::widgets::ListState,
     is_active: bool,
     graph: &'a StorePathGraph,
-    stats: &'a HashMap<String, PathStats>,
+    stats: &'a Stats,
     sort_order: SortOrder,
 }
 
@@ -90,26 +104,22 @@ fn render_pane(f: &mut Frame, area: Rect, title: &str, ctx: &PaneRenderContext)
         .items
         .iter()
         .enumerate()
-        .map(|(idx, path)| {
+        .map(|(idx, &path)| {
             let is_selected = ctx.state.selected() == Some(idx);
-            let store_path = ctx.graph.get_path(path);
-            let path_stats = ctx.stats.get(path);
+            let store_path = ctx.graph.path(path);
+            let path_stats = &ctx.stats[path];
 
-            let name = store_path.map(|p| p.short_name()).unwrap_or(path.as_str());
+            let name = store_path.short_name();
 
-            let size = if ctx.sort_order == SortOrder::AddedSize {
-                ctx.added.get(path).copied()
-            } else {
-                None
+            let size = match ctx.sort_order {
+                SortOrder::AddedSize => ctx.added.get(&path).copied(),
+                SortOrder::RetainedSize => Some(path_stats.retained_size),
+                _ => None,
             }
-            .or_else(|| path_stats.map(|s| s.closure_size));
-            let size_str = size
-                .map(|s| format!("{:>10}", bytesize::ByteSize(s)))
-                .unwrap_or_default();
+            .unwrap_or(path_stats.closure_size);
+            let size_str = format!("{:>10}", bytesize::ByteSize(size));
 
-            let signed = store_path
-                .map(|p| if p.is_signed() { "✓ " } else { "  " })
-                .unwrap_or("  ");
+            let signed = if store_path.is_signed() { "✓ " } else { "  " };
 
             let name_budget = inner_width
                 .saturating_sub(SIGN_W)
@@ -154,6 +164,62 @@ fn render_pane(f: &mut Frame, area: Rect, title: &str, ctx: &PaneRenderContext)
     f.render_stateful_widget(list, area, &mut ctx.state.clone());
 }
 
+/// The Dependencies pane while browsing a path's files: the entries of the
+/// current directory, largest first, with `du`-style sizes.
+fn render_files(f: &mut Frame, area: Rect, app: &App, view: &FileView) {
+    let inner_width = area.width.saturating_sub(2) as usize;
+    let entries = view.tree.entries(&view.dir);
+    let list_items: Vec<ListItem> = entries
+        .iter()
+        .enumerate()
+        .map(|(idx, entry)| {
+            let (marker, color) = match entry.kind {
+                Kind::Dir => ("/", Color::Blue),
+                Kind::Symlink => ("@", Color::Cyan),
+                Kind::File => ("", Color::Reset),
+            };
+            let size_str = format!("{:>10}", bytesize::ByteSize(entry.size));
+            let name_budget = inner_width.saturating_sub(size_str.len() + 1 + marker.len());
+            let (name, pad) = fit_and_pad(&entry.name, name_budget);
+            let style = if view.state.selected() == Some(idx) {
+                Style::default()
+                    .bg(Color::Blue)
+                    .add_modifier(Modifier::BOLD)
+            } else {
+                Style::default()
+            };
+            ListItem::new(Line::from(vec![
+                Span::styled(format!("{name}{marker}"), Style::default().fg(color)),
+                Span::raw(" ".repeat(pad + 1)),
+                Span::styled(size_str, Style::default().fg(Color::Green)),
+            ]))
+            .style(style)
+        })
+        .collect();
+
+    let name = app.graph.path(view.path).short_name();
+    let dir = if view.dir.as_os_str().is_empty() {
+        name.to_string()
+    } else {
+        format!("{name}/{}", view.dir.display())
+    };
+    let title = format!("Files · {dir} · {}", bytesize::ByteSize(view.tree.size));
+    let position = view
+        .state
+        .selected()
+        .map(|i| format!(" {}/{} ", i + 1, entries.len()))
+        .unwrap_or_default();
+
+    let list = List::new(list_items).block(
+        Block::default()
+            .title(title)
+            .title_bottom(Line::from(position).right_aligned())
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(Color::Yellow)),
+    );
+    f.render_stateful_widget(list, area, &mut view.state.clone());
+}
+
 /// Truncate `s` to at most `budget` columns (appending `…` if cut) and report
 /// remaining columns so the caller can right-align the next span. Store path
 /// names are restricted to ASCII so byte length equals display width here.
@@ -1,4 +1,5 @@
-use crate::ui::app::{App, Modal};
+use crate::store_path::PathId;
+use crate::ui::app::{App, Modal, Report, StatusTab};
 use ratatui::{
     Frame,
     layout::{Alignment, Constraint, Margin, Rect},
@@ -19,7 +20,21 @@ pub fn render_help(f: &mut Frame, area: Rect) {
         Line::from(""),
         Line::from("Actions:"),
         Line::from("  /       Search"),
-        Line::from("  w       Show why-depends (use h/l to scroll horizontally)"),
+        Line::from("  w       Show why-depends (h/l scroll, g/G first/last chain)"),
+        Line::from("          p there: files along the chain that hold each reference"),
+        Line::from("  m       Mark/unmark a source for why-depends"),
+        Line::from("  x       Cut/uncut the selected path for what-if"),
+        Line::from("  X       Cut every path matching a pattern (*-doc,perl)"),
+        Line::from("  W       Show what cutting would save"),
+        Line::from("  D       List packages present in several versions/builds"),
+        Line::from("  S       Show references that only doc/dev files hold"),
+        Line::from("  R       Toggle all transitive referrers in the left pane"),
+        Line::from("  f       Browse the selected path's files (h/l up/into, f closes)"),
+        Line::from("  F       List the largest files in the whole closure"),
+        Line::from("  L       List paths shipping a file (bin/ssh, lib/libz.so*, re:...)"),
+        Line::from("  Tab     Switch the status area between info and ELF linking"),
+        Line::from("  d       Jump to the immediate dominator"),
+        Line::from("  c       List paths in reference cycles"),
         Line::from("  y       Yank selected store path to clipboard"),
         Line::from("  r       Jump back to roots"),
         Line::from("  s       Change sort order"),
@@ -44,11 +59,11 @@ pub fn render_help(f: &mut Frame, area: Rect) {
     f.render_widget(paragraph, help_area);
 }
 
-pub fn render_search(f: &mut Frame, area: Rect, query: &str) {
-    let search_text = vec![Line::from("Search:"), Line::from(query)];
+pub fn render_search(f: &mut Frame, area: Rect, title: &str, query: &str) {
+    let search_text = vec![Line::from(format!("{title}:")), Line::from(query)];
 
     let block = Block::default()
-        .title("Search")
+        .title(title)
         .borders(Borders::ALL)
         .style(Style::default().fg(Color::Yellow));
 
@@ -70,81 +85,240 @@ pub fn render_status_bar(f: &mut Frame, app: &App, area: Rect) {
         f.render_widget(Paragraph::new(line), area);
         return;
     }
-    if let Some(path) = &app.current_path {
-        // First line: full path
-        let path_line = Line::from(vec![Span::raw(path)]);
-
-        // Second line: detailed stats
-        if let Some(store_path) = app.graph.get_path(path) {
-            let stats = app.stats.get(path);
-
-            let nar_size = bytesize::ByteSize(store_path.nar_size);
-            let closure_size = stats
-                .map(|s| bytesize::ByteSize(s.closure_size))
-                .unwrap_or(bytesize::ByteSize(0));
-            let added_size = bytesize::ByteSize(app.added_size_of(path));
-
-            let signatures = if store_path.signatures.is_empty() {
-                "none".to_string()
-            } else {
-                store_path.signatures.join(", ")
+    if let Some(path) = app.current_path {
+        // First line: full path, and the why-depends source if one is marked
+        let mut path_line = Line::from(vec![Span::raw(&app.graph.path(path).path)]);
+        if let Some(marked) = app.marked.map(|m| app.graph.path(m)) {
+            path_line.push_span(Span::styled(
+                format!("  [why-depends from {}]", marked.short_name()),
+                Style::default().fg(Color::Yellow),
+            ));
+        }
+        if !app.cut.is_empty() {
+            path_line.push_span(Span::styled(
+                format!("  [{} cut]", app.cut.len()),
+                Style::default().fg(Color::Red),
+            ));
+        }
+        if let Some(files) = app.found_files.get(&path) {
+            let more = match files.len() {
+                1 => String::new(),
+                n => format!(" +{}", n - 1),
             };
+            path_line.push_span(Span::styled(
+                format!("  [ships {}{more}]", files[0].display()),
+                Style::default().fg(Color::Green),
+            ));
+        }
 
-            let parents_count = stats.map(|s| s.immediate_parents.len()).unwrap_or(0);
-            let parents_preview = stats
-                .map(|s| {
-                    let names: Vec<String> = s
-                        .immediate_parents
-                        .iter()
-                        .take(5)
-                        .filter_map(|p| app.graph.get_path(p))
-                        .map(|sp| sp.short_name().to_string())
-                        .collect();
-                    if s.immediate_parents.len() > 5 {
-                        format!("{}, ...", names.join(", "))
-                    } else {
-                        names.join(", ")
-                    }
-                })
-                .unwrap_or_default();
-
-            let stats_line = Line::from(vec![
-                Span::raw("NAR Size: "),
-                Span::styled(nar_size.to_string(), Style::default().fg(Color::Yellow)),
-                Span::raw(" | Closure Size: "),
-                Span::styled(closure_size.to_string(), Style::default().fg(Color::Green)),
-                Span::raw(" | Added Size: "),
-                Span::styled(added_size.to_string(), Style::default().fg(Color::Cyan)),
-            ]);
-
-            let info_line = Line::from(vec![
-                Span::raw("Signatures: "),
-                Span::styled(signatures, Style::default().fg(Color::Magenta)),
-            ]);
-
-            let parents_line = if parents_count > 0 {
-                Line::from(vec![
-                    Span::raw(format!("Immediate Parents ({parents_count}): ")),
-                    Span::styled(parents_preview, Style::default().fg(Color::Blue)),
-                ])
-            } else {
-                Line::from(vec![Span::raw("Immediate Parents: none")])
-            };
+        if app.pending_file_search.is_some() {
+            path_line.push_span(Span::styled(
+                "  [reading file names…]",
+                Style::default().fg(Color::Cyan),
+            ));
+        }
+        if app.scanning_chain {
+            path_line.push_span(Span::styled(
+                "  [scanning the chain…]",
+                Style::default().fg(Color::Cyan),
+            ));
+        }
+        if app.scanning_tree.is_some() {
+            path_line.push_span(Span::styled(
+                "  [reading files…]",
+                Style::default().fg(Color::Cyan),
+            ));
+        }
+        if app.split_pending.is_some() {
+            path_line.push_span(Span::styled(
+                "  [scanning for S…]",
+                Style::default().fg(Color::Cyan),
+            ));
+        }
+        if app.finding_largest_files {
+            path_line.push_span(Span::styled(
+                "  [finding the largest files…]",
+                Style::default().fg(Color::Cyan),
+            ));
+        }
+
+        if app.status_tab == StatusTab::Linking {
+            path_line.push_span(Span::styled(
+                "  [linking, Tab for info]",
+                Style::default().fg(Color::Cyan),
+            ));
+            let mut text = vec![path_line];
+            text.extend(linking_lines(app, path));
+            f.render_widget(Paragraph::new(text), area);
+            return;
+        }
 
-            let text = vec![path_line, stats_line, info_line, parents_line];
-            let paragraph = Paragraph::new(text);
-            f.render_widget(paragraph, area);
+        // Second line: detailed stats
+        let store_path = app.graph.path(path);
+        let stats = app.stats.get(path);
+
+        let nar_size = bytesize::ByteSize(store_path.nar_size);
+        let closure_size = stats
+            .map(|s| bytesize::ByteSize(s.closure_size))
+            .unwrap_or(bytesize::ByteSize(0));
+        let added_size = bytesize::ByteSize(app.added_size_of(path));
+        let retained_size = bytesize::ByteSize(stats.map_or(0, |s| s.retained_size));
+
+        let signatures = if store_path.signatures.is_empty() {
+            "none".to_string()
+        } else {
+            store_path.signatures.join(", ")
+        };
+
+        let parents_count = stats.map(|s| s.immediate_parents.len()).unwrap_or(0);
+        let parents_preview = stats
+            .map(|s| {
+                let names: Vec<String> = s
+                    .immediate_parents
+                    .iter()
+                    .take(5)
+                    .map(|&p| app.graph.path(p).short_name().to_string())
+                    .collect();
+                if s.immediate_parents.len() > 5 {
+                    format!("{}, ...", names.join(", "))
+                } else {
+                    names.join(", ")
+                }
+            })
+            .unwrap_or_default();
+
+        let mut stats_spans = vec![
+            Span::raw("NAR Size: "),
+            Span::styled(nar_size.to_string(), Style::default().fg(Color::Yellow)),
+            Span::raw(" | Closure Size: "),
+            Span::styled(closure_size.to_string(), Style::default().fg(Color::Green)),
+            Span::raw(" | Added Size: "),
+            Span::styled(added_size.to_string(), Style::default().fg(Color::Cyan)),
+            Span::raw(" | Retained Size: "),
+            Span::styled(retained_size.to_string(), Style::default().fg(Color::Cyan)),
+        ];
+        if let Some(download) = store_path.download_size {
+            let mut text = bytesize::ByteSize(download).to_string();
+            if let Some(closure) = store_path.closure_download_size {
+                text = format!("{text} (closure {})", bytesize::ByteSize(closure));
+            }
+            stats_spans.push(Span::raw(" | Download: "));
+            stats_spans.push(Span::styled(text, Style::default().fg(Color::Yellow)));
+        }
+        let stats_line = Line::from(stats_spans);
+
+        let origin = match store_path.ultimate {
+            Some(true) => " (built locally)",
+            Some(false) => " (substituted)",
+            None => "",
+        };
+        let mut provenance_spans = vec![
+            Span::raw("Registered: "),
+            Span::styled(
+                store_path
+                    .registration_time
+                    .map_or_else(|| "unknown".to_string(), format_timestamp),
+                Style::default().fg(Color::Green),
+            ),
+            Span::raw(format!("{origin} | Deriver: ")),
+            Span::styled(
+                store_path
+                    .deriver
+                    .as_deref()
+                    .map_or("unknown", |d| app.graph.base_name(d)),
+                Style::default().fg(Color::Blue),
+            ),
+        ];
+        if let Some(ca) = &store_path.ca {
+            provenance_spans.push(Span::raw(" | CA: "));
+            provenance_spans.push(Span::styled(
+                ca.clone(),
+                Style::default().fg(Color::Magenta),
+            ));
+        }
+        let provenance_line = Line::from(provenance_spans);
+
+        let info_line = Line::from(vec![
+            Span::raw("NAR Hash: "),
+            Span::styled(
+                store_path.nar_hash.as_deref().unwrap_or("unknown"),
+                Style::default().fg(Color::Yellow),
+            ),
+            Span::raw(" | Signatures: "),
+            Span::styled(signatures, Style::default().fg(Color::Magenta)),
+        ]);
+
+        let dominator = stats
+            .and_then(|s| s.immediate_dominator)
+            .map_or("roots", |d| app.graph.path(d).short_name());
+        let mut parents_spans = Vec::new();
+        if let Some(cycle) = stats.and_then(|s| s.cycle.as_ref()) {
+            let others: Vec<&str> = cycle
+                .iter()
+                .filter(|&&p| p != path)
+                .take(3)
+                .map(|&p| app.graph.path(p).short_name())
+                .collect();
+            let more = if cycle.len() > 4 { ", ..." } else { "" };
+            parents_spans.push(Span::raw(format!("Cycle ({}): ", cycle.len())));
+            parents_spans.push(Span::styled(
+                format!("{}{more}", others.join(", ")),
+                Style::default().fg(Color::Red),
+            ));
+            parents_spans.push(Span::raw(" | "));
+        }
+        let depth = match stats.map(|s| (s.min_depth, s.max_depth)) {
+            Some((Some(min), Some(max))) if min == max => min.to_string(),
+            Some((Some(min), Some(max))) => format!("{min}–{max}"),
+            _ => "unreachable".to_string(),
+        };
+        parents_spans.extend([
+            Span::raw(format!(
+                "Depth: {depth} · Height: {} | ",
+                stats.map_or(0, |s| s.height)
+            )),
+            Span::raw("Dominator: "),
+            Span::styled(dominator, Style::default().fg(Color::Blue)),
+            Span::raw(" | "),
+        ]);
+        if let Some(rc) = app.reverse_closure() {
+            let roots: Vec<&str> = rc
+                .roots
+                .iter()
+                .take(5)
+                .map(|&r| app.graph.path(r).short_name())
+                .collect();
+            let more = if rc.roots.len() > 5 { ", ..." } else { "" };
+            parents_spans.push(Span::raw(format!(
+                "Referrers: {} ({}) | Roots ({}): ",
+                rc.paths.len(),
+                bytesize::ByteSize(rc.size),
+                rc.roots.len()
+            )));
+            parents_spans.push(Span::styled(
+                format!("{}{more}", roots.join(", ")),
+                Style::default().fg(Color::Blue),
+            ));
+        } else if parents_count > 0 {
+            parents_spans.push(Span::raw(format!("Immediate Parents ({parents_count}): ")));
+            parents_spans.push(Span::styled(
+                parents_preview,
+                Style::default().fg(Color::Blue),
+            ));
         } else {
-            // Fallback to simple display
-            let status_line = Line::from(vec![
-                Span::raw(path),
-                Span::raw(" | Sort: "),
-                Span::raw(app.sort_order.as_str()),
-                Span::raw(" | Press ? for help"),
-            ]);
-            let paragraph = Paragraph::new(status_line);
-            f.render_widget(paragraph, area);
+            parents_spans.push(Span::raw("Immediate Parents: none"));
         }
+        let parents_line = Line::from(parents_spans);
+
+        let text = vec![
+            path_line,
+            stats_line,
+            provenance_line,
+            info_line,
+            parents_line,
+        ];
+        let paragraph = Paragraph::new(text);
+        f.render_widget(paragraph, area);
     } else {
         let status_line = Line::from(vec![Span::raw("No selection | Press ? for help")]);
         let paragraph = Paragraph::new(status_line);
@@ -152,16 +326,96 @@ pub fn render_status_bar(f: &mut Frame, app: &App, area: Rect) {
     }
 }
 
+/// The Linking tab: which references the path's ELF files load, and which
+/// only a runpath entry keeps alive.
+fn linking_lines(app: &App, path: PathId) -> Vec<Line<'_>> {
+    let linking = match app.linking.get(&path) {
+        Some(Ok(linking)) if linking.binaries > 0 => linking,
+        Some(Ok(_)) => return vec![Line::from("No ELF executables or libraries")],
+        Some(Err(e)) => {
+            return vec![Line::from(Span::styled(
+                e.clone(),
+                Style::default().fg(Color::Red),
+            ))];
+        }
+        None => return vec![Line::from("Reading ELF headers…")],
+    };
+    let name = |p: PathId| app.graph.path(p).short_name();
+    let list = |names: Vec<String>| {
+        if names.is_empty() {
+            "none".to_string()
+        } else {
+            names.join(", ")
+        }
+    };
+
+    let linked = linking
+        .linked
+        .iter()
+        .map(|(p, libs)| format!("{} ({})", name(*p), libs.join(", ")))
+        .collect();
+    let runpath_only = linking
+        .runpath_only
+        .iter()
+        .map(|&p| name(p).to_string())
+        .collect();
+    let other: Vec<String> = app
+        .graph
+        .references_of(path)
+        .iter()
+        .filter(|&&r| {
+            !linking.runpath_only.contains(&r) && !linking.linked.iter().any(|(l, _)| *l == r)
+        })
+        .map(|&r| name(r).to_string())
+        .collect();
+
+    let mut summary = vec![Span::raw(format!("ELF files: {}", linking.binaries))];
+    if !linking.unreadable.is_empty() {
+        summary.push(Span::styled(
+            format!(" ({} unreadable files)", linking.unreadable.len()),
+            Style::default().fg(Color::Red),
+        ));
+    }
+    if !linking.unresolved.is_empty() {
+        summary.push(Span::raw(" | Not found: "));
+        summary.push(Span::styled(
+            linking.unresolved.join(", "),
+            Style::default().fg(Color::Yellow),
+        ));
+    }
+    vec![
+        Line::from(summary),
+        Line::from(vec![
+            Span::raw("Linked: "),
+            Span::styled(list(linked), Style::default().fg(Color::Green)),
+        ]),
+        Line::from(vec![
+            Span::raw("Runpath only: "),
+            Span::styled(list(runpath_only), Style::default().fg(Color::Red)),
+        ]),
+        Line::from(vec![
+            Span::raw(format!("Not linked ({}): ", other.len())),
+            Span::styled(list(other), Style::default().fg(Color::Blue)),
+        ]),
+    ]
+}
+
 pub fn render_why_depends(f: &mut Frame, area: Rect, modal: &Modal) {
     let Modal::WhyDepends {
-        paths: _,
+        chains,
+        source,
+        page_start,
         formatted_lines,
         max_line_width,
         selected,
         vertical_scroll_state,
         horizontal_scroll_state,
         horizontal_scroll,
-    } = modal;
+        ..
+    } = modal
+    else {
+        return;
+    };
     let (max_line_width, selected, horizontal_scroll) =
         (*max_line_width, *selected, *horizontal_scroll);
 
@@ -171,7 +425,13 @@ pub fn render_why_depends(f: &mut Frame, area: Rect, modal: &Modal) {
     f.render_widget(Clear, modal_area);
 
     let block = Block::default()
-        .title("Why Depends - Shows paths from roots to selected package")
+        .title(format!(
+            "Why Depends - chain {} of {}{} from {} to the selected package",
+            page_start + selected as u128 + 1,
+            if chains.is_exact() { "" } else { "at least " },
+            chains.total(),
+            source.as_deref().unwrap_or("the roots")
+        ))
         .borders(Borders::ALL);
 
     let inner_area = block.inner(modal_area);
@@ -217,7 +477,7 @@ pub fn render_why_depends(f: &mut Frame, area: Rect, modal: &Modal) {
     f.render_widget(paragraph, inner_area);
 
     // Render vertical scrollbar if there are items to scroll
-    if !formatted_lines.is_empty() && formatted_lines.len() > visible_height {
+    if chains.total() > visible_height as u128 {
         let vertical_scrollbar = Scrollbar::new(ScrollbarOrientation::VerticalRight)
             .begin_symbol(Some("↑"))
             .end_symbol(Some("↓"));
@@ -254,10 +514,50 @@ pub fn render_why_depends(f: &mut Frame, area: Rect, modal: &Modal) {
     }
 }
 
+pub fn render_report(
+    f: &mut Frame,
+    area: Rect,
+    app: &App,
+    kind: Report,
+    lines: &[String],
+    scroll: usize,
+) {
+    let modal_area = centered_rect(80, 70, area);
+    f.render_widget(Clear, modal_area);
+
+    let title = match kind {
+        Report::WhatIf => format!(
+            "What If - without {} cut paths (u clears, q closes)",
+            app.cut.len()
+        ),
+        Report::Duplicates => "Duplicate Packages (q closes)".to_string(),
+        Report::Precise => {
+            "Precise Why Depends - files referring to the next path (q closes)".to_string()
+        }
+        Report::Split => {
+            "Split Outputs - references only docs or dev files hold (q closes)".to_string()
+        }
+        Report::LargestFiles => "Largest Files in the Closure (q closes)".to_string(),
+    };
+    let block = Block::default().title(title).borders(Borders::ALL);
+    let visible = lines
+        .iter()
+        .skip(scroll)
+        .map(|l| Line::from(l.as_str()))
+        .collect::<Vec<_>>();
+    f.render_widget(Paragraph::new(visible).block(block), modal_area);
+}
+
 pub fn render_modal(f: &mut Frame, app: &App, area: Rect) {
     if let Some(modal) = &app.modal {
         match modal {
             Modal::WhyDepends { .. } => render_why_depends(f, area, modal),
+            Modal::Report {
+                kind,
+                lines,
+                scroll,
+                ..
+            } => render_report(f, area, app, *kind, lines, *scroll),
         }
     }
 }
@@ -277,3 +577,33 @@ fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
     ])
     .split(popup_layout[1])[1]
 }
+
+/// `YYYY-MM-DD HH:MM UTC` for seconds since the epoch, without pulling in a
+/// date crate for one status bar field.
+fn format_timestamp(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let (hour, minute) = (secs % 86_400 / 3600, secs % 3600 / 60);
+    // Howard Hinnant's civil_from_days.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02} {hour:02}:{minute:02} UTC")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_timestamp;
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00 UTC");
+        assert_eq!(format_timestamp(951_782_400), "2000-02-29 00:00 UTC");
+        assert_eq!(format_timestamp(1_700_000_000), "2023-11-14 22:13 UTC");
+    }
+}
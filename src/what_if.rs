@@ -0,0 +1,106 @@
+use std::io::{self, Write};
+
+use crate::path_stats::WhatIf;
+use crate::store_path::{PathId, StorePathGraph};
+
+/// The report for `--what-if` and the TUI overlay: the overall saving, then
+/// each root, the cut paths and what would become unreachable with them.
+pub fn lines(graph: &StorePathGraph, report: &WhatIf) -> Vec<String> {
+    let size = |p: PathId| graph.path(p).nar_size;
+    let name = |p: PathId| graph.path(p).short_name();
+    let total = |paths: &[PathId]| paths.iter().map(|&p| size(p)).sum::<u64>();
+
+    let mut lines = vec![format!(
+        "Total: {} → {} ({})",
+        bytesize::ByteSize(report.before),
+        bytesize::ByteSize(report.after),
+        fmt_saving(report.before, report.after),
+    )];
+    if report.roots.len() > 1 {
+        lines.push(String::new());
+        for (root, before, after) in &report.roots {
+            lines.push(format!(
+                "{:>12}  {}: {} → {}",
+                fmt_saving(*before, *after),
+                name(*root),
+                bytesize::ByteSize(*before),
+                bytesize::ByteSize(*after),
+            ));
+        }
+    }
+    for (title, paths) in [("Cut", &report.cut), ("Unreachable", &report.unreachable)] {
+        lines.push(String::new());
+        lines.push(format!(
+            "{title}: {} paths, {}",
+            paths.len(),
+            bytesize::ByteSize(total(paths))
+        ));
+        for &p in paths {
+            lines.push(format!(
+                "{:>12}  {}",
+                bytesize::ByteSize(size(p)).to_string(),
+                name(p)
+            ));
+        }
+    }
+    lines
+}
+
+pub fn write(graph: &StorePathGraph, report: &WhatIf, out: &mut impl Write) -> io::Result<()> {
+    for line in lines(graph, report) {
+        writeln!(out, "{line}")?;
+    }
+    Ok(())
+}
+
+fn fmt_saving(before: u64, after: u64) -> String {
+    format!("-{}", bytesize::ByteSize(before.saturating_sub(after)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::{Pattern, select};
+    use crate::store_path::testing::{TestGraph, p};
+
+    #[test]
+    fn cut_docs_and_perl() {
+        let mut graph = TestGraph::default();
+        for (name, size, refs) in [
+            (
+                "system",
+                1000,
+                &["git-2.44.0", "git-2.44.0-doc", "man-db-2.12.0"][..],
+            ),
+            ("git-2.44.0", 500, &["perl-5.38.2", "zlib-1.3.1"]),
+            ("git-2.44.0-doc", 200, &[]),
+            ("man-db-2.12.0", 100, &["groff-1.23.0", "zlib-1.3.1"]),
+            ("groff-1.23.0", 300, &["perl-5.38.2"]),
+            ("perl-5.38.2", 4000, &["perl5.38.2-XML-Parser-2.46"]),
+            ("perl5.38.2-XML-Parser-2.46", 50, &[]),
+            ("zlib-1.3.1", 10, &[]),
+        ] {
+            graph.add_named(name, size, refs);
+        }
+        let graph = graph.build(&[p("system")]);
+
+        let cut = select(&graph, &Pattern::parse_list("*-doc,perl"));
+        let report = WhatIf::new(&graph, &cut);
+        let id = |name: &str| graph.id_of(&p(name)).unwrap();
+        assert_eq!(report.cut, [id("perl-5.38.2"), id("git-2.44.0-doc")]);
+        assert_eq!(report.unreachable, [id("perl5.38.2-XML-Parser-2.46")]);
+        assert_eq!(report.before, 6160);
+        assert_eq!(report.after, 1910);
+
+        let lines = lines(&graph, &report);
+        assert_eq!(lines[0], "Total: 6.0 KiB → 1.9 KiB (-4.2 KiB)");
+        assert!(
+            lines.contains(&"Cut: 2 paths, 4.1 KiB".to_string()),
+            "{lines:#?}"
+        );
+        assert!(
+            lines.contains(&"Unreachable: 1 paths, 50 B".to_string()),
+            "{lines:#?}"
+        );
+    }
+}
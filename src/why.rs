@@ -0,0 +1,145 @@
+use anyhow::{Result, bail};
+use std::io::Write;
+
+use crate::path_stats::WhyDepends;
+use crate::pattern::is_package;
+use crate::store_path::{PathId, StorePathGraph};
+
+/// Find a path in the graph by store path, `<hash>-<name>` base name, name,
+/// or package name without the version (`openssl` for `openssl-3.0.13`).
+pub fn resolve(graph: &StorePathGraph, query: &str) -> Result<PathId> {
+    let query = query.trim_end_matches('/');
+    if let Some(id) = graph.id_of(query) {
+        return Ok(id);
+    }
+    let ids = 0..graph.paths().len() as PathId;
+    let exact: Vec<PathId> = ids
+        .clone()
+        .filter(|&id| {
+            let p = graph.path(id);
+            graph.base_name(&p.path) == query || p.name == query
+        })
+        .collect();
+    let candidates = if exact.is_empty() {
+        ids.filter(|&id| is_package(&graph.path(id).name, query))
+            .collect()
+    } else {
+        exact
+    };
+    match candidates[..] {
+        [id] => Ok(id),
+        [] => bail!("'{query}' is not in the closure"),
+        _ => {
+            let paths: Vec<&str> = candidates
+                .iter()
+                .map(|&id| graph.path(id).path.as_str())
+                .collect();
+            bail!(
+                "'{query}' is ambiguous, use one of:\n  {}",
+                paths.join("\n  ")
+            )
+        }
+    }
+}
+
+/// Chains `write` prints without `--limit` or `--all`.
+pub const LIMIT: usize = 200;
+
+/// Print the number of reference chains from `from` to `to`, then the first
+/// `limit` of them, shortest first, one per line.
+pub fn write(
+    graph: &StorePathGraph,
+    from: PathId,
+    to: PathId,
+    limit: usize,
+    out: &mut impl Write,
+) -> Result<()> {
+    let name = |id: PathId| graph.path(id).short_name();
+    let chains = WhyDepends::between(graph, from, to);
+    let total = chains.total();
+    if total == 0 {
+        bail!("{} does not depend on {}", name(from), name(to));
+    }
+    writeln!(
+        out,
+        "{}{total} chain{} from {} to {}",
+        if chains.is_exact() { "" } else { "at least " },
+        if total == 1 { "" } else { "s" },
+        name(from),
+        name(to)
+    )?;
+    let shown = total.min(limit as u128);
+    for rank in 0..shown {
+        let Some(chain) = chains.chain(rank) else {
+            break;
+        };
+        let names: Vec<&str> = chain.iter().map(|&p| name(p)).collect();
+        writeln!(out, "{}", names.join(" → "))?;
+    }
+    if shown < total {
+        writeln!(
+            out,
+            "… {} more, pass --limit <N> or --all to see them",
+            total - shown
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store_path::testing::{TestGraph, p};
+
+    fn graph() -> StorePathGraph {
+        let mut graph = TestGraph::default();
+        for (name, refs) in [
+            ("app-1.0", &["python3-3.12.4", "openssl-3.0.13"][..]),
+            ("python3-3.12.4", &["libffi-3.4.6", "openssl-3.0.13"]),
+            ("libffi-3.4.6", &["openssl-3.0.13"]),
+            ("openssl-3.0.13", &[]),
+            ("openssl-3.0.13-dev", &["openssl-3.0.13"]),
+        ] {
+            graph.add_named(name, 0, refs);
+        }
+        graph.build(&[p("app-1.0")])
+    }
+
+    #[test]
+    fn resolves_names() {
+        let g = graph();
+        let name = |q: &str| g.path(resolve(&g, q).unwrap()).name.as_str();
+        assert_eq!(name("python3"), "python3-3.12.4");
+        assert_eq!(name("openssl-3.0.13"), "openssl-3.0.13");
+        let err = resolve(&g, "openssl").unwrap_err().to_string();
+        assert!(err.contains("ambiguous"), "{err}");
+        assert!(resolve(&g, "zlib").is_err());
+    }
+
+    #[test]
+    fn prints_chains() {
+        let g = graph();
+        let from = resolve(&g, "python3").unwrap();
+        let to = resolve(&g, "openssl-3.0.13").unwrap();
+        let mut out = Vec::new();
+        write(&g, from, to, LIMIT, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "2 chains from python3-3.12.4 to openssl-3.0.13\n\
+             python3-3.12.4 → openssl-3.0.13\n\
+             python3-3.12.4 → libffi-3.4.6 → openssl-3.0.13\n"
+        );
+
+        let mut out = Vec::new();
+        write(&g, from, to, 1, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "2 chains from python3-3.12.4 to openssl-3.0.13\n\
+             python3-3.12.4 → openssl-3.0.13\n\
+             … 1 more, pass --limit <N> or --all to see them\n"
+        );
+
+        let err = write(&g, to, from, LIMIT, &mut Vec::new()).unwrap_err();
+        assert!(err.to_string().contains("does not depend on"), "{err}");
+    }
+}
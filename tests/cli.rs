@@ -112,3 +112,138 @@ fn diff_requires_two_args() {
     assert!(!out.status.success());
     assert!(String::from_utf8_lossy(&out.stderr).contains("exactly two"));
 }
+
+/// `app-<version>` depending on each of `libs`, saved as a snapshot.
+fn write_snapshot(dir: &std::path::Path, version: &str, libs: &[&str]) -> Result<String> {
+    use nix_tree::store_path::testing::{TestGraph, p};
+
+    let app = format!("ntfx-app-{version}");
+    let mut graph = TestGraph::default();
+    graph.add_named(&app, 64 * 1024, libs);
+    for lib in libs {
+        graph.add_named(lib, 64 * 1024, &[]);
+    }
+    let graph = graph.build(&[p(&app)]);
+
+    let file = dir.join(format!("{version}.json"));
+    nix_tree::snapshot::save(&graph, &file)?;
+    Ok(file.to_string_lossy().into_owned())
+}
+
+#[test]
+fn dot_from_snapshot() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let snap = write_snapshot(dir.path(), "1.0", &["ntfx-liba-1.0"])?;
+    let dot = run(&["--dot", "--load-snapshot", &snap])?;
+    assert!(
+        dot.contains(r#""ntfx-app-1.0" -> "ntfx-liba-1.0""#),
+        "got: {dot}"
+    );
+    Ok(())
+}
+
+#[test]
+fn diff_from_snapshots() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let old = write_snapshot(dir.path(), "1.0", &["ntfx-liba-1.0", "ntfx-libb-1.0"])?;
+    let new = write_snapshot(dir.path(), "2.0", &["ntfx-liba-1.0"])?;
+    let diff = run(&["--diff", "--load-snapshot", &old, "--load-snapshot", &new])?;
+    assert!(diff.contains("1.0 → 2.0"), "got: {diff}");
+    assert!(diff.contains("ntfx-libb"), "got: {diff}");
+    assert!(!diff.contains("ntfx-liba"), "got: {diff}");
+    Ok(())
+}
+
+#[test]
+fn dot_from_closure_info() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    use nix_tree::store_path::testing::p;
+    let registration = format!(
+        "{app}\nsha256:0000\n1000\n\n2\n{app}\n{lib}\n{lib}\nsha256:1111\n500\n\n0\n",
+        app = p("ntfx-app-1.0"),
+        lib = p("ntfx-liba-1.0"),
+    );
+    std::fs::write(dir.path().join("registration"), registration)?;
+    let dot = run(&["--dot", "--closure-info", &dir.path().to_string_lossy()])?;
+    assert!(
+        dot.contains(r#""ntfx-app-1.0" -> "ntfx-liba-1.0""#),
+        "got: {dot}"
+    );
+    Ok(())
+}
+
+#[test]
+fn why_from_snapshot() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let snap = write_snapshot(dir.path(), "1.0", &["ntfx-liba-1.0", "ntfx-libb-1.0"])?;
+    let out = run(&["why", "--load-snapshot", &snap, "ntfx-app", "ntfx-libb"])?;
+    assert_eq!(
+        out,
+        "1 chain from ntfx-app-1.0 to ntfx-libb-1.0\nntfx-app-1.0 → ntfx-libb-1.0\n"
+    );
+    let out = run(&[
+        "why",
+        "--limit",
+        "0",
+        "--load-snapshot",
+        &snap,
+        "ntfx-app",
+        "ntfx-libb",
+    ])?;
+    assert_eq!(
+        out,
+        "1 chain from ntfx-app-1.0 to ntfx-libb-1.0\n\
+         … 1 more, pass --limit <N> or --all to see them\n"
+    );
+
+    let out = Command::new(BIN)
+        .args(["why", "--load-snapshot", &snap, "ntfx-liba", "ntfx-libb"])
+        .output()?;
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("does not depend on"));
+
+    // <to> is a name in the closure even when a file of that name is at hand.
+    std::fs::write(dir.path().join("ntfx-libb"), "")?;
+    let out = Command::new(BIN)
+        .current_dir(dir.path())
+        .args(["why", "--load-snapshot", &snap, "ntfx-app", "ntfx-libb"])
+        .output()?;
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    assert!(String::from_utf8_lossy(&out.stdout).starts_with("1 chain from"));
+    Ok(())
+}
+
+#[test]
+fn what_if_from_snapshot() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let snap = write_snapshot(dir.path(), "1.0", &["ntfx-liba-1.0", "ntfx-libb-1.0"])?;
+    let out = run(&["--what-if", "*-libb-*", "--load-snapshot", &snap])?;
+    assert!(
+        out.starts_with("Total: 192.0 KiB → 128.0 KiB (-64.0 KiB)\n"),
+        "got: {out}"
+    );
+    assert!(out.contains("Cut: 1 paths, 64.0 KiB\n"), "got: {out}");
+    Ok(())
+}
+
+#[test]
+fn duplicates_from_snapshot() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let snap = write_snapshot(dir.path(), "1.0", &["ntfx-liba-1.0", "ntfx-liba-2.0"])?;
+    let out = run(&["--duplicates", "--load-snapshot", &snap])?;
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(
+        lines[0], "1 duplicated packages, 64.0 KiB extra",
+        "got: {out}"
+    );
+    assert_eq!(
+        lines[2], "ntfx-liba: 2 copies, 64.0 KiB extra",
+        "got: {out}"
+    );
+    assert!(lines[3].contains("via ntfx-liba-1.0"), "got: {out}");
+    Ok(())
+}
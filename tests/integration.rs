@@ -18,7 +18,9 @@ async fn closure_size_matches_nix() -> Result<()> {
     );
     let root = String::from_utf8(out.stdout)?.trim().to_string();
 
-    let graph = nix_tree::nix::query_path_info(&[root.clone()], true, &Default::default()).await?;
+    let graph =
+        nix_tree::nix::query_path_info(std::slice::from_ref(&root), true, &Default::default())
+            .await?;
     let stats = nix_tree::path_stats::calculate_stats(&graph);
 
     let expected: u64 = String::from_utf8(
@@ -38,7 +40,10 @@ async fn closure_size_matches_nix() -> Result<()> {
     .unwrap()
     .parse()?;
 
-    assert_eq!(stats[&root].closure_size, expected);
+    assert_eq!(
+        stats.for_path(&graph, &root).unwrap().closure_size,
+        expected
+    );
     Ok(())
 }
 
@@ -1,23 +1,21 @@
+use std::collections::HashMap;
+
 use crossterm::event::{KeyCode, KeyEvent};
 use nix_tree::{
     path_stats,
-    store_path::{StorePath, StorePathGraph},
-    ui::App,
+    store_path::{
+        PathId, StorePath, StorePathGraph,
+        testing::{p, store_path},
+    },
+    ui::{
+        App,
+        app::{Modal, Report, StatusTab},
+    },
 };
 
 fn sp(name: &str, nar_size: u64, refs: &[&str]) -> StorePath {
-    let path = format!("/nix/store/{:a<32}-{name}", "");
-    StorePath {
-        hash: "a".repeat(32),
-        name: name.to_string(),
-        nar_size,
-        references: refs
-            .iter()
-            .map(|r| format!("/nix/store/{:a<32}-{r}", ""))
-            .collect(),
-        signatures: vec![],
-        path,
-    }
+    let refs: Vec<String> = refs.iter().map(|r| p(r)).collect();
+    store_path(&p(name), nar_size, &refs)
 }
 
 fn graph(paths: Vec<StorePath>, root: &str) -> StorePathGraph {
@@ -34,6 +32,17 @@ fn graph(paths: Vec<StorePath>, root: &str) -> StorePathGraph {
     g
 }
 
+/// Id by name, usable after the graph has moved into the `App`.
+fn ids(g: &StorePathGraph) -> impl Fn(&str) -> PathId + use<> {
+    let index: HashMap<String, PathId> = g
+        .paths()
+        .iter()
+        .zip(0..)
+        .map(|(p, id)| (p.name.clone(), id))
+        .collect();
+    move |name| index[name]
+}
+
 #[test]
 fn ranger_navigation() {
     let g = graph(
@@ -74,13 +83,554 @@ fn added_size_excludes_shared_deps() {
         ],
         "root",
     );
+    let p = ids(&g);
     let stats = path_stats::calculate_stats(&g);
     let mut app = App::new(g, stats);
 
     app.handle_key(KeyEvent::from(KeyCode::Right)).unwrap();
 
-    let p = |n: &str| format!("/nix/store/{:a<32}-{n}", "");
-    assert_eq!(app.added_size_of(&p("dep1")), 600); // dep1 + only1; shared survives via dep2
-    assert_eq!(app.added_size_of(&p("dep2")), 450); // dep2 + only2
-    assert_eq!(app.added_size_of(&p("shared")), 200); // leaf: just itself
+    assert_eq!(app.added_size_of(p("dep1")), 600); // dep1 + only1; shared survives via dep2
+    assert_eq!(app.added_size_of(p("dep2")), 450); // dep2 + only2
+    assert_eq!(app.added_size_of(p("shared")), 200); // leaf: just itself
+}
+
+#[test]
+fn jump_to_dominator() {
+    let g = graph(
+        vec![
+            sp("root", 1000, &["dep1", "dep2"]),
+            sp("dep1", 500, &["hub"]),
+            sp("dep2", 300, &["hub"]),
+            sp("hub", 200, &["leaf"]),
+            sp("leaf", 100, &[]),
+        ],
+        "root",
+    );
+    let p = ids(&g);
+    let stats = path_stats::calculate_stats(&g);
+    let mut app = App::new(g, stats);
+    assert_eq!(app.stats[p("hub")].retained_size, 300);
+
+    // root → dep1 → hub → leaf
+    for _ in 0..3 {
+        app.handle_key(KeyEvent::from(KeyCode::Right)).unwrap();
+    }
+    assert_eq!(app.current_path, Some(p("leaf")));
+
+    app.handle_key(KeyEvent::from(KeyCode::Char('d'))).unwrap();
+    assert_eq!(app.current_path, Some(p("hub")));
+    // hub is shared by dep1 and dep2, so only root dominates it.
+    app.handle_key(KeyEvent::from(KeyCode::Char('d'))).unwrap();
+    assert_eq!(app.current_path, Some(p("root")));
+    app.handle_key(KeyEvent::from(KeyCode::Char('d'))).unwrap();
+    assert_eq!(app.current_path, Some(p("root")));
+    assert!(app.status_message.is_some());
+}
+
+#[test]
+fn list_cycles() {
+    let g = graph(
+        vec![
+            sp("root", 1000, &["out", "lib"]),
+            sp("out", 500, &["dev"]),
+            sp("dev", 300, &["out"]),
+            sp("lib", 100, &["lib"]),
+        ],
+        "root",
+    );
+    let p = ids(&g);
+    let stats = path_stats::calculate_stats(&g);
+    let mut app = App::new(g, stats);
+
+    app.handle_key(KeyEvent::from(KeyCode::Char('c'))).unwrap();
+    let mut items = app.current_items.clone();
+    items.sort();
+    assert_eq!(items, [p("out"), p("dev")]);
+    assert_eq!(app.stats[p("out")].closure_size, 800);
+}
+
+#[test]
+fn why_depends_pages() {
+    // 2^9 chains from root to t, more than one page of the modal.
+    let mut paths = vec![sp("root", 1, &["a0", "b0"]), sp("t", 1, &[])];
+    for i in 0..9 {
+        let next = if i == 8 {
+            vec!["t".to_string()]
+        } else {
+            vec![format!("a{}", i + 1), format!("b{}", i + 1)]
+        };
+        let next: Vec<&str> = next.iter().map(String::as_str).collect();
+        paths.push(sp(&format!("a{i}"), 1, &next));
+        paths.push(sp(&format!("b{i}"), 1, &next));
+    }
+    let g = graph(paths, "root");
+    let p = ids(&g);
+    let stats = path_stats::calculate_stats(&g);
+    let mut app = App::new(g, stats);
+    app.current_path = Some(p("t"));
+
+    app.handle_key(KeyEvent::from(KeyCode::Char('w'))).unwrap();
+    let rank = |app: &App| match &app.modal {
+        Some(Modal::WhyDepends {
+            chains,
+            page_start,
+            selected,
+            paths,
+            ..
+        }) => (
+            chains.total(),
+            *page_start + *selected as u128,
+            paths[*selected].clone(),
+        ),
+        _ => panic!("no why-depends modal"),
+    };
+    assert_eq!(rank(&app).0, 512);
+
+    app.handle_key(KeyEvent::from(KeyCode::Char('G'))).unwrap();
+    let (_, last, chain) = rank(&app);
+    assert_eq!(last, 511);
+    assert_eq!(chain.len(), 11);
+    assert!(
+        chain[1..10]
+            .iter()
+            .all(|&c| app.graph.path(c).name.starts_with('b'))
+    );
+
+    app.handle_key(KeyEvent::from(KeyCode::Char('g'))).unwrap();
+    for _ in 0..201 {
+        app.handle_key(KeyEvent::from(KeyCode::Char('j'))).unwrap();
+    }
+    assert_eq!(rank(&app).1, 201);
+    for _ in 0..2 {
+        app.handle_key(KeyEvent::from(KeyCode::Char('k'))).unwrap();
+    }
+    assert_eq!(rank(&app).1, 199);
+}
+
+#[test]
+fn why_depends_from_marked_source() {
+    let g = graph(
+        vec![
+            sp("root", 1000, &["python", "openssl"]),
+            sp("python", 500, &["libffi", "openssl"]),
+            sp("libffi", 300, &["openssl"]),
+            sp("openssl", 100, &[]),
+        ],
+        "root",
+    );
+    let p = ids(&g);
+    let stats = path_stats::calculate_stats(&g);
+    let mut app = App::new(g, stats);
+
+    app.current_path = Some(p("python"));
+    app.handle_key(KeyEvent::from(KeyCode::Char('m'))).unwrap();
+    assert_eq!(app.marked, Some(p("python")));
+
+    app.current_path = Some(p("openssl"));
+    app.handle_key(KeyEvent::from(KeyCode::Char('w'))).unwrap();
+    let Some(Modal::WhyDepends { chains, paths, .. }) = &app.modal else {
+        panic!("no why-depends modal");
+    };
+    assert_eq!(chains.total(), 2);
+    assert_eq!(paths[0], [p("python"), p("openssl")]);
+
+    // Enter walks there from the roots.
+    app.handle_key(KeyEvent::from(KeyCode::Enter)).unwrap();
+    assert_eq!(app.current_path, Some(p("openssl")));
+    let walked: Vec<PathId> = app
+        .navigation_history
+        .iter()
+        .map(|(items, selected)| items[selected.unwrap()])
+        .collect();
+    assert_eq!(walked, [p("root"), p("python")]);
+
+    // The other direction has no chains.
+    app.marked = Some(p("openssl"));
+    app.current_path = Some(p("python"));
+    app.handle_key(KeyEvent::from(KeyCode::Char('w'))).unwrap();
+    assert!(app.modal.is_none());
+    assert!(app.status_message.unwrap().contains("does not depend on"));
+}
+
+#[test]
+fn what_if_cuts() {
+    let g = graph(
+        vec![
+            sp("root", 1000, &["git-2.44.0", "git-2.44.0-doc"]),
+            sp("git-2.44.0", 500, &["perl-5.38.2"]),
+            sp("git-2.44.0-doc", 200, &[]),
+            sp("perl-5.38.2", 4000, &["perl-xml"]),
+            sp("perl-xml", 50, &[]),
+        ],
+        "root",
+    );
+    let p = ids(&g);
+    let stats = path_stats::calculate_stats(&g);
+    let mut app = App::new(g, stats);
+    let type_keys = |app: &mut App, s: &str| {
+        for c in s.chars() {
+            app.handle_key(KeyEvent::from(KeyCode::Char(c))).unwrap();
+        }
+    };
+
+    app.handle_key(KeyEvent::from(KeyCode::Char('W'))).unwrap();
+    assert!(app.modal.is_none());
+
+    app.current_path = Some(p("git-2.44.0-doc"));
+    app.handle_key(KeyEvent::from(KeyCode::Char('x'))).unwrap();
+    app.handle_key(KeyEvent::from(KeyCode::Char('X'))).unwrap();
+    type_keys(&mut app, "perl");
+    app.handle_key(KeyEvent::from(KeyCode::Enter)).unwrap();
+    assert_eq!(app.cut, [p("git-2.44.0-doc"), p("perl-5.38.2")]);
+
+    let Some(Modal::Report { lines, .. }) = &app.modal else {
+        panic!("no what-if modal");
+    };
+    assert!(lines[0].starts_with("Total:"), "{lines:?}");
+    assert!(lines.iter().any(|l| l.ends_with("  perl-xml")), "{lines:?}");
+
+    app.handle_key(KeyEvent::from(KeyCode::Char('u'))).unwrap();
+    assert!(app.cut.is_empty());
+    assert!(app.modal.is_none());
+}
+
+#[test]
+fn reverse_closure_toggle() {
+    let g = graph(
+        vec![
+            sp("root", 1000, &["app", "tool"]),
+            sp("app", 500, &["lib"]),
+            sp("tool", 300, &["lib"]),
+            sp("lib", 200, &["zlib"]),
+            sp("zlib", 100, &[]),
+        ],
+        "root",
+    );
+    let p = ids(&g);
+    let stats = path_stats::calculate_stats(&g);
+    let mut app = App::new(g, stats);
+
+    // root → app → lib → zlib
+    for _ in 0..3 {
+        app.handle_key(KeyEvent::from(KeyCode::Right)).unwrap();
+    }
+    assert_eq!(app.current_path, Some(p("zlib")));
+    assert_eq!(app.previous_items, [p("lib")]);
+
+    app.handle_key(KeyEvent::from(KeyCode::Char('R'))).unwrap();
+    let mut items = app.previous_items.clone();
+    items.sort();
+    assert_eq!(items, [p("root"), p("app"), p("tool"), p("lib")]);
+    let rc = app.reverse_closure().unwrap();
+    assert_eq!(rc.size, 2000);
+    assert_eq!(rc.roots, [p("root")]);
+
+    // Moving away and back reuses the walk.
+    app.handle_key(KeyEvent::from(KeyCode::Left)).unwrap();
+    assert_eq!(app.current_path, Some(p("lib")));
+    assert_eq!(app.reverse_closure().unwrap().size, 1800);
+    app.handle_key(KeyEvent::from(KeyCode::Right)).unwrap();
+    assert_eq!(app.reverse_closures.len(), 2);
+    assert_eq!(app.reverse_closure().unwrap().size, 2000);
+
+    app.handle_key(KeyEvent::from(KeyCode::Char('R'))).unwrap();
+    assert_eq!(app.previous_items, [p("lib")]);
+    assert!(app.reverse_closure().is_none());
+}
+
+#[test]
+fn precise_why_depends() {
+    let g = graph(
+        vec![
+            sp("root", 1000, &["python"]),
+            sp("python", 500, &["openssl"]),
+            sp("openssl", 100, &[]),
+        ],
+        "root",
+    );
+    let p = ids(&g);
+    let stats = path_stats::calculate_stats(&g);
+    let mut app = App::new(g, stats);
+
+    app.current_path = Some(p("openssl"));
+    app.handle_key(KeyEvent::from(KeyCode::Char('w'))).unwrap();
+    app.handle_key(KeyEvent::from(KeyCode::Char('p'))).unwrap();
+    assert!(app.scanning_chain);
+    assert!(matches!(app.modal, Some(Modal::WhyDepends { .. })));
+    app.wait_background();
+    assert!(!app.scanning_chain);
+    let Some(Modal::Report { kind, lines, .. }) = &app.modal else {
+        panic!("no precise report");
+    };
+    assert_eq!(*kind, Report::Precise);
+    let edges: Vec<&str> = lines
+        .iter()
+        .filter(|l| l.contains('→'))
+        .map(String::as_str)
+        .collect();
+    assert_eq!(edges, ["root → python", "python → openssl"]);
+
+    // Closing the report goes back to the chain it was opened from.
+    app.handle_key(KeyEvent::from(KeyCode::Char('q'))).unwrap();
+    let Some(Modal::WhyDepends {
+        paths, selected, ..
+    }) = &app.modal
+    else {
+        panic!("no why-depends modal");
+    };
+    assert_eq!(paths[*selected], [p("root"), p("python"), p("openssl")]);
+
+    // The same chain again is not scanned again.
+    app.handle_key(KeyEvent::from(KeyCode::Char('p'))).unwrap();
+    assert!(!app.scanning_chain);
+    assert!(matches!(app.modal, Some(Modal::Report { .. })));
+    app.handle_key(KeyEvent::from(KeyCode::Char('q'))).unwrap();
+    app.handle_key(KeyEvent::from(KeyCode::Char('q'))).unwrap();
+    assert!(app.modal.is_none());
+}
+
+#[test]
+fn linking_tab_follows_selection() {
+    let g = graph(
+        vec![sp("root", 1000, &["lib"]), sp("lib", 100, &[])],
+        "root",
+    );
+    let p = ids(&g);
+    let stats = path_stats::calculate_stats(&g);
+    let mut app = App::new(g, stats);
+    assert!(app.linking.is_empty());
+
+    app.handle_key(KeyEvent::from(KeyCode::Tab)).unwrap();
+    assert_eq!(app.status_tab, StatusTab::Linking);
+    assert_eq!(app.linking_pending, Some(p("root")));
+    app.wait_background();
+    // The test paths are not on disk.
+    let Some(Err(e)) = app.linking.get(&p("root")) else {
+        panic!("no linking error");
+    };
+    assert!(e.starts_with("Cannot read /nix/store/"), "{e}");
+
+    app.handle_key(KeyEvent::from(KeyCode::Char('l'))).unwrap();
+    app.wait_background();
+    let Some(Err(e)) = app.linking.get(&p("lib")) else {
+        panic!("no linking error");
+    };
+    assert!(e.contains("-lib:"), "{e}");
+
+    // Coming back to a path reuses what was read.
+    app.handle_key(KeyEvent::from(KeyCode::Char('h'))).unwrap();
+    assert_eq!(app.linking_pending, None);
+
+    app.handle_key(KeyEvent::from(KeyCode::Tab)).unwrap();
+    assert_eq!(app.status_tab, StatusTab::Info);
+    app.handle_key(KeyEvent::from(KeyCode::Char('l'))).unwrap();
+    assert_eq!(app.linking_pending, None);
+}
+
+#[test]
+fn browse_files() {
+    let store = tempfile::tempdir().unwrap();
+    let pkg = store.path().join(format!("{:a<32}-pkg", ""));
+    std::fs::create_dir_all(pkg.join("share/doc")).unwrap();
+    std::fs::write(pkg.join("share/doc/big"), vec![1u8; 64 * 1024]).unwrap();
+    std::fs::write(pkg.join("README"), "small").unwrap();
+    let mut root = sp("pkg", 1000, &[]);
+    root.path = pkg.to_str().unwrap().to_string();
+    let g = graph(vec![root], "pkg");
+    let stats = path_stats::calculate_stats(&g);
+    let mut app = App::new(g, stats);
+
+    let key = |app: &mut App, code| app.handle_key(KeyEvent::from(code)).unwrap();
+    let selected = |app: &App| {
+        let view = app.files.as_ref().unwrap();
+        let entries = view.tree.entries(&view.dir);
+        (
+            view.dir.to_str().unwrap().to_string(),
+            entries[view.state.selected().unwrap()].name.clone(),
+        )
+    };
+    key(&mut app, KeyCode::Char('f'));
+    assert!(app.scanning_tree.is_some());
+    assert!(app.files.is_none());
+    app.wait_background();
+    assert_eq!(selected(&app), ("".to_string(), "share".to_string()));
+    key(&mut app, KeyCode::Char('l'));
+    key(&mut app, KeyCode::Char('l'));
+    assert_eq!(selected(&app), ("share/doc".to_string(), "big".to_string()));
+    // A file has nothing to descend into.
+    key(&mut app, KeyCode::Char('l'));
+    assert_eq!(selected(&app).0, "share/doc");
+    key(&mut app, KeyCode::Char('h'));
+    key(&mut app, KeyCode::Char('h'));
+    assert_eq!(selected(&app), ("".to_string(), "share".to_string()));
+    key(&mut app, KeyCode::Char('j'));
+    assert_eq!(selected(&app).1, "README");
+    key(&mut app, KeyCode::Char('h'));
+    assert!(app.files.is_none());
+
+    // Going back in reuses what was read.
+    key(&mut app, KeyCode::Char('f'));
+    assert!(app.scanning_tree.is_none());
+    assert_eq!(selected(&app), ("".to_string(), "share".to_string()));
+    key(&mut app, KeyCode::Char('q'));
+
+    key(&mut app, KeyCode::Char('F'));
+    assert!(app.finding_largest_files);
+    assert!(app.modal.is_none());
+    app.wait_background();
+    let Some(Modal::Report { kind, lines, .. }) = &app.modal else {
+        panic!("no largest files report");
+    };
+    assert_eq!(*kind, Report::LargestFiles);
+    assert!(lines[2].ends_with("  pkg/share/doc/big"), "{lines:#?}");
+
+    // Shown again without another scan.
+    key(&mut app, KeyCode::Char('q'));
+    key(&mut app, KeyCode::Char('F'));
+    assert!(!app.finding_largest_files);
+    assert!(matches!(app.modal, Some(Modal::Report { .. })));
+}
+
+#[test]
+fn find_files_by_name() {
+    let store = tempfile::tempdir().unwrap();
+    let mut paths = vec![
+        sp("system", 10, &["openssh", "python3"]),
+        sp("openssh", 100, &[]),
+        sp("python3", 1000, &[]),
+    ];
+    for (p, files) in paths
+        .iter_mut()
+        .zip([&[][..], &["bin/ssh"], &["bin/python3"]])
+    {
+        let dir = store.path().join(&p.name);
+        std::fs::create_dir_all(&dir).unwrap();
+        for file in files {
+            std::fs::create_dir_all(dir.join(file).parent().unwrap()).unwrap();
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+        p.path = dir.to_str().unwrap().to_string();
+        p.references.clear();
+    }
+    let g = graph(paths, "system");
+    let p = ids(&g);
+    let stats = path_stats::calculate_stats(&g);
+    let mut app = App::new(g, stats);
+
+    let find = |app: &mut App, query: &str| {
+        app.handle_key(KeyEvent::from(KeyCode::Char('L'))).unwrap();
+        assert!(app.finding_files);
+        for c in query.chars() {
+            app.handle_key(KeyEvent::from(KeyCode::Char(c))).unwrap();
+        }
+        app.handle_key(KeyEvent::from(KeyCode::Enter)).unwrap();
+        assert!(!app.finding_files);
+        app.wait_background();
+    };
+    find(&mut app, "ssh");
+    assert!(app.pending_file_search.is_none());
+    find(&mut app, "bin/*");
+    let found = app.current_items.clone();
+    let message = app.status_message.clone();
+    find(&mut app, "re:^lib/");
+    // Largest closure first.
+    assert_eq!(found, [p("python3"), p("openssh")]);
+    assert_eq!(message, None);
+    assert_eq!(
+        app.status_message.as_deref(),
+        Some("No file matches re:^lib/")
+    );
+    assert_eq!(
+        app.found_files[&p("openssh")],
+        [std::path::Path::new("bin/ssh")]
+    );
+
+    // Anything else in the pane is not a file search result.
+    app.handle_key(KeyEvent::from(KeyCode::Char('/'))).unwrap();
+    app.handle_key(KeyEvent::from(KeyCode::Char('s'))).unwrap();
+    app.handle_key(KeyEvent::from(KeyCode::Enter)).unwrap();
+    assert_eq!(app.current_items[0], p("system"));
+    assert!(app.found_files.is_empty());
+}
+
+#[test]
+fn split_advice() {
+    let store = tempfile::tempdir().unwrap();
+    let dir = |c: char, name: &str| {
+        store
+            .path()
+            .join(format!("{}-{name}", c.to_string().repeat(32)))
+    };
+    let mut paths = Vec::new();
+    for (c, name, size, refs) in [
+        (
+            'l',
+            "libfoo",
+            1,
+            &[('g', "glibc"), ('p', "python3"), ('x', "groff")][..],
+        ),
+        ('g', "glibc", 100, &[]),
+        ('p', "python3", 1000, &[('g', "glibc")]),
+        ('x', "groff", 50, &[]),
+    ] {
+        let mut p = sp(name, size, &[]);
+        p.hash = c.to_string().repeat(32);
+        p.path = dir(c, name).to_str().unwrap().to_string();
+        p.references = refs
+            .iter()
+            .map(|&(c, r)| dir(c, r).to_str().unwrap().to_string())
+            .collect();
+        paths.push(p);
+    }
+    let lib = dir('l', "libfoo");
+    for (file, reference) in [
+        ("lib/libfoo.so.1", dir('g', "glibc").join("lib/libc.so.6")),
+        (
+            "lib/pkgconfig/foo.pc",
+            dir('p', "python3").join("bin/python3"),
+        ),
+        ("share/man/man1/foo.1", dir('x', "groff").join("bin/groff")),
+    ] {
+        std::fs::create_dir_all(lib.join(file).parent().unwrap()).unwrap();
+        std::fs::write(lib.join(file), reference.to_str().unwrap()).unwrap();
+    }
+    let mut g = graph(paths, "libfoo");
+    g.detect_store_dir();
+    let p = ids(&g);
+    let stats = path_stats::calculate_stats(&g);
+    let mut app = App::new(g, stats);
+
+    app.handle_key(KeyEvent::from(KeyCode::Char('S'))).unwrap();
+    assert_eq!(app.split_pending, Some(p("libfoo")));
+    assert!(app.modal.is_none());
+    app.wait_background();
+    let Some(Modal::Report { kind, lines, .. }) = &app.modal else {
+        panic!("no split report: {:?}", app.status_message);
+    };
+    assert_eq!(*kind, Report::Split);
+    assert_eq!(
+        lines[0],
+        "libfoo: 3 references, 2 only held by documentation or development files"
+    );
+    assert_eq!(
+        lines[2],
+        "     -1000 B  python3 → dev: lib/pkgconfig/foo.pc"
+    );
+    assert_eq!(lines[3], "       -50 B  groff → man: share/man/man1/foo.1");
+
+    // Shown again from what was scanned.
+    app.handle_key(KeyEvent::from(KeyCode::Char('q'))).unwrap();
+    std::fs::remove_dir_all(&lib).unwrap();
+    app.handle_key(KeyEvent::from(KeyCode::Char('S'))).unwrap();
+    assert_eq!(app.split_pending, None);
+    assert!(matches!(app.modal, Some(Modal::Report { .. })));
+
+    // Paths that are not on disk say so instead.
+    app.handle_key(KeyEvent::from(KeyCode::Char('q'))).unwrap();
+    app.current_path = Some(p("glibc"));
+    app.handle_key(KeyEvent::from(KeyCode::Char('S'))).unwrap();
+    app.wait_background();
+    assert!(app.modal.is_none());
+    let message = app.status_message.as_deref().unwrap();
+    assert!(message.starts_with("Cannot read "), "{message}");
 }